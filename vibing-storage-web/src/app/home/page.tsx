@@ -8,6 +8,15 @@ import PageMonitor from '@/components/PageMonitor'
 
 const PAGE_SIZE = 10;
 
+// the page object GET /tracks answers with
+interface TrackPage {
+  items: Track[];
+  total_items: number;
+  total_page: number;
+  page_num: number;
+  page_size: number;
+}
+
 interface TrackListProps {
   tracks: Track[];
   currentVolume: number;
@@ -55,6 +64,13 @@ const HomeBody = () => {
   const [tracks, setTracks] = useState<Track[]>([]);
   const [currentVolume, setCurentVolume] = useState(50);
   const [currentPage, setCurrentPage] = useState(1);
+  const [totalPage, setTotalPage] = useState(0);
+
+  const showPage = (page: TrackPage) => {
+    setTracks(page.items);
+    setCurrentPage(page.page_num);
+    setTotalPage(page.total_page);
+  }
 
   useEffect(() => {
     const fetchInitialTracks = async () => {
@@ -62,7 +78,11 @@ const HomeBody = () => {
         const BACKEND_URL = process.env.NEXT_PUBLIC_BACKEND_URL;
         const response = await fetch(`${BACKEND_URL}/tracks?page=1&size=${PAGE_SIZE}`);
         const data = await response.json();
-        setTracks(data);
+        if (!response.ok) {
+          console.error(`Failed to fetch initial tracks (${data.code}): ${data.detail}`);
+          return;
+        }
+        showPage(data);
       } catch (error) {
         console.error("Failed to fetch initial tracks:", error);
       }
@@ -75,7 +95,8 @@ const HomeBody = () => {
     console.log('Search query:', query)
     const searchParams = new URLSearchParams();
     if (query.pattern) {
-      searchParams.append('pattern', query.pattern);
+      // the search box takes the query syntax, plain words still match titles and authors
+      searchParams.append('q', query.pattern);
     }
     if (query.order_by) {
       searchParams.append('order_by', query.order_by);
@@ -83,12 +104,18 @@ const HomeBody = () => {
     if (query.vibes) {
       query.vibes.forEach(vibe => searchParams.append('vibes', vibe));
     }
+    searchParams.append('page', String(query.page));
+    searchParams.append('size', String(query.size));
 
     try {
       const BACKEND_URL = process.env.NEXT_PUBLIC_BACKEND_URL;
       const response = await fetch(`${BACKEND_URL}/tracks?${searchParams.toString()}`);
       const data = await response.json();
-      setTracks(data);
+      if (!response.ok) {
+        console.error(`Failed to fetch search results (${data.code}): ${data.detail}`);
+        return;
+      }
+      showPage(data);
     } catch (error) {
       console.error("Failed to fetch search results:", error);
     }
@@ -106,7 +133,7 @@ const HomeBody = () => {
           pageSize={PAGE_SIZE}
           onSearch={handleSearch} />
         <TrackList tracks={tracks} currentVolume={currentVolume}/>
-        <PageMonitor currentPage={0} totalPage={10} />
+        <PageMonitor currentPage={currentPage} totalPage={totalPage} />
         <div className="fixed bottom-25 right-4 z-50">
           <VolumeSlider volume={currentVolume} onVolumeChange={handleVolumeChange} />
         </div>
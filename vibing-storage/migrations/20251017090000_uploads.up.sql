@@ -0,0 +1,17 @@
+-- Add up migration script here
+
+CREATE TABLE uploads (
+    upload_id UUID PRIMARY KEY,
+    file_name TEXT NOT NULL,
+    upload_length BIGINT NOT NULL,
+    upload_offset BIGINT NOT NULL DEFAULT 0,
+    title TEXT NULL,
+    author TEXT NULL,
+    genre TEXT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX uploads_expires_at ON uploads(expires_at);
+
+GRANT SELECT, INSERT, UPDATE, DELETE ON uploads TO viber;
@@ -0,0 +1,5 @@
+-- Add down migration script here
+
+ALTER TABLE tracks
+    DROP COLUMN codec,
+    DROP COLUMN container;
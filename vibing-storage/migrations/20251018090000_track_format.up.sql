@@ -0,0 +1,10 @@
+-- Add up migration script here
+
+ALTER TABLE tracks
+    ADD COLUMN codec TEXT NULL,
+    ADD COLUMN container TEXT NULL;
+
+-- everything imported so far was mp3
+UPDATE tracks
+SET codec = 'mp3', container = 'mpeg'
+WHERE path ILIKE '%.mp3';
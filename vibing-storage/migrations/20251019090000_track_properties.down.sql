@@ -0,0 +1,8 @@
+-- Add down migration script here
+
+ALTER TABLE tracks
+    DROP COLUMN bitrate,
+    DROP COLUMN sample_rate,
+    DROP COLUMN channels,
+    DROP COLUMN bit_depth,
+    DROP COLUMN file_size;
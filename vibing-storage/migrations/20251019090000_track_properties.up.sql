@@ -0,0 +1,10 @@
+-- Add up migration script here
+
+ALTER TABLE tracks
+    ADD COLUMN bitrate INT NULL,
+    ADD COLUMN sample_rate INT NULL,
+    ADD COLUMN channels SMALLINT NULL,
+    ADD COLUMN bit_depth SMALLINT NULL,
+    ADD COLUMN file_size BIGINT NULL;
+
+CREATE INDEX tracks_bitrate ON tracks(bitrate);
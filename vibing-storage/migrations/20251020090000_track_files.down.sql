@@ -0,0 +1,6 @@
+-- Add down migration script here
+
+ALTER TABLE tracks
+    DROP COLUMN modified_at,
+    DROP COLUMN missing,
+    ALTER COLUMN path TYPE VARCHAR(255);
@@ -0,0 +1,6 @@
+-- Add up migration script here
+
+ALTER TABLE tracks
+    ALTER COLUMN path TYPE TEXT,
+    ADD COLUMN modified_at TIMESTAMPTZ NULL,
+    ADD COLUMN missing BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,6 @@
+-- Add down migration script here
+
+DROP INDEX tracks_added_at_idx;
+
+ALTER TABLE tracks
+    DROP COLUMN added_at;
@@ -0,0 +1,6 @@
+-- Add up migration script here
+
+ALTER TABLE tracks
+    ADD COLUMN added_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+CREATE INDEX tracks_added_at_idx ON tracks (added_at);
@@ -0,0 +1,18 @@
+-- Add down migration script here
+
+DROP INDEX tracks_author_trgm_idx;
+DROP INDEX tracks_title_trgm_idx;
+DROP INDEX tracks_search_vector_idx;
+
+DROP TRIGGER vibes_search_vector ON vibes;
+DROP FUNCTION vibes_refresh_search_vector();
+DROP TRIGGER tracks_with_vibes_search_vector ON tracks_with_vibes;
+DROP FUNCTION tracks_with_vibes_refresh_search_vector();
+DROP TRIGGER tracks_search_vector ON tracks;
+DROP FUNCTION tracks_refresh_search_vector();
+
+ALTER TABLE tracks DROP COLUMN search_vector;
+
+DROP FUNCTION track_search_vector(INT, TEXT, TEXT, TEXT);
+
+-- pg_trgm is left installed, other objects may rely on it
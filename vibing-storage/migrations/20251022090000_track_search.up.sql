@@ -0,0 +1,75 @@
+-- Add up migration script here
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- 'simple' keeps words as they are, titles come in every language
+CREATE FUNCTION track_search_vector(id INT, title TEXT, author TEXT, genre TEXT)
+RETURNS tsvector
+LANGUAGE SQL STABLE
+AS $$
+    SELECT
+        setweight(to_tsvector('simple', COALESCE(title, '')), 'A') ||
+        setweight(to_tsvector('simple', COALESCE(author, '')), 'B') ||
+        setweight(to_tsvector('simple', COALESCE(genre, '')), 'C') ||
+        setweight(to_tsvector('simple', COALESCE((
+            SELECT string_agg(v.name, ' ')
+            FROM tracks_with_vibes twv
+            JOIN vibes v ON v.vibe_id = twv.vibe
+            WHERE twv.track = id
+        ), '')), 'D')
+$$;
+
+ALTER TABLE tracks ADD COLUMN search_vector TSVECTOR NOT NULL DEFAULT ''::TSVECTOR;
+
+UPDATE tracks SET search_vector = track_search_vector(track_id, title, author, genre);
+
+CREATE FUNCTION tracks_refresh_search_vector() RETURNS TRIGGER
+LANGUAGE plpgsql
+AS $$
+BEGIN
+    NEW.search_vector := track_search_vector(NEW.track_id, NEW.title, NEW.author, NEW.genre);
+    RETURN NEW;
+END;
+$$;
+
+CREATE TRIGGER tracks_search_vector
+    BEFORE INSERT OR UPDATE OF title, author, genre ON tracks
+    FOR EACH ROW EXECUTE FUNCTION tracks_refresh_search_vector();
+
+CREATE FUNCTION tracks_with_vibes_refresh_search_vector() RETURNS TRIGGER
+LANGUAGE plpgsql
+AS $$
+DECLARE
+    changed INT := CASE WHEN TG_OP = 'DELETE' THEN OLD.track ELSE NEW.track END;
+BEGIN
+    UPDATE tracks
+    SET search_vector = track_search_vector(track_id, title, author, genre)
+    WHERE track_id = changed;
+    RETURN NULL;
+END;
+$$;
+
+CREATE TRIGGER tracks_with_vibes_search_vector
+    AFTER INSERT OR DELETE ON tracks_with_vibes
+    FOR EACH ROW EXECUTE FUNCTION tracks_with_vibes_refresh_search_vector();
+
+CREATE FUNCTION vibes_refresh_search_vector() RETURNS TRIGGER
+LANGUAGE plpgsql
+AS $$
+BEGIN
+    UPDATE tracks
+    SET search_vector = track_search_vector(track_id, title, author, genre)
+    WHERE track_id IN (SELECT track FROM tracks_with_vibes WHERE vibe = NEW.vibe_id);
+    RETURN NULL;
+END;
+$$;
+
+CREATE TRIGGER vibes_search_vector
+    AFTER UPDATE OF name ON vibes
+    FOR EACH ROW EXECUTE FUNCTION vibes_refresh_search_vector();
+
+CREATE INDEX tracks_search_vector_idx ON tracks USING GIN (search_vector);
+
+-- serve typo tolerant matches as well as the substring matches of ILIKE
+CREATE INDEX tracks_title_trgm_idx ON tracks USING GIN (title gin_trgm_ops);
+CREATE INDEX tracks_author_trgm_idx ON tracks USING GIN (author gin_trgm_ops);
@@ -0,0 +1,29 @@
+-- Add down migration script here
+
+DROP INDEX tracks_title_trgm_idx;
+DROP INDEX tracks_author_trgm_idx;
+CREATE INDEX tracks_title_trgm_idx ON tracks USING GIN (title gin_trgm_ops);
+CREATE INDEX tracks_author_trgm_idx ON tracks USING GIN (author gin_trgm_ops);
+
+CREATE OR REPLACE FUNCTION track_search_vector(id INT, title TEXT, author TEXT, genre TEXT)
+RETURNS tsvector
+LANGUAGE SQL STABLE
+AS $$
+    SELECT
+        setweight(to_tsvector('simple', COALESCE(title, '')), 'A') ||
+        setweight(to_tsvector('simple', COALESCE(author, '')), 'B') ||
+        setweight(to_tsvector('simple', COALESCE(genre, '')), 'C') ||
+        setweight(to_tsvector('simple', COALESCE((
+            SELECT string_agg(v.name, ' ')
+            FROM tracks_with_vibes twv
+            JOIN vibes v ON v.vibe_id = twv.vibe
+            WHERE twv.track = id
+        ), '')), 'D')
+$$;
+
+UPDATE tracks SET search_vector = track_search_vector(track_id, title, author, genre);
+
+DROP TEXT SEARCH CONFIGURATION simple_unaccent;
+DROP FUNCTION fold_accents(TEXT);
+
+-- unaccent is left installed, other objects may rely on it
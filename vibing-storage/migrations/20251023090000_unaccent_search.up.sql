@@ -0,0 +1,45 @@
+-- Add up migration script here
+
+CREATE EXTENSION IF NOT EXISTS unaccent;
+
+-- unaccent() is only STABLE since its dictionary could change, pinning the dictionary
+-- makes it usable in indexes
+CREATE FUNCTION fold_accents(value TEXT) RETURNS TEXT
+LANGUAGE SQL IMMUTABLE PARALLEL SAFE STRICT
+AS $$
+    SELECT public.unaccent('public.unaccent'::REGDICTIONARY, value)
+$$;
+
+-- "Sơn Tùng" and "son tung" give the same lexemes
+CREATE TEXT SEARCH CONFIGURATION simple_unaccent (COPY = simple);
+ALTER TEXT SEARCH CONFIGURATION simple_unaccent
+    ALTER MAPPING FOR asciihword, asciiword, hword, hword_asciipart, hword_part, word
+    WITH unaccent, simple;
+
+-- tags written before ingest normalized them may be decomposed
+UPDATE tracks SET title = NORMALIZE(title, NFC) WHERE title IS NOT NFC NORMALIZED;
+UPDATE tracks SET author = NORMALIZE(author, NFC) WHERE author IS NOT NFC NORMALIZED;
+UPDATE tracks SET genre = NORMALIZE(genre, NFC) WHERE genre IS NOT NFC NORMALIZED;
+
+CREATE OR REPLACE FUNCTION track_search_vector(id INT, title TEXT, author TEXT, genre TEXT)
+RETURNS tsvector
+LANGUAGE SQL STABLE
+AS $$
+    SELECT
+        setweight(to_tsvector('simple_unaccent', COALESCE(title, '')), 'A') ||
+        setweight(to_tsvector('simple_unaccent', COALESCE(author, '')), 'B') ||
+        setweight(to_tsvector('simple_unaccent', COALESCE(genre, '')), 'C') ||
+        setweight(to_tsvector('simple_unaccent', COALESCE((
+            SELECT string_agg(v.name, ' ')
+            FROM tracks_with_vibes twv
+            JOIN vibes v ON v.vibe_id = twv.vibe
+            WHERE twv.track = id
+        ), '')), 'D')
+$$;
+
+UPDATE tracks SET search_vector = track_search_vector(track_id, title, author, genre);
+
+DROP INDEX tracks_title_trgm_idx;
+DROP INDEX tracks_author_trgm_idx;
+CREATE INDEX tracks_title_trgm_idx ON tracks USING GIN (fold_accents(title) gin_trgm_ops);
+CREATE INDEX tracks_author_trgm_idx ON tracks USING GIN (fold_accents(author) gin_trgm_ops);
@@ -0,0 +1,6 @@
+-- Add down migration script here
+
+DROP INDEX tracks_title_prefix_idx;
+DROP INDEX tracks_author_prefix_idx;
+DROP INDEX tracks_genre_prefix_idx;
+DROP INDEX vibes_name_prefix_idx;
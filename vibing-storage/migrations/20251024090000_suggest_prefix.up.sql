@@ -0,0 +1,7 @@
+-- Add up migration script here
+
+-- byte-wise ordering serves the prefix range of /suggest whatever the collation
+CREATE INDEX tracks_title_prefix_idx ON tracks (LOWER(fold_accents(title)) text_pattern_ops);
+CREATE INDEX tracks_author_prefix_idx ON tracks (LOWER(fold_accents(author)) text_pattern_ops);
+CREATE INDEX tracks_genre_prefix_idx ON tracks (LOWER(fold_accents(genre)) text_pattern_ops);
+CREATE INDEX vibes_name_prefix_idx ON vibes (LOWER(fold_accents(name::TEXT)) text_pattern_ops);
@@ -0,0 +1,4 @@
+-- Add up migration script here
+
+ALTER TABLE tracks
+    ADD COLUMN play_count INT NOT NULL DEFAULT 0;
@@ -0,0 +1,6 @@
+-- Add down migration script here
+
+ALTER TABLE vibes
+    DROP CONSTRAINT vibes_group_name_fkey;
+
+DROP TABLE vibe_groups;
@@ -0,0 +1,31 @@
+-- Add up migration script here
+
+CREATE TABLE vibe_groups (
+    name CITEXT PRIMARY KEY,
+    display_name TEXT,
+    description TEXT,
+    sort_order INT NOT NULL DEFAULT 0,
+    -- `#rrggbb`
+    color TEXT,
+    icon TEXT,
+    -- a track carries at most one vibe of an exclusive group
+    exclusive BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+INSERT INTO vibe_groups (name, display_name, description, sort_order, exclusive)
+VALUES
+    ('seasonal', 'Seasonal', 'The time of the year a track belongs to', 1, FALSE),
+    ('daytime', 'Time of day', 'When in the day a track sounds best', 2, TRUE),
+    ('weather', 'Weather', 'The weather a track goes with', 3, FALSE);
+
+INSERT INTO vibe_groups (name)
+SELECT DISTINCT group_name
+FROM vibes
+WHERE group_name IS NOT NULL
+ON CONFLICT DO NOTHING;
+
+ALTER TABLE vibes
+    ADD CONSTRAINT vibes_group_name_fkey FOREIGN KEY (group_name)
+    REFERENCES vibe_groups(name) ON DELETE SET NULL ON UPDATE CASCADE;
+
+GRANT SELECT, INSERT, UPDATE, DELETE ON vibe_groups TO viber;
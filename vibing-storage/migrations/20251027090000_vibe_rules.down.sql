@@ -0,0 +1,7 @@
+-- Add down migration script here
+
+ALTER TABLE tracks_with_vibes
+    DROP COLUMN rule;
+
+DROP TABLE vibe_rule_vibes;
+DROP TABLE vibe_rules;
@@ -0,0 +1,25 @@
+-- Add up migration script here
+
+CREATE TABLE vibe_rules (
+    rule_id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    -- a filter expression over tracks, in its JSON form
+    filter JSONB NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE vibe_rule_vibes (
+    rule INT NOT NULL REFERENCES vibe_rules(rule_id) ON DELETE CASCADE,
+    vibe INT NOT NULL REFERENCES vibes(vibe_id) ON DELETE CASCADE,
+    PRIMARY KEY (rule, vibe)
+);
+
+-- the rule that added the vibe, NULL when it was added by hand
+ALTER TABLE tracks_with_vibes
+    ADD COLUMN rule INT NULL REFERENCES vibe_rules(rule_id) ON DELETE CASCADE;
+
+CREATE INDEX tracks_with_vibes_rule ON tracks_with_vibes(rule);
+
+GRANT SELECT, INSERT, UPDATE, DELETE ON vibe_rules, vibe_rule_vibes TO viber;
+GRANT USAGE ON SEQUENCE vibe_rules_rule_id_seq TO viber;
@@ -0,0 +1,7 @@
+-- Add down migration script here
+
+ALTER TABLE tracks_with_vibes
+    DROP COLUMN confidence,
+    DROP COLUMN locked,
+    DROP COLUMN down_votes,
+    DROP COLUMN up_votes;
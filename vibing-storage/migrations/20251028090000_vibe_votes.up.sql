@@ -0,0 +1,21 @@
+-- Add up migration script here
+
+ALTER TABLE tracks_with_vibes
+    ADD COLUMN up_votes INT NOT NULL DEFAULT 0,
+    ADD COLUMN down_votes INT NOT NULL DEFAULT 0,
+    -- a curated vibe, votes never take it off the track
+    ADD COLUMN locked BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- lower bound of the 95% Wilson interval of the share of up votes, the tag itself
+-- counting as one, a curated vibe is certain
+ALTER TABLE tracks_with_vibes
+    ADD COLUMN confidence REAL GENERATED ALWAYS AS (
+        CASE WHEN locked THEN 1.0 ELSE (
+            (up_votes + 1.0) / (up_votes + down_votes + 1.0)
+            + 1.9208 / (up_votes + down_votes + 1.0)
+            - 1.96 * sqrt(
+                (up_votes + 1.0) * down_votes / power(up_votes + down_votes + 1.0, 3)
+                + 0.9604 / power(up_votes + down_votes + 1.0, 2)
+            )
+        ) / (1.0 + 3.8416 / (up_votes + down_votes + 1.0)) END
+    ) STORED;
@@ -0,0 +1,11 @@
+-- Add up migration script here
+
+-- vibes taken off a track by hand or by votes, the rules leave them off
+CREATE TABLE suppressed_vibes (
+    track INT NOT NULL REFERENCES tracks(track_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    vibe INT NOT NULL REFERENCES vibes(vibe_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    suppressed_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (track, vibe)
+);
+
+GRANT SELECT, INSERT, UPDATE, DELETE ON suppressed_vibes TO viber;
@@ -0,0 +1,14 @@
+-- Add up migration script here
+
+-- one vote per listener for each vibe of a track, the counters of tracks_with_vibes
+-- add them up, votes for a vibe the track does not carry propose it
+CREATE TABLE vibe_votes (
+    track INT NOT NULL REFERENCES tracks(track_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    vibe INT NOT NULL REFERENCES vibes(vibe_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    voter TEXT NOT NULL,
+    up BOOLEAN NOT NULL,
+    voted_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (track, vibe, voter)
+);
+
+GRANT SELECT, INSERT, UPDATE, DELETE ON vibe_votes TO viber;
@@ -1,4 +1,6 @@
 pub mod api;
 pub mod error;
 pub mod fetch;
+pub mod format;
 pub mod services;
+pub mod state;
@@ -1,4 +1,8 @@
 pub mod delete;
+pub mod extract;
 pub mod get;
+pub mod paging;
 pub mod patch;
 pub mod post;
+pub mod put;
+pub mod tus;
@@ -1,8 +1,14 @@
-use crate::database::{core::pool::VibingPool, entities::track::TrackFull};
-use axum::{
-    extract::{Query, State},
-    http::StatusCode,
+use crate::{
+    app::{
+        api::extract::{Admin, Path, Query},
+        error::{AppError, Result},
+    },
+    database::{
+        core::pool::VibingPool,
+        entities::{track::TrackFull, track_vibe::TrackVibe, vibe::Vibe, vibe_rule::VibeRule},
+    },
 };
+use axum::{Json, extract::State, http::StatusCode};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
@@ -14,42 +20,52 @@ pub struct DeleteTrack {
 pub async fn delete_track(
     State(pool): State<VibingPool>,
     Query(target): Query<DeleteTrack>,
-) -> Result<StatusCode, StatusCode> {
-    if let Some(id) = target.id {
-        let track = match TrackFull::get_by_id(id, &pool).await {
-            Ok(track) => track,
-            Err(_) => {
-                return Err(StatusCode::NOT_FOUND);
-            }
-        };
-
-        match track.remove(&pool).await {
-            Ok(_) => {
-                return Ok(StatusCode::OK);
-            }
-            Err(_) => {
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
+) -> Result<StatusCode> {
+    let track = match (target.id, target.title) {
+        (Some(id), _) => TrackFull::get_by_id(id, &pool).await?,
+        (None, Some(title)) => TrackFull::get_by_title(&title, &pool).await?,
+        (None, None) => {
+            return Err(AppError::InvalidRequest(String::from(
+                "either id or title is required",
+            )));
         }
-    }
-
-    if let Some(title) = target.title {
-        let track = match TrackFull::get_by_title(&title, &pool).await {
-            Ok(track) => track,
-            Err(_) => {
-                return Err(StatusCode::NOT_FOUND);
-            }
-        };
-
-        match track.remove(&pool).await {
-            Ok(_) => {
-                return Ok(StatusCode::OK);
-            }
-            Err(_) => {
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
-        }
-    }
+    };
+
+    track.remove(&pool).await?;
+
+    Ok(StatusCode::OK)
+}
 
-    Err(StatusCode::BAD_REQUEST)
+/// Deletes a vibe, the tracks tagged with it lose the tag
+pub async fn delete_vibe(
+    _: Admin,
+    State(pool): State<VibingPool>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode> {
+    Vibe::remove(id, &pool).await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Deletes a rule, the tracks lose the vibes it added
+pub async fn delete_vibe_rule(
+    _: Admin,
+    State(pool): State<VibingPool>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode> {
+    VibeRule::remove(id, &pool).await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Lets votes take the vibe off the track again
+pub async fn unlock_track_vibe(
+    _: Admin,
+    State(pool): State<VibingPool>,
+    Path((id, vibe_id)): Path<(i32, i32)>,
+) -> Result<(StatusCode, Json<TrackVibe>)> {
+    Ok((
+        StatusCode::OK,
+        Json(TrackVibe::set_locked(id, vibe_id, false, &pool).await?),
+    ))
 }
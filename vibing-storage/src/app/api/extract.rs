@@ -0,0 +1,144 @@
+use crate::{app::error::AppError, config::Configuration};
+use axum::{
+    extract::{ConnectInfo, FromRequest, FromRequestParts},
+    http::{HeaderMap, header, request::Parts},
+};
+use std::net::{IpAddr, SocketAddr};
+
+/// Query string extractor that accepts repeated keys for lists (`vibes=1&vibes=2`)
+/// and answers a malformed query string with a problem document
+#[derive(Debug, Clone, Copy, Default, FromRequestParts)]
+#[from_request(via(axum_extra::extract::Query), rejection(AppError))]
+pub struct Query<T>(pub T);
+
+/// JSON body extractor that answers a missing or malformed body with a problem document
+#[derive(Debug, Clone, Copy, Default, FromRequest)]
+#[from_request(via(axum::Json), rejection(AppError))]
+pub struct JsonBody<T>(pub T);
+
+/// Path parameters extractor that answers a malformed segment with a problem document
+#[derive(Debug, Clone, Copy, Default, FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(AppError))]
+pub struct Path<T>(pub T);
+
+/// Guards a handler with the `Authorization: Bearer` token of `admin_token`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Admin;
+
+impl<S: Send + Sync> FromRequestParts<S> for Admin {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Admin::from_headers(&parts.headers)
+    }
+}
+
+impl Admin {
+    /// For handlers that only guard some of what they do
+    pub fn from_headers(headers: &HeaderMap) -> Result<Admin, AppError> {
+        let Some(expected) = &Configuration::get().admin_token else {
+            return Err(AppError::Forbidden(String::from(
+                "admin actions are turned off, no admin token is configured",
+            )));
+        };
+
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+
+        // compares every byte so the time taken does not tell how much matched
+        let matches = token.len() == expected.len()
+            && token
+                .bytes()
+                .zip(expected.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0;
+
+        if matches {
+            Ok(Admin)
+        } else {
+            Err(AppError::Unauthorized(String::from(
+                "a valid admin token is required",
+            )))
+        }
+    }
+}
+
+/// Tells listeners apart by their address, one vote each.
+///
+/// The address is the peer of the connection, or the one `trusted_proxy_header` holds
+/// when the server sits behind a reverse proxy. Listeners sharing an address, as behind
+/// a NAT, share a single vote, and a listener changing address votes again: votes weigh
+/// opinions, they do not authenticate anyone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Voter(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for Voter {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let address = match &Configuration::get().trusted_proxy_header {
+            Some(name) => forwarded_address(&parts.headers, name),
+            None => parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(address)| address.ip()),
+        };
+
+        address
+            .map(|address| Voter(address.to_string()))
+            .ok_or_else(|| {
+                AppError::Forbidden(String::from("votes need the address of the listener"))
+            })
+    }
+}
+
+/// The address the proxy appended last to the `name` header, the ones before it are
+/// whatever the client sent
+fn forwarded_address(headers: &HeaderMap, name: &str) -> Option<IpAddr> {
+    headers
+        .get_all(name)
+        .iter()
+        .next_back()?
+        .to_str()
+        .ok()?
+        .rsplit(',')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn forwarded_address_trusts_only_the_last_hop() {
+        let cases = [
+            (vec!["203.0.113.7"], Some("203.0.113.7")),
+            (vec![" 10.0.0.1 , 203.0.113.7 "], Some("203.0.113.7")),
+            (vec!["10.0.0.1", "203.0.113.7"], Some("203.0.113.7")),
+            (vec!["2001:db8::1"], Some("2001:db8::1")),
+            (vec!["203.0.113.7, unknown"], None),
+            (vec![""], None),
+            (vec![], None),
+        ];
+
+        for (values, address) in cases {
+            let mut headers = HeaderMap::new();
+            for value in &values {
+                headers.append("x-forwarded-for", HeaderValue::from_str(value).unwrap());
+            }
+
+            assert_eq!(
+                forwarded_address(&headers, "x-forwarded-for"),
+                address.map(|address| address.parse().unwrap()),
+                "{values:?}"
+            );
+        }
+    }
+}
@@ -1,21 +1,47 @@
 use crate::{
-    app::services::download::DownloadableFile,
+    app::{
+        api::{
+            extract::{Path, Query},
+            paging::{
+                PageShape, check_page_bounds, decode_cursor, default_page, default_page_size,
+                page_response,
+            },
+        },
+        error::{AppError, Result},
+        fetch::normalize_text,
+        services::{
+            download::DownloadableFile,
+            moment::{Hemisphere, Moment},
+            stream_music::{StreamRange, read_range},
+            suggest::SuggestionCache,
+            weather::{Place, WeatherProvider},
+        },
+    },
     database::{
         core::pool::VibingPool,
         entities::{
             Paginate,
-            track::{TrackFilter, TrackFull, TrackFullPatch, TrackPaginationParams},
+            suggestion::Suggestions,
+            track::{TrackFilter, TrackFull, TrackFullPatch, TrackPaginationParams, VibeMatch},
+            track_vibe::TrackVibe,
+            vibe::{Vibe, VibeCount},
+            vibe_group::VibeGroup,
+            vibe_rule::VibeRule,
         },
+        filter::{FilterExpr, VibeRef},
+        sort::{Direction, SortKey, TrackOrder},
     },
 };
 use axum::{
     Json,
     body::Body,
-    extract::{Query, State},
-    http::{Response, StatusCode, header},
+    extract::State,
+    http::{HeaderMap, Response, StatusCode, Uri, header},
     response::IntoResponse,
 };
+use chrono::{DateTime, FixedOffset, Utc};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use tokio_util::io::ReaderStream;
 
 pub async fn get_root() -> String {
@@ -40,35 +66,386 @@ pub struct ResponseTrack {
     pub vibes: Vec<ResponseVibe>,
     pub average_rating: f64,
     pub download_count: i32,
+    pub play_count: i32,
+    pub codec: Option<String>,
+    pub container: Option<String>,
+    pub bitrate: Option<i32>,
+    pub sample_rate: Option<i32>,
+    pub channels: Option<i16>,
+    pub bit_depth: Option<i16>,
+    pub file_size: Option<i64>,
+    pub added_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
 pub struct PageFilterQuery {
+    /// Search box syntax, see `filter::query`
+    pub q: Option<String>,
     pub pattern: Option<String>,
     pub author: Option<String>,
     pub vibes: Option<Vec<i32>>,
+    pub vibe_match: Option<VibeMatch>,
+    /// From 0 to 1, see `TrackFilter`
+    pub min_vibe_confidence: Option<f64>,
+    pub exclude_vibes: Option<Vec<i32>>,
+    pub vibe_groups: Option<Vec<String>>,
+    pub exclude_vibe_groups: Option<Vec<String>>,
     pub limit: Option<i32>,
     pub order_by: Option<String>,
+    pub lossless: Option<bool>,
+    pub min_bitrate: Option<i32>,
+    pub max_bitrate: Option<i32>,
+    pub min_sample_rate: Option<i32>,
+    pub min_bit_depth: Option<i16>,
+    /// Count the facets of every matching track in the page object
+    pub facets: Option<bool>,
+    /// `next_cursor` of the previous page, takes the place of `page`
+    pub cursor: Option<String>,
+    #[serde(default = "default_page")]
     pub page: i32,
+    #[serde(default = "default_page_size")]
     pub size: i32,
 }
 
+/// Answers with the page object, or with the bare tracks to clients that accept
+/// `LIST_MEDIA_TYPE`
 pub async fn get_filtered_page(
     State(pool): State<VibingPool>,
-    Query(filter): Query<PageFilterQuery>,
-) -> Result<(StatusCode, Json<Vec<ResponseTrack>>), StatusCode> {
-    let page = match TrackFull::page(&filter.into(), &pool).await {
-        Ok(page) => page,
-        Err(_) => {
-            return Err(StatusCode::BAD_REQUEST);
+    Query(mut query): Query<PageFilterQuery>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response<Body>> {
+    check_page_bounds(query.page, query.size, query.limit)?;
+
+    let search = query.q.take().map(|q| FilterExpr::parse(&q)).transpose()?;
+    let cursor = query.cursor.take();
+    let order_by = query.order_by.clone();
+
+    let mut params = TrackPaginationParams::from(query);
+    if let Some(search) = search {
+        params.filter = FilterExpr::And(vec![params.filter, search]);
+    }
+
+    // the order depends on the patterns of the search box too
+    params.order = TrackOrder::parse(order_by.as_deref(), &params.filter)?;
+    params.cursor = cursor
+        .map(|cursor| decode_cursor(&cursor, &params.order))
+        .transpose()?;
+
+    let page = TrackFull::page(&params, &pool)
+        .await?
+        .map(ResponseTrack::from);
+
+    Ok(page_response(page, PageShape::from_headers(&headers), &uri))
+}
+
+/// Most completions of each kind a suggestion request can ask for
+const MAX_SUGGESTIONS: i64 = 20;
+const MAX_PREFIX_LEN: usize = 100;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct SuggestQuery {
+    pub prefix: String,
+    /// Per kind, 5 when left out
+    pub limit: Option<i64>,
+}
+
+/// Completes what is typed in the search box with titles, authors, genres and vibes
+pub async fn handle_suggest_request(
+    State(pool): State<VibingPool>,
+    State(cache): State<SuggestionCache>,
+    Query(query): Query<SuggestQuery>,
+) -> Result<(StatusCode, Json<Suggestions>)> {
+    let prefix = normalize_text(query.prefix.trim_start());
+    if prefix.is_empty() {
+        return Err(AppError::InvalidRequest(String::from("prefix is required")));
+    }
+
+    if prefix.chars().count() > MAX_PREFIX_LEN {
+        return Err(AppError::InvalidRequest(format!(
+            "prefix is longer than {} characters",
+            MAX_PREFIX_LEN
+        )));
+    }
+
+    let limit = query.limit.unwrap_or(5);
+    if !(1..=MAX_SUGGESTIONS).contains(&limit) {
+        return Err(AppError::InvalidRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_SUGGESTIONS
+        )));
+    }
+
+    let suggestions = cache.get_or_fetch(&prefix, limit, &pool).await?;
+
+    Ok((StatusCode::OK, Json(suggestions)))
+}
+
+/// A vibe group and its vibes, `group_name` is `None` for the ungrouped ones
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct ResponseVibeGroup {
+    pub group_name: Option<String>,
+    pub display_name: Option<String>,
+    pub description: Option<String>,
+    pub sort_order: Option<i32>,
+    pub color: Option<String>,
+    pub icon: Option<String>,
+    pub exclusive: bool,
+    pub vibes: Vec<VibeCount>,
+}
+
+/// Lists every vibe with its number of tracks, by group in their sort order
+pub async fn get_vibes(
+    State(pool): State<VibingPool>,
+) -> Result<(StatusCode, Json<Vec<ResponseVibeGroup>>)> {
+    let mut vibes = VibeCount::get_all(&pool).await?;
+    let mut groups = Vec::new();
+
+    for group in VibeGroup::get_all(&pool).await? {
+        // group names ignore case like the column does
+        let group_key = group.name.to_lowercase();
+        let (group_vibes, others) = vibes.into_iter().partition(|vibe| {
+            vibe.group_name
+                .as_deref()
+                .is_some_and(|group_name| group_name.to_lowercase() == group_key)
+        });
+        vibes = others;
+
+        groups.push(ResponseVibeGroup {
+            group_name: Some(group.name),
+            display_name: group.display_name,
+            description: group.description,
+            sort_order: Some(group.sort_order),
+            color: group.color,
+            icon: group.icon,
+            exclusive: group.exclusive,
+            vibes: group_vibes,
+        });
+    }
+
+    if !vibes.is_empty() {
+        groups.push(ResponseVibeGroup {
+            vibes,
+            ..Default::default()
+        });
+    }
+
+    Ok((StatusCode::OK, Json(groups)))
+}
+
+pub async fn get_vibe_groups(
+    State(pool): State<VibingPool>,
+) -> Result<(StatusCode, Json<Vec<VibeGroup>>)> {
+    Ok((StatusCode::OK, Json(VibeGroup::get_all(&pool).await?)))
+}
+
+/// The vibes of a track with their votes, the surest first
+pub async fn get_track_vibes(
+    State(pool): State<VibingPool>,
+    Path(id): Path<i32>,
+) -> Result<(StatusCode, Json<Vec<TrackVibe>>)> {
+    // tells an unknown track from one without vibes
+    TrackFull::get_by_id(id, &pool).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(TrackVibe::get_by_track_id(id, &pool).await?),
+    ))
+}
+
+/// Every rule, in the order they are evaluated
+pub async fn get_vibe_rules(
+    State(pool): State<VibingPool>,
+) -> Result<(StatusCode, Json<Vec<VibeRule>>)> {
+    Ok((StatusCode::OK, Json(VibeRule::get_all(&pool).await?)))
+}
+
+pub async fn get_vibe_rule(
+    State(pool): State<VibingPool>,
+    Path(id): Path<i32>,
+) -> Result<(StatusCode, Json<VibeRule>)> {
+    Ok((StatusCode::OK, Json(VibeRule::get_by_id(id, &pool).await?)))
+}
+
+/// Headers a client can tell where it is with, the query takes precedence
+const TIMEZONE_HEADER: &str = "x-timezone";
+const HEMISPHERE_HEADER: &str = "x-hemisphere";
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct NowQuery {
+    /// IANA name such as `Europe/Paris` or offset such as `+07:00` (`%2B07:00` once
+    /// escaped, an unescaped `+` is accepted too), UTC when not sent
+    pub tz: Option<String>,
+    /// Told by the latitude when left out, north without either
+    pub hemisphere: Option<Hemisphere>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// Name of a `weather` vibe, the weather provider is asked when left out
+    pub weather: Option<String>,
+    #[serde(default = "default_page")]
+    pub page: i32,
+    #[serde(default = "default_page_size")]
+    pub size: i32,
+}
+
+/// The vibes of the moment of a client, a vibe missing from the library is `None`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ResponseNow {
+    pub local_time: DateTime<FixedOffset>,
+    pub timezone: String,
+    pub hemisphere: Hemisphere,
+    pub season: Option<ResponseVibe>,
+    pub daytime: Option<ResponseVibe>,
+    pub weather: Option<ResponseVibe>,
+}
+
+impl ResponseNow {
+    fn vibe_ids(&self) -> Vec<i32> {
+        [&self.season, &self.daytime, &self.weather]
+            .into_iter()
+            .flatten()
+            .map(|vibe| vibe.id)
+            .collect()
+    }
+}
+
+/// The season, time of day and weather vibes of where the client is
+pub async fn get_vibes_now(
+    State(pool): State<VibingPool>,
+    State(weather): State<Arc<dyn WeatherProvider>>,
+    Query(query): Query<NowQuery>,
+    headers: HeaderMap,
+) -> Result<(StatusCode, Json<ResponseNow>)> {
+    let now = now_context(&query, &headers, weather.as_ref(), &pool).await?;
+
+    Ok((StatusCode::OK, Json(now)))
+}
+
+/// The tracks carrying any vibe of the moment of the client, the best rated first
+pub async fn get_tracks_now(
+    State(pool): State<VibingPool>,
+    State(weather): State<Arc<dyn WeatherProvider>>,
+    Query(query): Query<NowQuery>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response<Body>> {
+    check_page_bounds(query.page, query.size, None)?;
+
+    let now = now_context(&query, &headers, weather.as_ref(), &pool).await?;
+    let filter = FilterExpr::Or(
+        now.vibe_ids()
+            .into_iter()
+            .map(|id| FilterExpr::Vibe(VibeRef::Id(id)))
+            .collect(),
+    );
+
+    let params = TrackPaginationParams {
+        page_num: query.page,
+        page_size: query.size,
+        filter,
+        order: TrackOrder::by(SortKey::Rating, Direction::Desc),
+        ..Default::default()
+    };
+
+    let page = TrackFull::page(&params, &pool)
+        .await?
+        .map(ResponseTrack::from);
+
+    Ok(page_response(page, PageShape::from_headers(&headers), &uri))
+}
+
+async fn now_context(
+    query: &NowQuery,
+    headers: &HeaderMap,
+    provider: &dyn WeatherProvider,
+    pool: &VibingPool,
+) -> Result<ResponseNow> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_string())
+    };
+
+    // a `+` left unescaped in the query string arrives as a space
+    let timezone = query
+        .tz
+        .as_deref()
+        .map(|tz| match tz.strip_prefix(' ') {
+            Some(offset) if offset.starts_with(|c: char| c.is_ascii_digit()) => {
+                format!("+{}", offset.trim())
+            }
+            _ => tz.trim().to_string(),
+        })
+        .or_else(|| header(TIMEZONE_HEADER))
+        .unwrap_or_else(|| String::from("UTC"));
+
+    let hemisphere = match query.hemisphere {
+        Some(hemisphere) => hemisphere,
+        None => match header(HEMISPHERE_HEADER) {
+            Some(hemisphere) => match hemisphere.to_lowercase().as_str() {
+                "north" => Hemisphere::North,
+                "south" => Hemisphere::South,
+                _ => {
+                    return Err(AppError::InvalidRequest(String::from(
+                        "hemisphere must be north or south",
+                    )));
+                }
+            },
+            None if query.latitude.is_some_and(|latitude| latitude < 0.0) => Hemisphere::South,
+            None => Hemisphere::North,
+        },
+    };
+
+    let moment = Moment::at(Utc::now(), &timezone, hemisphere).ok_or_else(|| {
+        AppError::InvalidRequest(format!(
+            "{} is neither a timezone name such as Europe/Paris nor an offset such as +07:00",
+            timezone
+        ))
+    })?;
+
+    let weather = match &query.weather {
+        Some(name) => {
+            let vibe = Vibe::get_in_group(name.trim(), "weather", pool).await?;
+            if vibe.is_none() {
+                return Err(AppError::InvalidRequest(format!(
+                    "{} is not a weather vibe",
+                    name
+                )));
+            }
+            vibe
+        }
+        None => {
+            let place = Place {
+                timezone: timezone.clone(),
+                latitude: query.latitude,
+                longitude: query.longitude,
+            };
+
+            // the moment still has a season and a time of day without the weather
+            match provider.current(&place).await {
+                Ok(Some(name)) => Vibe::get_in_group(&name, "weather", pool).await?,
+                Ok(None) => None,
+                Err(_) => {
+                    // LOG_WEATHER_ERROR
+                    None
+                }
+            }
         }
     };
-    let mut response_tracks = Vec::new();
-    for track in page.items {
-        response_tracks.push(track.into());
-    }
 
-    Ok((StatusCode::OK, Json(response_tracks)))
+    Ok(ResponseNow {
+        local_time: moment.local_time,
+        timezone,
+        hemisphere,
+        season: Vibe::get_in_group(moment.season(), "seasonal", pool)
+            .await?
+            .map(ResponseVibe::from),
+        daytime: Vibe::get_in_group(moment.daytime(), "daytime", pool)
+            .await?
+            .map(ResponseVibe::from),
+        weather: weather.map(ResponseVibe::from),
+    })
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
@@ -79,26 +456,14 @@ pub struct DownloadQuery {
 pub async fn handle_download_request(
     State(pool): State<VibingPool>,
     Query(target_track): Query<DownloadQuery>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let track_full = match TrackFull::get_by_id(target_track.track_id, &pool).await {
-        Ok(track_full) => track_full,
-        Err(_) => {
-            return Err(StatusCode::NOT_FOUND);
-        }
-    };
+) -> Result<impl IntoResponse> {
+    let track_full = TrackFull::get_by_id(target_track.track_id, &pool).await?;
 
-    let path = &track_full.track.path;
-
-    let downloadable_file = match DownloadableFile::get_from(&path).await {
-        Ok(file) => file,
-        Err(_) => {
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
-    };
+    let downloadable_file = open_track_file(&track_full).await?;
 
     let body = Body::from_stream(ReaderStream::new(downloadable_file.file));
 
-    let response = match Response::builder()
+    let response = Response::builder()
         .status(200)
         .header(header::CONTENT_TYPE, &downloadable_file.content_type)
         .header(
@@ -106,21 +471,14 @@ pub async fn handle_download_request(
             format!("attachment; filename=\"{}\"", downloadable_file.name),
         )
         .body(body)
-    {
-        Ok(response) => response,
-        Err(_) => {
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
-    };
+        .map_err(|error| AppError::IoError(error.to_string()))?;
 
     let patch = TrackFullPatch {
         new_download: true,
         ..Default::default()
     };
 
-    if track_full.apply_patch(patch, &pool).await.is_err() {
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-    }
+    track_full.apply_patch(patch, &pool).await?;
 
     Ok(response)
 }
@@ -141,83 +499,162 @@ pub struct ResponseMusicStream {
 pub async fn handle_stream_request(
     State(pool): State<VibingPool>,
     Query(target_track): Query<MusicStreamQuery>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let track_full = match TrackFull::get_by_id(target_track.track_id, &pool).await {
-        Ok(track_full) => track_full,
-        Err(_) => {
-            return Err(StatusCode::NOT_FOUND);
-        }
-    };
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    let track_full = TrackFull::get_by_id(target_track.track_id, &pool).await?;
 
-    let path = &track_full.track.path;
+    let downloadable_file = open_track_file(&track_full).await?;
 
-    let downloadable_file = match DownloadableFile::get_from(&path).await {
-        Ok(file) => file,
-        Err(_) => {
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
+    // an explicit Range header takes precedence over the start_at position
+    let range = match StreamRange::from_headers(&headers, &downloadable_file) {
+        Some(range) => range,
+        None => match (target_track.start_at, track_full.track.duration) {
+            (Some(start_at), Some(duration)) => {
+                StreamRange::from_seconds(start_at, duration, downloadable_file.size)
+            }
+            _ => StreamRange::Full,
+        },
     };
 
-    let body = Body::from_stream(ReaderStream::new(downloadable_file.file));
-
-    let response = match Response::builder()
-        .status(200)
+    let mut builder = Response::builder()
         .header(header::CONTENT_TYPE, &downloadable_file.content_type)
+        .header(header::ACCEPT_RANGES, "bytes")
         .header(header::CACHE_CONTROL, "no-cache")
-        .body(body)
-    {
-        Ok(response) => response,
-        Err(_) => {
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        .header(header::ETAG, downloadable_file.etag());
+
+    if let Some(last_modified) = downloadable_file.last_modified() {
+        builder = builder.header(header::LAST_MODIFIED, last_modified);
+    }
+
+    let size = downloadable_file.size;
+
+    // a listen asks for the whole file once, probes, seeks and buffering ask for less
+    let is_new_play = range.is_whole_file(size);
+    let response = match range {
+        StreamRange::Full => builder
+            .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, size)
+            .body(Body::from_stream(ReaderStream::new(downloadable_file.file))),
+        StreamRange::Partial { start, end } => {
+            let length = end - start + 1;
+            let reader = read_range(downloadable_file.file, start, length).await?;
+
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", start, end, size),
+                )
+                .header(header::CONTENT_LENGTH, length)
+                .body(Body::from_stream(ReaderStream::new(reader)))
         }
+        StreamRange::Unsatisfiable => builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", size))
+            .body(Body::empty()),
     };
+    let response = response.map_err(|error| AppError::IoError(error.to_string()))?;
+
+    if is_new_play {
+        let patch = TrackFullPatch {
+            new_play: true,
+            ..Default::default()
+        };
+
+        track_full.apply_patch(patch, &pool).await?;
+    }
 
     Ok(response)
 }
 
-impl Into<ResponseTrack> for TrackFull {
-    fn into(self) -> ResponseTrack {
+/// A track whose file vanished from disk is reported as gone rather than as a failure
+async fn open_track_file(track_full: &TrackFull) -> Result<DownloadableFile> {
+    if track_full.track.missing {
+        return Err(AppError::Gone(format!(
+            "the file of track {} is missing",
+            track_full.track.id
+        )));
+    }
+
+    DownloadableFile::get_from(&track_full.track.path).await
+}
+
+impl From<Vibe> for ResponseVibe {
+    fn from(vibe: Vibe) -> Self {
+        ResponseVibe {
+            id: vibe.id,
+            name: vibe.name,
+            group_name: vibe.group_name,
+        }
+    }
+}
+
+impl From<TrackFull> for ResponseTrack {
+    fn from(track_full: TrackFull) -> Self {
         let mut vibes = Vec::new();
-        for vibe in self.vibes {
-            vibes.push(ResponseVibe {
-                id: vibe.id,
-                group_name: vibe.group_name,
-                name: vibe.name,
-            });
+        for vibe in track_full.vibes {
+            vibes.push(ResponseVibe::from(vibe));
         }
 
-        let average_rating = if self.track.vote_count != 0 {
-            self.track.total_rating as f64 / self.track.vote_count as f64
+        let average_rating = if track_full.track.vote_count != 0 {
+            track_full.track.total_rating as f64 / track_full.track.vote_count as f64
         } else {
             0.00
         };
 
         ResponseTrack {
-            id: self.track.id,
-            path: self.track.path,
-            title: self.track.title,
-            author: self.track.author,
-            genre: self.track.genre,
-            duration: self.track.duration,
+            id: track_full.track.id,
+            path: track_full.track.path,
+            title: track_full.track.title,
+            author: track_full.track.author,
+            genre: track_full.track.genre,
+            duration: track_full.track.duration,
             vibes,
             average_rating,
-            download_count: self.track.download_count,
+            download_count: track_full.track.download_count,
+            play_count: track_full.track.play_count,
+            codec: track_full.track.codec,
+            container: track_full.track.container,
+            bitrate: track_full.track.bitrate,
+            sample_rate: track_full.track.sample_rate,
+            channels: track_full.track.channels,
+            bit_depth: track_full.track.bit_depth,
+            file_size: track_full.track.file_size,
+            added_at: track_full.track.added_at,
         }
     }
 }
 
-impl Into<TrackPaginationParams> for PageFilterQuery {
-    fn into(self) -> TrackPaginationParams {
+impl From<PageFilterQuery> for TrackPaginationParams {
+    fn from(query: PageFilterQuery) -> Self {
+        let filter = TrackFilter {
+            pattern: query.pattern,
+            author: query.author,
+            vibes: query.vibes,
+            vibe_match: query.vibe_match,
+            min_vibe_confidence: query.min_vibe_confidence,
+            exclude_vibes: query.exclude_vibes,
+            vibe_groups: query.vibe_groups,
+            exclude_vibe_groups: query.exclude_vibe_groups,
+            limit: query.limit,
+            order_by: query.order_by,
+            lossless: query.lossless,
+            min_bitrate: query.min_bitrate,
+            max_bitrate: query.max_bitrate,
+            min_sample_rate: query.min_sample_rate,
+            min_bit_depth: query.min_bit_depth,
+        };
+        let filter_expr = FilterExpr::from(&filter);
+
         TrackPaginationParams {
-            page_num: self.page,
-            page_size: self.size,
-            filter: TrackFilter {
-                pattern: self.pattern,
-                author: self.author,
-                vibes: self.vibes,
-                limit: self.limit,
-                order_by: self.order_by,
-            },
+            page_num: query.page,
+            page_size: query.size,
+            // `order_by` can be refused, it is read by the handler
+            order: TrackOrder::default(),
+            filter: filter_expr,
+            cursor: None,
+            limit: filter.limit,
+            facets: query.facets.unwrap_or_default(),
         }
     }
 }
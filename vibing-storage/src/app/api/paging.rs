@@ -0,0 +1,283 @@
+use crate::{
+    app::error::{AppError, Result},
+    database::{
+        entities::Page,
+        sort::{Cursor, TrackOrder},
+    },
+};
+use axum::{
+    Json,
+    body::Body,
+    http::{HeaderMap, HeaderValue, Response, StatusCode, Uri, header},
+    response::IntoResponse,
+};
+use serde::Serialize;
+
+/// Largest page a listing can ask for
+pub const MAX_PAGE_SIZE: i32 = 100;
+
+/// The page object, also what `application/json` gets
+pub const PAGE_MEDIA_TYPE: &str = "application/vnd.vibing.page+json";
+/// The bare array of items that listings answered with before the page object
+pub const LIST_MEDIA_TYPE: &str = "application/vnd.vibing.list+json";
+
+/// Headers of a paged listing the web UI needs to read across origins
+pub const PAGE_EXPOSED_HEADERS: [&str; 2] = ["x-total-count", "link"];
+
+pub fn default_page() -> i32 {
+    1
+}
+
+pub fn default_page_size() -> i32 {
+    20
+}
+
+/// Checks the page number, the page size and the `limit` capping it
+pub fn check_page_bounds(page: i32, size: i32, limit: Option<i32>) -> Result<()> {
+    if page < 1 {
+        return Err(AppError::InvalidRequest(String::from("page starts at 1")));
+    }
+
+    if !(1..=MAX_PAGE_SIZE).contains(&size) {
+        return Err(AppError::InvalidRequest(format!(
+            "size must be between 1 and {}",
+            MAX_PAGE_SIZE
+        )));
+    }
+
+    if limit.is_some_and(|limit| limit < 1) {
+        return Err(AppError::InvalidRequest(String::from(
+            "limit must be at least 1",
+        )));
+    }
+
+    Ok(())
+}
+
+/// Reads the `cursor` parameter of a listing sorted by `order`
+pub fn decode_cursor(cursor: &str, order: &TrackOrder) -> Result<Cursor> {
+    Cursor::decode(cursor, order).ok_or_else(|| {
+        AppError::InvalidRequest(String::from(
+            "cursor is invalid or was made for another order",
+        ))
+    })
+}
+
+/// How a client wants a listing, told by its Accept header
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PageShape {
+    #[default]
+    Page,
+    /// Only the items, for clients written before the page object
+    List,
+}
+
+impl PageShape {
+    pub fn from_headers(headers: &HeaderMap) -> PageShape {
+        let accepts_list = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|media_range| {
+                media_range
+                    .split(';')
+                    .next()
+                    .is_some_and(|media_type| media_type.trim() == LIST_MEDIA_TYPE)
+            });
+
+        if accepts_list {
+            PageShape::List
+        } else {
+            PageShape::Page
+        }
+    }
+}
+
+/// Answers with `page` in the negotiated shape. Either way the total goes in
+/// `X-Total-Count` and the neighbouring pages of `uri` in an RFC 8288 `Link` header.
+pub fn page_response<T: Serialize>(page: Page<T>, shape: PageShape, uri: &Uri) -> Response<Body> {
+    let mut headers = HeaderMap::new();
+    headers.insert("x-total-count", HeaderValue::from(page.total_items));
+    headers.insert(header::VARY, HeaderValue::from_static("accept"));
+
+    if let Ok(link) = HeaderValue::from_str(&link_header(&page, uri)) {
+        headers.insert(header::LINK, link);
+    }
+
+    match shape {
+        PageShape::Page => (StatusCode::OK, headers, Json(page)).into_response(),
+        PageShape::List => {
+            headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(LIST_MEDIA_TYPE),
+            );
+            (StatusCode::OK, headers, Json(page.items)).into_response()
+        }
+    }
+}
+
+fn link_header<T>(page: &Page<T>, uri: &Uri) -> String {
+    // pages reached through a cursor only know their way forward
+    if page.page_num == 0 {
+        let mut links = vec![(listing_uri(uri, "page", "1"), "first")];
+        if let Some(cursor) = &page.next_cursor {
+            links.push((listing_uri(uri, "cursor", cursor), "next"));
+        }
+
+        return format_links(links);
+    }
+
+    let last = page.total_page.max(1);
+    let mut links = vec![(1, "first")];
+
+    if page.page_num > 1 {
+        links.push(((page.page_num - 1).min(last), "prev"));
+    }
+    if page.page_num < page.total_page {
+        links.push((page.page_num + 1, "next"));
+    }
+    links.push((last, "last"));
+
+    format_links(
+        links
+            .into_iter()
+            .map(|(page_num, rel)| (listing_uri(uri, "page", &page_num.to_string()), rel))
+            .collect(),
+    )
+}
+
+fn format_links(links: Vec<(String, &str)>) -> String {
+    links
+        .into_iter()
+        .map(|(target, rel)| format!("<{}>; rel=\"{}\"", target, rel))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// `uri` asking for another page by `page` or `cursor`, every other parameter kept as it
+/// was sent
+fn listing_uri(uri: &Uri, name: &str, value: &str) -> String {
+    let mut pairs: Vec<&str> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| {
+            let key = pair.split('=').next().unwrap_or_default();
+            !pair.is_empty() && key != "page" && key != "cursor"
+        })
+        .collect();
+
+    let position = format!("{}={}", name, value);
+    pairs.push(&position);
+
+    format!("{}?{}", uri.path(), pairs.join("&"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(page_num: i32, total_page: i32, next_cursor: Option<&str>) -> Page<()> {
+        Page {
+            total_page,
+            page_num,
+            next_cursor: next_cursor.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn check_page_bounds_refuses_out_of_range_values() {
+        let cases = [
+            (1, 20, None, true),
+            (i32::MAX, MAX_PAGE_SIZE, Some(1), true),
+            (0, 20, None, false),
+            (1, 0, None, false),
+            (1, MAX_PAGE_SIZE + 1, None, false),
+            (1, 20, Some(0), false),
+            (1, 20, Some(-5), false),
+        ];
+
+        for (page, size, limit, valid) in cases {
+            assert_eq!(
+                check_page_bounds(page, size, limit).is_ok(),
+                valid,
+                "{page} {size} {limit:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn from_headers_negotiates_the_shape() {
+        let cases = [
+            (vec![], PageShape::Page),
+            (vec!["application/json"], PageShape::Page),
+            (vec![PAGE_MEDIA_TYPE], PageShape::Page),
+            (vec![LIST_MEDIA_TYPE], PageShape::List),
+            (
+                vec!["application/json;q=0.5, application/vnd.vibing.list+json ; q=1"],
+                PageShape::List,
+            ),
+            (vec!["text/html", LIST_MEDIA_TYPE], PageShape::List),
+            (vec!["application/vnd.vibing.list+jsonx"], PageShape::Page),
+        ];
+
+        for (accepts, shape) in cases {
+            let mut headers = HeaderMap::new();
+            for accept in &accepts {
+                headers.append(header::ACCEPT, HeaderValue::from_str(accept).unwrap());
+            }
+
+            assert_eq!(PageShape::from_headers(&headers), shape, "{accepts:?}");
+        }
+    }
+
+    #[test]
+    fn link_header_points_at_the_neighbouring_pages() {
+        let uri: Uri = "/tracks?author=Nujabes&page=2&size=10".parse().unwrap();
+        let cases = [
+            (
+                page(2, 3, None),
+                "</tracks?author=Nujabes&size=10&page=1>; rel=\"first\", \
+                 </tracks?author=Nujabes&size=10&page=1>; rel=\"prev\", \
+                 </tracks?author=Nujabes&size=10&page=3>; rel=\"next\", \
+                 </tracks?author=Nujabes&size=10&page=3>; rel=\"last\"",
+            ),
+            (
+                page(1, 0, None),
+                "</tracks?author=Nujabes&size=10&page=1>; rel=\"first\", \
+                 </tracks?author=Nujabes&size=10&page=1>; rel=\"last\"",
+            ),
+            // past the end, prev leads back to the last page
+            (
+                page(7, 3, None),
+                "</tracks?author=Nujabes&size=10&page=1>; rel=\"first\", \
+                 </tracks?author=Nujabes&size=10&page=3>; rel=\"prev\", \
+                 </tracks?author=Nujabes&size=10&page=3>; rel=\"last\"",
+            ),
+            (
+                page(0, 3, Some("abc")),
+                "</tracks?author=Nujabes&size=10&page=1>; rel=\"first\", \
+                 </tracks?author=Nujabes&size=10&cursor=abc>; rel=\"next\"",
+            ),
+        ];
+
+        for (page, link) in cases {
+            assert_eq!(link_header(&page, &uri), link, "{page:?}");
+        }
+    }
+
+    #[test]
+    fn page_response_sends_the_negotiated_shape() {
+        let uri: Uri = "/tracks".parse().unwrap();
+        let list = page_response(page(1, 1, None), PageShape::List, &uri);
+
+        assert_eq!(list.headers()["x-total-count"], "0");
+        assert_eq!(list.headers()[header::CONTENT_TYPE], LIST_MEDIA_TYPE);
+        assert!(list.headers().contains_key(header::LINK));
+
+        let page = page_response(page(1, 1, None), PageShape::Page, &uri);
+        assert_eq!(page.headers()[header::CONTENT_TYPE], "application/json");
+    }
+}
@@ -1,12 +1,28 @@
-use crate::database::{
-    core::pool::VibingPool,
-    entities::track::{TrackFull, TrackFullPatch},
+use crate::{
+    app::{
+        api::{
+            extract::{Admin, JsonBody, Path, Query},
+            post::{check_rule_vibes, check_vibe_name, rule_filter},
+        },
+        error::{AppError, Result},
+    },
+    database::{
+        core::pool::VibingPool,
+        entities::{
+            track::{TrackFull, TrackFullPatch},
+            vibe::{Vibe, VibeCount, VibePatch},
+            vibe_group::{VibeGroup, VibeGroupPatch},
+            vibe_rule::{VibeRule, VibeRulePatch},
+        },
+        filter::FilterExpr,
+    },
 };
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
+    Json,
+    extract::State,
+    http::{HeaderMap, StatusCode},
 };
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
 pub struct TrackPatchQuery {
@@ -21,41 +37,297 @@ pub struct TrackPatchQuery {
     pub remove_vibes: Option<Vec<i32>>,
 }
 
+/// Changing the vibes of a track takes the admin token, listeners vote on them instead
 pub async fn update_track(
     State(pool): State<VibingPool>,
+    headers: HeaderMap,
     Query(patch): Query<TrackPatchQuery>,
-) -> Result<StatusCode, StatusCode> {
+) -> Result<StatusCode> {
+    if patch.add_vibes.is_some() || patch.remove_vibes.is_some() {
+        Admin::from_headers(&headers)?;
+    }
+
     let (track_id, track_patch) = patch.into();
 
-    let track = match TrackFull::get_by_id(track_id, &pool).await {
-        Ok(track) => track,
-        Err(_) => {
-            return Err(StatusCode::NOT_FOUND);
-        }
-    };
-
-    if track.apply_patch(track_patch, &pool).await.is_err() {
-        return Err(StatusCode::BAD_REQUEST);
-    }
+    let track = TrackFull::get_by_id(track_id, &pool).await?;
+    track.apply_patch(track_patch, &pool).await?;
 
     Ok(StatusCode::OK)
 }
 
-impl Into<(i32, TrackFullPatch)> for TrackPatchQuery {
-    fn into(self) -> (i32, TrackFullPatch) {
+impl From<TrackPatchQuery> for (i32, TrackFullPatch) {
+    fn from(query: TrackPatchQuery) -> Self {
         (
-            self.id,
+            query.id,
             TrackFullPatch {
-                path: self.path,
-                title: self.title,
-                author: self.author,
-                genre: self.genre,
-                duration: self.duration,
-                new_vote: self.rating,
+                path: query.path,
+                title: query.title,
+                author: query.author,
+                genre: query.genre,
+                duration: query.duration,
+                new_vote: query.rating,
                 new_download: false,
-                add_vibes: self.add_vibes,
-                remove_vibes: self.remove_vibes,
+                add_vibes: query.add_vibes,
+                remove_vibes: query.remove_vibes,
+                ..Default::default()
             },
         )
     }
 }
+
+/// Most vibes a single request can change
+const MAX_VIBE_PATCHES: usize = 100;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct VibePatchRequest {
+    pub name: Option<String>,
+    /// `null` takes the vibe out of its group, leaving it out keeps the group
+    #[serde(default, deserialize_with = "present")]
+    pub group_name: Option<Option<String>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct VibeBatchPatchRequest {
+    pub id: i32,
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    pub group_name: Option<Option<String>>,
+}
+
+/// Renames or regroups one vibe
+pub async fn update_vibe(
+    _: Admin,
+    State(pool): State<VibingPool>,
+    Path(id): Path<i32>,
+    JsonBody(request): JsonBody<VibePatchRequest>,
+) -> Result<(StatusCode, Json<VibeCount>)> {
+    let patch = vibe_patch(id, request.name, request.group_name)?;
+    Vibe::update_all(&[patch], &pool).await?;
+
+    Ok((StatusCode::OK, Json(VibeCount::get_by_id(id, &pool).await?)))
+}
+
+/// Renames or regroups several vibes at once, all of them or none. Names only have to be
+/// unique once every change is made, so they can be swapped.
+pub async fn update_vibes(
+    _: Admin,
+    State(pool): State<VibingPool>,
+    JsonBody(requests): JsonBody<Vec<VibeBatchPatchRequest>>,
+) -> Result<(StatusCode, Json<Vec<VibeCount>>)> {
+    if requests.len() > MAX_VIBE_PATCHES {
+        return Err(AppError::InvalidRequest(format!(
+            "at most {} vibes can be changed at once",
+            MAX_VIBE_PATCHES
+        )));
+    }
+
+    let patches = requests
+        .into_iter()
+        .map(|request| vibe_patch(request.id, request.name, request.group_name))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut vibes = Vec::with_capacity(patches.len());
+    for vibe in Vibe::update_all(&patches, &pool).await? {
+        vibes.push(VibeCount::get_by_id(vibe.id, &pool).await?);
+    }
+
+    Ok((StatusCode::OK, Json(vibes)))
+}
+
+fn vibe_patch(
+    id: i32,
+    name: Option<String>,
+    group_name: Option<Option<String>>,
+) -> Result<VibePatch> {
+    Ok(VibePatch {
+        id,
+        name: name
+            .map(|name| check_vibe_name(&name, "name"))
+            .transpose()?,
+        group_name: group_name
+            .map(|group_name| {
+                group_name
+                    .map(|group_name| check_vibe_name(&group_name, "group_name"))
+                    .transpose()
+            })
+            .transpose()?,
+    })
+}
+
+/// Longest display name, icon or color of a vibe group
+const MAX_GROUP_FIELD_LEN: usize = 64;
+const MAX_GROUP_DESCRIPTION_LEN: usize = 1000;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct VibeGroupPatchRequest {
+    #[serde(default, deserialize_with = "present")]
+    pub display_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub description: Option<Option<String>>,
+    pub sort_order: Option<i32>,
+    /// `#rrggbb`
+    #[serde(default, deserialize_with = "present")]
+    pub color: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub icon: Option<Option<String>>,
+    pub exclusive: Option<bool>,
+}
+
+/// Changes the details of a vibe group. Making it exclusive fails while a track carries
+/// two of its vibes.
+pub async fn update_vibe_group(
+    _: Admin,
+    State(pool): State<VibingPool>,
+    Path(name): Path<String>,
+    JsonBody(request): JsonBody<VibeGroupPatchRequest>,
+) -> Result<(StatusCode, Json<VibeGroup>)> {
+    let color = request
+        .color
+        .map(|color| color.map(|color| color.trim().to_lowercase()));
+    if let Some(Some(color)) = &color {
+        let is_hex = color.len() == 7
+            && color.starts_with('#')
+            && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+        if !is_hex {
+            return Err(AppError::InvalidRequest(String::from(
+                "color must look like #rrggbb",
+            )));
+        }
+    }
+
+    let patch = VibeGroupPatch {
+        display_name: group_text(request.display_name, "display_name", MAX_GROUP_FIELD_LEN)?,
+        description: group_text(
+            request.description,
+            "description",
+            MAX_GROUP_DESCRIPTION_LEN,
+        )?,
+        sort_order: request.sort_order,
+        color,
+        icon: group_text(request.icon, "icon", MAX_GROUP_FIELD_LEN)?,
+        exclusive: request.exclusive,
+    };
+
+    let group = VibeGroup::update(&name, patch, &pool).await?;
+
+    Ok((StatusCode::OK, Json(group)))
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct VibeRulePatchRequest {
+    pub name: Option<String>,
+    /// Replaces the filter, or `query` in the search box syntax
+    pub filter: Option<FilterExpr>,
+    pub query: Option<String>,
+    /// Replaces every vibe of the rule
+    pub vibes: Option<Vec<i32>>,
+    pub enabled: Option<bool>,
+}
+
+/// Changes a rule, the catalog follows on the next apply
+pub async fn update_vibe_rule(
+    _: Admin,
+    State(pool): State<VibingPool>,
+    Path(id): Path<i32>,
+    JsonBody(request): JsonBody<VibeRulePatchRequest>,
+) -> Result<(StatusCode, Json<VibeRule>)> {
+    let patch = VibeRulePatch {
+        name: request
+            .name
+            .map(|name| check_vibe_name(&name, "name"))
+            .transpose()?,
+        filter: rule_filter(request.filter, request.query)?,
+        vibes: request.vibes.map(check_rule_vibes).transpose()?,
+        enabled: request.enabled,
+    };
+
+    let rule = VibeRule::update(id, patch, &pool).await?;
+
+    Ok((StatusCode::OK, Json(rule)))
+}
+
+/// Trimmed `text`, an empty one clears the field
+fn group_text(
+    text: Option<Option<String>>,
+    field: &str,
+    max_len: usize,
+) -> Result<Option<Option<String>>> {
+    let Some(text) = text else {
+        return Ok(None);
+    };
+
+    let text = text
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty());
+
+    if text
+        .as_ref()
+        .is_some_and(|text| text.chars().count() > max_len)
+    {
+        return Err(AppError::InvalidRequest(format!(
+            "{} is longer than {} characters",
+            field, max_len
+        )));
+    }
+
+    Ok(Some(text))
+}
+
+/// Tells a field sent as `null` apart from a field left out, which `default` leaves `None`
+fn present<'de, D, T>(deserializer: D) -> std::result::Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn group_text_trims_and_clears() {
+        let text = |text: &str| Some(Some(text.to_string()));
+        let cases = [
+            (None, None),
+            (Some(None), Some(None)),
+            (
+                text("  Time of day "),
+                Some(Some(String::from("Time of day"))),
+            ),
+            (text(""), Some(None)),
+            (text(" \t "), Some(None)),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                group_text(input.clone(), "icon", 16).unwrap(),
+                expected,
+                "{input:?}"
+            );
+        }
+
+        assert!(group_text(text(&"☀".repeat(17)), "icon", 16).is_err());
+        assert!(group_text(text(&"☀".repeat(16)), "icon", 16).is_ok());
+    }
+
+    #[test]
+    fn vibe_group_patch_tells_null_from_absent() {
+        let request: VibeGroupPatchRequest =
+            serde_json::from_value(json!({"display_name": null, "icon": "sun"})).unwrap();
+
+        assert_eq!(request.display_name, Some(None));
+        assert_eq!(request.icon, Some(Some(String::from("sun"))));
+        assert_eq!(request.description, None);
+        assert_eq!(request.color, None);
+
+        let unknown = serde_json::from_value::<VibeGroupPatchRequest>(json!({"name": "x"}));
+        assert!(unknown.is_err());
+    }
+}
@@ -1,41 +1,433 @@
 use crate::{
-    app::fetch::fetch_metadata_from,
+    app::{
+        api::{
+            extract::{Admin, JsonBody, Path, Query, Voter},
+            get::ResponseTrack,
+            paging::{check_page_bounds, decode_cursor, default_page, default_page_size},
+        },
+        error::{AppError, Result},
+        fetch::normalize_text,
+        services::{
+            scanner::{ScanReport, scan_all},
+            upload::{StagedFile, UploadOverrides, import_staged, store_field},
+        },
+    },
+    config::Configuration,
     database::{
         core::pool::VibingPool,
-        entities::track::{TrackFull, TrackMetadata},
+        entities::{
+            Page, Paginate,
+            track::{TrackFull, TrackPaginationParams},
+            track_vibe::{TrackVibe, VibeVote},
+            vibe::{Vibe, VibeCount},
+            vibe_rule::{NewVibeRule, RuleReport, VibeRule},
+        },
+        filter::FilterExpr,
+        sort::TrackOrder,
     },
 };
-use axum::{Json, extract::State, http::StatusCode};
+use axum::{
+    Json,
+    extract::{Multipart, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::path;
 
+/// Accepts a multipart form with a `file` field and optional `title`, `author`
+/// and `genre` fields that take precedence over the tags found in the file.
 pub async fn handle_upload_request(
     State(pool): State<VibingPool>,
-    Json(mut metadata): Json<TrackMetadata>,
-) -> Result<StatusCode, StatusCode> {
-    let default_metadata = match fetch_metadata_from(&metadata.path) {
-        Ok(metadata) => metadata,
-        Err(_) => {
-            return Err(StatusCode::NOT_FOUND);
+    mut multipart: Multipart,
+) -> Result<(StatusCode, Json<ResponseTrack>)> {
+    let config = Configuration::get();
+
+    let mut staged: Option<StagedFile> = None;
+    let mut title = None;
+    let mut author = None;
+    let mut genre = None;
+
+    while let Some(field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or_default().to_string();
+        match name.as_str() {
+            "file" => {
+                if staged.is_some() {
+                    return Err(AppError::InvalidUpload(String::from(
+                        "only one file can be uploaded at a time",
+                    )));
+                }
+
+                let resource_dir = path::Path::new(config.upload_dir());
+                staged = Some(store_field(field, resource_dir, config.max_upload_size).await?);
+            }
+            "title" | "author" | "genre" => {
+                let value = field.text().await?;
+                let value = (!value.trim().is_empty()).then(|| value.trim().to_string());
+
+                match name.as_str() {
+                    "title" => title = value,
+                    "author" => author = value,
+                    _ => genre = value,
+                }
+            }
+            _ => {}
         }
+    }
+
+    let Some(staged) = staged else {
+        return Err(AppError::InvalidUpload(String::from("missing file field")));
+    };
+
+    let overrides = UploadOverrides {
+        title,
+        author,
+        genre,
+    };
+
+    let track = import_staged(staged, overrides, &pool).await?;
+
+    Ok((StatusCode::CREATED, Json(track.into())))
+}
+
+/// Rescans every resource directory and reports what changed
+pub async fn handle_scan_request(
+    _: Admin,
+    State(pool): State<VibingPool>,
+) -> Result<(StatusCode, Json<ScanReport>)> {
+    let report = scan_all(&Configuration::get().resource_dirs, &pool, |_| {}).await?;
+
+    Ok((StatusCode::OK, Json(report)))
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SearchRequest {
+    /// Every track on disk when left out
+    #[serde(default)]
+    pub filter: FilterExpr,
+    pub order_by: Option<String>,
+    /// `next_cursor` of the previous page, takes the place of `page`
+    pub cursor: Option<String>,
+    #[serde(default = "default_page")]
+    pub page: i32,
+    #[serde(default = "default_page_size")]
+    pub size: i32,
+    /// Also count the facets of every matching track
+    #[serde(default)]
+    pub facets: bool,
+}
+
+/// Returns one page of the tracks matching the filter expression of the body
+pub async fn handle_search_request(
+    State(pool): State<VibingPool>,
+    JsonBody(search): JsonBody<SearchRequest>,
+) -> Result<(StatusCode, Json<Page<ResponseTrack>>)> {
+    check_page_bounds(search.page, search.size, None)?;
+
+    let order = TrackOrder::parse(search.order_by.as_deref(), &search.filter)?;
+    let cursor = search
+        .cursor
+        .map(|cursor| decode_cursor(&cursor, &order))
+        .transpose()?;
+
+    let params = TrackPaginationParams {
+        page_num: search.page,
+        page_size: search.size,
+        filter: search.filter,
+        order,
+        cursor,
+        limit: None,
+        facets: search.facets,
+    };
+
+    let page = TrackFull::page(&params, &pool).await?;
+
+    Ok((StatusCode::OK, Json(page.map(ResponseTrack::from))))
+}
+
+/// Longest vibe or group name
+const MAX_VIBE_NAME_LEN: usize = 64;
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct CreateVibeRequest {
+    pub name: String,
+    /// Ungrouped when left out
+    pub group_name: Option<String>,
+}
+
+pub async fn create_vibe(
+    _: Admin,
+    State(pool): State<VibingPool>,
+    JsonBody(request): JsonBody<CreateVibeRequest>,
+) -> Result<(
+    StatusCode,
+    [(header::HeaderName, String); 1],
+    Json<VibeCount>,
+)> {
+    let name = check_vibe_name(&request.name, "name")?;
+    let group_name = request
+        .group_name
+        .map(|group_name| check_vibe_name(&group_name, "group_name"))
+        .transpose()?;
+
+    let vibe = Vibe::create(&name, group_name.as_deref(), &pool).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, format!("/vibes/{}", vibe.id))],
+        Json(VibeCount {
+            id: vibe.id,
+            name: vibe.name,
+            group_name: vibe.group_name,
+            track_count: 0,
+        }),
+    ))
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct MergeVibeRequest {
+    /// The vibe that takes over the tracks
+    pub into: i32,
+}
+
+/// Moves the tracks of a vibe to another one and deletes it
+pub async fn merge_vibe(
+    _: Admin,
+    State(pool): State<VibingPool>,
+    Path(id): Path<i32>,
+    JsonBody(request): JsonBody<MergeVibeRequest>,
+) -> Result<(StatusCode, Json<VibeCount>)> {
+    if id == request.into {
+        return Err(AppError::InvalidRequest(String::from(
+            "a vibe cannot be merged into itself",
+        )));
+    }
+
+    Vibe::merge(id, request.into, &pool).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(VibeCount::get_by_id(request.into, &pool).await?),
+    ))
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct VoteRequest {
+    pub vote: VibeVote,
+}
+
+/// Votes a vibe of a track up or down, once per listener. Up votes propose a vibe the
+/// track does not carry yet, down votes take an unlocked vibe off. Answered with no
+/// content while the track does not carry the vibe.
+pub async fn vote_track_vibe(
+    State(pool): State<VibingPool>,
+    Voter(voter): Voter,
+    Path((id, vibe_id)): Path<(i32, i32)>,
+    JsonBody(request): JsonBody<VoteRequest>,
+) -> Result<Response> {
+    Ok(
+        match TrackVibe::vote(id, vibe_id, &voter, request.vote, &pool).await? {
+            Some(track_vibe) => (StatusCode::OK, Json(track_vibe)).into_response(),
+            None => StatusCode::NO_CONTENT.into_response(),
+        },
+    )
+}
+
+/// Most vibes a single rule adds
+const MAX_RULE_VIBES: usize = 32;
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CreateVibeRuleRequest {
+    pub name: String,
+    /// The tracks the rule applies to, or `query` in the search box syntax
+    pub filter: Option<FilterExpr>,
+    pub query: Option<String>,
+    pub vibes: Vec<i32>,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+/// Adds a rule, new tracks follow it at once and the catalog on the next apply
+pub async fn create_vibe_rule(
+    _: Admin,
+    State(pool): State<VibingPool>,
+    JsonBody(request): JsonBody<CreateVibeRuleRequest>,
+) -> Result<(
+    StatusCode,
+    [(header::HeaderName, String); 1],
+    Json<VibeRule>,
+)> {
+    let Some(filter) = rule_filter(request.filter, request.query)? else {
+        return Err(AppError::InvalidRequest(String::from(
+            "either filter or query is required",
+        )));
     };
 
-    if metadata.title.is_none() && default_metadata.title.is_some() {
-        metadata.title = default_metadata.title;
+    let rule = NewVibeRule {
+        name: check_vibe_name(&request.name, "name")?,
+        filter,
+        vibes: check_rule_vibes(request.vibes)?,
+        enabled: request.enabled,
+    };
+
+    let rule = VibeRule::create(rule, &pool).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, format!("/vibe-rules/{}", rule.id))],
+        Json(rule),
+    ))
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct ApplyVibeRulesQuery {
+    /// Only report what would change
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Only evaluate the rules over this track
+    pub track_id: Option<i32>,
+}
+
+/// Evaluates the enabled rules over the catalog and reports which tracks gain or lose
+/// which vibes
+pub async fn apply_vibe_rules(
+    _: Admin,
+    State(pool): State<VibingPool>,
+    Query(query): Query<ApplyVibeRulesQuery>,
+) -> Result<(StatusCode, Json<RuleReport>)> {
+    let track_ids = query.track_id.map(|id| vec![id]);
+
+    let report = VibeRule::apply(track_ids.as_deref(), query.dry_run, &pool).await?;
+
+    Ok((StatusCode::OK, Json(report)))
+}
+
+/// The filter of a rule, sent as an expression or as a query but not both
+pub fn rule_filter(
+    filter: Option<FilterExpr>,
+    query: Option<String>,
+) -> Result<Option<FilterExpr>> {
+    match (filter, query) {
+        (Some(_), Some(_)) => Err(AppError::InvalidRequest(String::from(
+            "filter and query cannot be sent together",
+        ))),
+        (Some(filter), None) => Ok(Some(filter)),
+        (None, Some(query)) => Ok(Some(FilterExpr::parse(&query)?)),
+        (None, None) => Ok(None),
+    }
+}
+
+/// The vibes of a rule without repeats, refused when there are none or too many
+pub fn check_rule_vibes(mut vibes: Vec<i32>) -> Result<Vec<i32>> {
+    vibes.sort_unstable();
+    vibes.dedup();
+
+    if vibes.is_empty() {
+        return Err(AppError::InvalidRequest(String::from(
+            "vibes cannot be empty",
+        )));
+    }
+
+    if vibes.len() > MAX_RULE_VIBES {
+        return Err(AppError::InvalidRequest(format!(
+            "a rule adds at most {} vibes",
+            MAX_RULE_VIBES
+        )));
+    }
+
+    Ok(vibes)
+}
+
+/// Trimmed and composed `text`, refused when empty or too long
+pub fn check_vibe_name(text: &str, field: &str) -> Result<String> {
+    let name = normalize_text(text.trim());
+
+    if name.is_empty() {
+        return Err(AppError::InvalidRequest(format!(
+            "{} cannot be empty",
+            field
+        )));
     }
 
-    if metadata.author.is_none() && default_metadata.author.is_some() {
-        metadata.author = default_metadata.author;
+    if name.chars().count() > MAX_VIBE_NAME_LEN {
+        return Err(AppError::InvalidRequest(format!(
+            "{} is longer than {} characters",
+            field, MAX_VIBE_NAME_LEN
+        )));
     }
 
-    if metadata.genre.is_none() && default_metadata.genre.is_some() {
-        metadata.genre = default_metadata.genre;
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_vibe_name_trims_and_composes() {
+        let cases = [
+            ("rainy", "rainy"),
+            ("  late night \n", "late night"),
+            ("m\u{0075}\u{0308}de", "müde"),
+        ];
+
+        for (text, name) in cases {
+            assert_eq!(check_vibe_name(text, "name").unwrap(), name, "{text:?}");
+        }
     }
 
-    if metadata.duration.is_none() && default_metadata.duration.is_some() {
-        metadata.duration = default_metadata.duration;
+    #[test]
+    fn check_vibe_name_refuses_empty_and_long_names() {
+        let long = "a".repeat(MAX_VIBE_NAME_LEN + 1);
+        let cases = ["", "   ", long.as_str()];
+
+        for text in cases {
+            assert!(
+                matches!(check_vibe_name(text, "name"), Err(AppError::InvalidRequest(message)) if message.starts_with("name ")),
+                "{text:?}"
+            );
+        }
+
+        let longest = "é".repeat(MAX_VIBE_NAME_LEN);
+        assert!(check_vibe_name(&longest, "name").is_ok());
+    }
+
+    #[test]
+    fn rule_filter_takes_a_filter_or_a_query() {
+        let genre = FilterExpr::Genre(String::from("jazz"));
+
+        assert_eq!(rule_filter(None, None).unwrap(), None);
+        assert_eq!(
+            rule_filter(Some(genre.clone()), None).unwrap(),
+            Some(genre.clone())
+        );
+        assert_eq!(
+            rule_filter(None, Some(String::from("genre:jazz"))).unwrap(),
+            Some(genre.clone())
+        );
+        assert!(matches!(
+            rule_filter(Some(genre), Some(String::from("genre:jazz"))),
+            Err(AppError::InvalidRequest(_))
+        ));
+        assert!(matches!(
+            rule_filter(None, Some(String::from("genre:"))),
+            Err(AppError::InvalidQuery(_))
+        ));
     }
 
-    match TrackFull::create_from(metadata, &pool).await {
-        Ok(_) => Ok(StatusCode::CREATED),
-        Err(_) => Err(StatusCode::BAD_REQUEST),
+    #[test]
+    fn check_rule_vibes_drops_repeats() {
+        assert_eq!(check_rule_vibes(vec![3, 1, 3]).unwrap(), [1, 3]);
+        assert!(check_rule_vibes(Vec::new()).is_err());
+        assert!(check_rule_vibes((0..=MAX_RULE_VIBES as i32).collect()).is_err());
     }
 }
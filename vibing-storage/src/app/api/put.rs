@@ -0,0 +1,25 @@
+use crate::{
+    app::{
+        api::extract::{Admin, Path},
+        error::Result,
+    },
+    database::{
+        core::pool::VibingPool,
+        entities::{track::TrackFull, track_vibe::TrackVibe},
+    },
+};
+use axum::{Json, extract::State, http::StatusCode};
+
+/// Curates a vibe of a track, adding it if needed, so votes never take it off
+pub async fn lock_track_vibe(
+    _: Admin,
+    State(pool): State<VibingPool>,
+    Path((id, vibe_id)): Path<(i32, i32)>,
+) -> Result<(StatusCode, Json<TrackVibe>)> {
+    TrackFull::get_by_id(id, &pool).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(TrackVibe::set_locked(id, vibe_id, true, &pool).await?),
+    ))
+}
@@ -0,0 +1,181 @@
+use crate::{
+    app::{
+        error::{AppError, Result},
+        services::tus::{self, Progress, TUS_EXTENSIONS, TUS_VERSION},
+    },
+    config::Configuration,
+    database::{
+        core::pool::VibingPool,
+        entities::upload::{Upload, UploadID},
+    },
+};
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{HeaderMap, Response, StatusCode, header, response::Builder},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+
+const TUS_RESUMABLE: &str = "Tus-Resumable";
+const UPLOAD_LENGTH: &str = "Upload-Length";
+const UPLOAD_OFFSET: &str = "Upload-Offset";
+const UPLOAD_METADATA: &str = "Upload-Metadata";
+const UPLOAD_EXPIRES: &str = "Upload-Expires";
+const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+/// Headers a browser client needs to read from tus responses
+pub const TUS_EXPOSED_HEADERS: [&str; 8] = [
+    "location",
+    "tus-resumable",
+    "tus-version",
+    "tus-extension",
+    "tus-max-size",
+    "upload-length",
+    "upload-offset",
+    "upload-expires",
+];
+
+pub async fn handle_tus_options() -> Result<impl IntoResponse> {
+    tus_response(StatusCode::NO_CONTENT)
+        .header("Tus-Version", TUS_VERSION)
+        .header("Tus-Extension", TUS_EXTENSIONS)
+        .header("Tus-Max-Size", Configuration::get().max_upload_size)
+        .body(Body::empty())
+        .map_err(|error| AppError::IoError(error.to_string()))
+}
+
+pub async fn handle_tus_creation(
+    State(pool): State<VibingPool>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    check_version(&headers)?;
+
+    let length = match header_value(&headers, UPLOAD_LENGTH).map(str::parse::<i64>) {
+        Some(Ok(length)) => length,
+        // deferred lengths are not supported
+        _ => {
+            return Err(AppError::InvalidRequest(format!(
+                "{} must be a number of bytes",
+                UPLOAD_LENGTH
+            )));
+        }
+    };
+
+    let upload = tus::create(length, header_value(&headers, UPLOAD_METADATA), &pool).await?;
+
+    tus_response(StatusCode::CREATED)
+        .header(header::LOCATION, format!("/tracks/tus/{}", upload.id))
+        .header(UPLOAD_EXPIRES, http_date(upload.expires_at))
+        .body(Body::empty())
+        .map_err(|error| AppError::IoError(error.to_string()))
+}
+
+pub async fn handle_tus_head(
+    State(pool): State<VibingPool>,
+    Path(id): Path<UploadID>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    check_version(&headers)?;
+
+    let upload = get_active_upload(id, &pool).await?;
+
+    tus_response(StatusCode::OK)
+        .header(header::CACHE_CONTROL, "no-store")
+        .header(UPLOAD_OFFSET, upload.offset)
+        .header(UPLOAD_LENGTH, upload.length)
+        .header(UPLOAD_EXPIRES, http_date(upload.expires_at))
+        .body(Body::empty())
+        .map_err(|error| AppError::IoError(error.to_string()))
+}
+
+pub async fn handle_tus_patch(
+    State(pool): State<VibingPool>,
+    Path(id): Path<UploadID>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse> {
+    check_version(&headers)?;
+
+    if header_value(&headers, header::CONTENT_TYPE.as_str()) != Some(OFFSET_CONTENT_TYPE) {
+        return Err(AppError::UnsupportedMediaType(format!(
+            "chunks must be sent as {}",
+            OFFSET_CONTENT_TYPE
+        )));
+    }
+
+    let offset = match header_value(&headers, UPLOAD_OFFSET).map(str::parse::<i64>) {
+        Some(Ok(offset)) => offset,
+        _ => {
+            return Err(AppError::InvalidRequest(format!(
+                "{} must be a number of bytes",
+                UPLOAD_OFFSET
+            )));
+        }
+    };
+
+    let upload = get_active_upload(id, &pool).await?;
+    let length = upload.length;
+
+    let response = match tus::append(upload, offset, body, &pool).await? {
+        Progress::Partial(upload) => tus_response(StatusCode::NO_CONTENT)
+            .header(UPLOAD_OFFSET, upload.offset)
+            .header(UPLOAD_EXPIRES, http_date(upload.expires_at)),
+        Progress::Finished(_) => tus_response(StatusCode::NO_CONTENT).header(UPLOAD_OFFSET, length),
+    };
+
+    response
+        .body(Body::empty())
+        .map_err(|error| AppError::IoError(error.to_string()))
+}
+
+pub async fn handle_tus_termination(
+    State(pool): State<VibingPool>,
+    Path(id): Path<UploadID>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    check_version(&headers)?;
+
+    let upload = Upload::get_by_id(id, &pool).await?;
+    tus::terminate(upload, &pool).await?;
+
+    tus_response(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .map_err(|error| AppError::IoError(error.to_string()))
+}
+
+async fn get_active_upload(id: UploadID, pool: &VibingPool) -> Result<Upload> {
+    let upload = Upload::get_by_id(id, pool).await?;
+
+    if tus::is_expired(&upload) {
+        let _ = tus::terminate(upload, pool).await;
+        return Err(AppError::Gone(format!("upload {} has expired", id)));
+    }
+
+    Ok(upload)
+}
+
+fn check_version(headers: &HeaderMap) -> Result<()> {
+    if header_value(headers, TUS_RESUMABLE) == Some(TUS_VERSION) {
+        Ok(())
+    } else {
+        Err(AppError::PreconditionFailed(format!(
+            "only {} {} is supported",
+            TUS_RESUMABLE, TUS_VERSION
+        )))
+    }
+}
+
+fn header_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn tus_response(status: StatusCode) -> Builder {
+    Response::builder()
+        .status(status)
+        .header(TUS_RESUMABLE, TUS_VERSION)
+}
+
+fn http_date(time: DateTime<Utc>) -> String {
+    httpdate::fmt_http_date(time.into())
+}
@@ -1,32 +1,240 @@
-use crate::database::error::DatabaseError;
+use crate::database::{
+    error::DatabaseError,
+    filter::query::{QueryError, Span},
+    sort::{SORT_OPTIONS, SortError},
+};
+use axum::{
+    Json,
+    extract::{
+        multipart::MultipartError,
+        rejection::{JsonRejection, PathRejection},
+    },
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::QueryRejection;
 use serde::{Deserialize, Serialize};
 use tokio::io;
 
 pub type Result<T> = std::result::Result<T, AppError>;
 
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub enum AppError {
     AudioTagError(String),
-    DatabaseError(String),
+    DatabaseError(DatabaseError),
     IoError(String),
+    UploadTooLarge,
+    UnsupportedFormat(String),
+    InvalidUpload(String),
+    UploadOffsetMismatch,
+    ScanInProgress,
+    WatchError(String),
+    NotFound(String),
+    InvalidRequest(String),
+    UnsupportedMediaType(String),
+    PreconditionFailed(String),
+    Gone(String),
+    InvalidQuery(QueryError),
+    InvalidSort(SortError),
+    /// Missing or wrong admin token
+    Unauthorized(String),
+    /// The action is turned off on this server
+    Forbidden(String),
 }
 
-impl From<audiotags::Error> for AppError {
-    fn from(error: audiotags::Error) -> Self {
-        // LOG_AUDIO_ERROR
+/// RFC 9457 problem details, `code` is the stable value clients should match on
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    pub code: String,
+    /// Where a search query is malformed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub span: Option<Span>,
+    /// What could have been sent instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<Vec<String>>,
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::AudioTagError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::DatabaseError(error) => match error {
+                DatabaseError::NotFound => StatusCode::NOT_FOUND,
+                DatabaseError::UniqueViolation(_) => StatusCode::CONFLICT,
+                DatabaseError::ForeignKeyViolation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+                DatabaseError::ExclusiveViolation(_) => StatusCode::CONFLICT,
+                DatabaseError::LockedVibe(_) => StatusCode::CONFLICT,
+                DatabaseError::QueryTimeout => StatusCode::SERVICE_UNAVAILABLE,
+                DatabaseError::DatabaseConnectionError => StatusCode::SERVICE_UNAVAILABLE,
+                DatabaseError::DatabaseError => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            AppError::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::UploadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::UnsupportedFormat(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::InvalidUpload(_) => StatusCode::BAD_REQUEST,
+            AppError::UploadOffsetMismatch => StatusCode::CONFLICT,
+            AppError::ScanInProgress => StatusCode::CONFLICT,
+            AppError::WatchError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            AppError::Gone(_) => StatusCode::GONE,
+            AppError::InvalidQuery(_) => StatusCode::BAD_REQUEST,
+            AppError::InvalidSort(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+        }
+    }
+
+    /// Machine readable code, part of the API: never rename one
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::AudioTagError(_) => "unreadable_audio",
+            AppError::DatabaseError(error) => match error {
+                DatabaseError::NotFound => "not_found",
+                DatabaseError::UniqueViolation(_) => "already_exists",
+                DatabaseError::ForeignKeyViolation(_) => "unknown_reference",
+                DatabaseError::ExclusiveViolation(_) => "exclusive_vibe_group",
+                DatabaseError::LockedVibe(_) => "locked_vibe",
+                DatabaseError::QueryTimeout => "query_timeout",
+                DatabaseError::DatabaseConnectionError => "database_unavailable",
+                DatabaseError::DatabaseError => "database_error",
+            },
+            AppError::IoError(_) => "io_error",
+            AppError::UploadTooLarge => "upload_too_large",
+            AppError::UnsupportedFormat(_) => "unsupported_format",
+            AppError::InvalidUpload(_) => "invalid_upload",
+            AppError::UploadOffsetMismatch => "upload_offset_mismatch",
+            AppError::ScanInProgress => "scan_in_progress",
+            AppError::WatchError(_) => "watch_error",
+            AppError::NotFound(_) => "not_found",
+            AppError::InvalidRequest(_) => "invalid_request",
+            AppError::UnsupportedMediaType(_) => "unsupported_media_type",
+            AppError::PreconditionFailed(_) => "precondition_failed",
+            AppError::Gone(_) => "gone",
+            AppError::InvalidQuery(_) => "invalid_query",
+            AppError::InvalidSort(_) => "invalid_sort",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+        }
+    }
 
-        match error {
-            audiotags::Error::ReadError { source } => AppError::AudioTagError(source.to_string()),
-            _ => AppError::AudioTagError(String::new()),
+    /// Human readable explanation, internal failures are not spelled out to clients
+    pub fn detail(&self) -> String {
+        match self {
+            AppError::AudioTagError(message) => format!("cannot read the audio file: {}", message),
+            AppError::DatabaseError(error) => match error {
+                DatabaseError::NotFound => String::from("the requested resource does not exist"),
+                DatabaseError::UniqueViolation(constraint) => {
+                    format!(
+                        "a resource with the same values already exists ({})",
+                        constraint
+                    )
+                }
+                DatabaseError::ForeignKeyViolation(constraint) => {
+                    format!("a referenced resource does not exist ({})", constraint)
+                }
+                DatabaseError::ExclusiveViolation(group_name) => {
+                    format!(
+                        "a track can carry a single vibe of the group {}",
+                        group_name
+                    )
+                }
+                DatabaseError::LockedVibe(name) => {
+                    format!("the vibe {} is locked on this track, unlock it first", name)
+                }
+                DatabaseError::QueryTimeout => String::from("the query took too long, try again"),
+                DatabaseError::DatabaseConnectionError => {
+                    String::from("the database is unavailable, try again later")
+                }
+                DatabaseError::DatabaseError => String::from("the database failed to answer"),
+            },
+            AppError::IoError(_) => String::from("a file could not be read or written"),
+            AppError::UploadTooLarge => String::from("the upload exceeds the size limit"),
+            AppError::UnsupportedFormat(message) | AppError::InvalidUpload(message) => {
+                message.clone()
+            }
+            AppError::UploadOffsetMismatch => {
+                String::from("the upload offset does not match the stored offset")
+            }
+            AppError::ScanInProgress => String::from("a scan is already running"),
+            AppError::WatchError(_) => String::from("the library cannot be watched"),
+            AppError::NotFound(message)
+            | AppError::InvalidRequest(message)
+            | AppError::UnsupportedMediaType(message)
+            | AppError::PreconditionFailed(message)
+            | AppError::Gone(message)
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message) => message.clone(),
+            AppError::InvalidQuery(error) => error.to_string(),
+            AppError::InvalidSort(error) => error.to_string(),
+        }
+    }
+
+    pub fn to_problem(&self) -> Problem {
+        let status = self.status();
+
+        Problem {
+            problem_type: String::from("about:blank"),
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            detail: self.detail(),
+            code: self.code().to_string(),
+            span: match self {
+                AppError::InvalidQuery(error) => Some(error.span),
+                _ => None,
+            },
+            options: match self {
+                AppError::InvalidSort(_) => Some(
+                    SORT_OPTIONS
+                        .iter()
+                        .map(|option| option.to_string())
+                        .collect(),
+                ),
+                _ => None,
+            },
         }
     }
 }
 
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let problem = self.to_problem();
+
+        if self.status().is_server_error() {
+            eprintln!("{} {}: {:?}", problem.status, problem.code, self);
+        }
+
+        (
+            self.status(),
+            [(header::CONTENT_TYPE, PROBLEM_CONTENT_TYPE)],
+            Json(problem),
+        )
+            .into_response()
+    }
+}
+
+impl From<lofty::error::LoftyError> for AppError {
+    fn from(error: lofty::error::LoftyError) -> Self {
+        // LOG_AUDIO_ERROR
+
+        AppError::AudioTagError(error.to_string())
+    }
+}
+
 impl From<DatabaseError> for AppError {
-    fn from(_error: DatabaseError) -> Self {
+    fn from(error: DatabaseError) -> Self {
         // LOG_DATABASE_ERROR
 
-        AppError::DatabaseError(String::from(""))
+        AppError::DatabaseError(error)
     }
 }
 
@@ -37,3 +245,186 @@ impl From<io::Error> for AppError {
         AppError::IoError(error.to_string())
     }
 }
+
+impl From<MultipartError> for AppError {
+    fn from(error: MultipartError) -> Self {
+        // LOG_UPLOAD_ERROR
+
+        if error.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            AppError::UploadTooLarge
+        } else {
+            AppError::InvalidUpload(error.body_text())
+        }
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        AppError::InvalidRequest(rejection.body_text())
+    }
+}
+
+impl From<QueryError> for AppError {
+    fn from(error: QueryError) -> Self {
+        AppError::InvalidQuery(error)
+    }
+}
+
+impl From<PathRejection> for AppError {
+    fn from(rejection: PathRejection) -> Self {
+        AppError::InvalidRequest(rejection.body_text())
+    }
+}
+
+impl From<SortError> for AppError {
+    fn from(error: SortError) -> Self {
+        AppError::InvalidSort(error)
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection {
+            JsonRejection::MissingJsonContentType(_) => {
+                AppError::UnsupportedMediaType(rejection.body_text())
+            }
+            _ => AppError::InvalidRequest(rejection.body_text()),
+        }
+    }
+}
+
+impl From<notify_debouncer_full::notify::Error> for AppError {
+    fn from(error: notify_debouncer_full::notify::Error) -> Self {
+        // LOG_WATCH_ERROR
+
+        AppError::WatchError(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_map_to_a_status_and_a_stable_code() {
+        let cases = [
+            (
+                AppError::DatabaseError(DatabaseError::NotFound),
+                StatusCode::NOT_FOUND,
+                "not_found",
+            ),
+            (
+                AppError::DatabaseError(DatabaseError::UniqueViolation(String::from("vibe_name"))),
+                StatusCode::CONFLICT,
+                "already_exists",
+            ),
+            (
+                AppError::DatabaseError(DatabaseError::ExclusiveViolation(String::from("mood"))),
+                StatusCode::CONFLICT,
+                "exclusive_vibe_group",
+            ),
+            (
+                AppError::DatabaseError(DatabaseError::QueryTimeout),
+                StatusCode::SERVICE_UNAVAILABLE,
+                "query_timeout",
+            ),
+            (
+                AppError::IoError(String::from("disk full")),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "io_error",
+            ),
+            (
+                AppError::UploadTooLarge,
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "upload_too_large",
+            ),
+            (
+                AppError::UploadOffsetMismatch,
+                StatusCode::CONFLICT,
+                "upload_offset_mismatch",
+            ),
+            (
+                AppError::NotFound(String::from("no such track")),
+                StatusCode::NOT_FOUND,
+                "not_found",
+            ),
+            (
+                AppError::Unauthorized(String::from("wrong token")),
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+            ),
+        ];
+
+        for (error, status, code) in cases {
+            assert_eq!(error.status(), status, "{error:?}");
+            assert_eq!(error.code(), code, "{error:?}");
+        }
+    }
+
+    #[test]
+    fn to_problem_describes_the_error() {
+        let problem =
+            AppError::DatabaseError(DatabaseError::LockedVibe(String::from("calm"))).to_problem();
+
+        assert_eq!(
+            problem,
+            Problem {
+                problem_type: String::from("about:blank"),
+                title: String::from("Conflict"),
+                status: 409,
+                detail: String::from("the vibe calm is locked on this track, unlock it first"),
+                code: String::from("locked_vibe"),
+                span: None,
+                options: None,
+            }
+        );
+    }
+
+    #[test]
+    fn to_problem_hides_internal_failures() {
+        let problem = AppError::IoError(String::from("/srv/music/secret.mp3: denied")).to_problem();
+
+        assert!(!problem.detail.contains("secret"), "{problem:?}");
+    }
+
+    #[test]
+    fn to_problem_points_at_the_malformed_query() {
+        let span = Span { start: 4, end: 9 };
+        let problem = AppError::InvalidQuery(QueryError {
+            message: String::from("unclosed quote"),
+            span,
+        })
+        .to_problem();
+
+        assert_eq!(problem.span, Some(span));
+        assert_eq!(problem.options, None);
+    }
+
+    #[test]
+    fn to_problem_lists_the_sort_options() {
+        let problem = AppError::InvalidSort(SortError {
+            message: String::from("unknown sort key loudness"),
+        })
+        .to_problem();
+
+        assert_eq!(
+            problem.options.as_deref().map(<[String]>::len),
+            Some(SORT_OPTIONS.len())
+        );
+        assert!(
+            problem.detail.starts_with("unknown sort key loudness"),
+            "{problem:?}"
+        );
+    }
+
+    #[test]
+    fn into_response_sends_problem_json() {
+        let response = AppError::ScanInProgress.into_response();
+
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            PROBLEM_CONTENT_TYPE
+        );
+    }
+}
@@ -1,47 +1,233 @@
-use crate::{app::error::Result, database::entities::track::TrackMetadata};
-use std::fs;
+use crate::{
+    app::{
+        error::{AppError, Result},
+        format::{AudioFormat, SNIFF_LEN},
+    },
+    database::entities::track::TrackMetadata,
+};
+use chrono::{DateTime, Utc};
+use lofty::{
+    config::ParseOptions,
+    file::{AudioFile, TaggedFileExt},
+    mp4::{Mp4Codec, Mp4File},
+    probe::Probe,
+    tag::Accessor,
+};
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::Path,
+};
+use symphonia::core::{
+    formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions, probe::Hint,
+};
+use unicode_normalization::UnicodeNormalization;
 
 pub fn fetch_metadata_from(path: &str) -> Result<TrackMetadata> {
-    let tag = audiotags::Tag::new().read_from_path(path)?;
+    let format = sniff_format_of(Path::new(path))?
+        .ok_or_else(|| AppError::UnsupportedFormat(format!("{} is not audio", path)))?;
 
-    let duration = if let Some(dur) = tag.duration() {
-        Some(dur as i32)
+    let tagged_file = Probe::open(path)?.guess_file_type()?.read()?;
+    let tag = tagged_file
+        .primary_tag()
+        .or_else(|| tagged_file.first_tag());
+
+    let properties = tagged_file.properties();
+    let file_metadata = fs::metadata(path)?;
+    let file_size = file_metadata.len();
+
+    // mp3 headers only give an estimate unless the encoder wrote a Xing/VBRI frame,
+    // so walk its frames instead. Other containers store an exact length.
+    let header_duration = properties.duration().as_secs_f64();
+    let seconds = if format == AudioFormat::Mp3 || header_duration <= 0.0 {
+        scan_duration(path, format).unwrap_or(header_duration)
+    } else {
+        header_duration
+    };
+
+    let duration = if seconds > 0.0 {
+        Some(seconds.round() as i32)
     } else {
         None
     };
 
+    // fall back to the average over the whole file, tags included
+    let bitrate = properties
+        .audio_bitrate()
+        .or(properties.overall_bitrate())
+        .filter(|bitrate| *bitrate > 0)
+        .map(|bitrate| bitrate as i32)
+        .or_else(|| (seconds > 0.0).then(|| (file_size as f64 * 8.0 / seconds / 1000.0) as i32));
+
     Ok(TrackMetadata {
         path: path.to_string(),
-        title: tag.title().map(String::from),
-        author: tag.artist().map(String::from),
-        genre: tag.genre().map(String::from),
+        title: tag
+            .and_then(|tag| tag.title())
+            .map(|title| normalize_text(&title)),
+        author: tag
+            .and_then(|tag| tag.artist())
+            .map(|author| normalize_text(&author)),
+        genre: tag
+            .and_then(|tag| tag.genre())
+            .map(|genre| normalize_text(&genre)),
         duration,
+        codec: Some(codec_of(path, format)),
+        container: Some(format.container().to_string()),
+        bitrate,
+        sample_rate: properties.sample_rate().map(|rate| rate as i32),
+        channels: properties.channels().map(i16::from),
+        bit_depth: properties.bit_depth().map(i16::from),
+        file_size: Some(file_size as i64),
+        modified_at: file_metadata.modified().ok().map(DateTime::<Utc>::from),
     })
 }
 
-pub fn fetch_resource_from(path: &str) -> Result<Vec<TrackMetadata>> {
-    let mut metadata_vec = Vec::new();
+/// Composes `text` into NFC, tags written on macOS often come decomposed and would
+/// neither match nor sort like the same text typed by hand
+pub fn normalize_text(text: &str) -> String {
+    text.nfc().collect()
+}
+
+/// Recognizes the audio format from the content of the file rather than its name
+pub fn sniff_format_of(path: &Path) -> Result<Option<AudioFormat>> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    File::open(path)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)?;
+
+    Ok(AudioFormat::sniff(&head))
+}
+
+/// Adds up the duration of every packet of the default track, without decoding them
+fn scan_duration(path: &str, format: AudioFormat) -> Option<f64> {
+    let file = File::open(path).ok()?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    hint.with_extension(format.extensions()[0]);
 
-    for entry in fs::read_dir(path)? {
-        let entry = entry?;
-        let path = entry.path();
+    let mut reader = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .ok()?
+        .format;
 
-        if !path.is_file() {
-            continue;
+    let track = reader.default_track()?;
+    let track_id = track.id;
+    let time_base = track.codec_params.time_base?;
+
+    let mut timestamps: u64 = 0;
+    while let Ok(packet) = reader.next_packet() {
+        if packet.track_id() == track_id {
+            timestamps += packet.dur();
         }
+    }
+
+    let time = time_base.calc_time(timestamps);
+
+    Some(time.seconds as f64 + time.frac)
+}
+
+/// The mp4 container can carry several codecs, every other format has a single one
+fn codec_of(path: &str, format: AudioFormat) -> String {
+    if format == AudioFormat::Mp4 {
+        let codec = File::open(path)
+            .ok()
+            .and_then(|mut file| Mp4File::read_from(&mut file, ParseOptions::new()).ok())
+            .map(|mp4| *mp4.properties().codec());
 
-        if let Some(extension) = path.extension() {
-            if extension != "mp3" {
-                continue;
-            }
+        match codec {
+            Some(Mp4Codec::ALAC) => return String::from("alac"),
+            Some(Mp4Codec::MP3) => return String::from("mp3"),
+            Some(Mp4Codec::FLAC) => return String::from("flac"),
+            _ => {}
         }
+    }
+
+    format.default_codec().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A silent 16 bit PCM wav file
+    fn wav(sample_rate: u32, channels: u16, seconds: u32) -> Vec<u8> {
+        let block_align = channels * 2;
+        let data_len = sample_rate * seconds * block_align as u32;
+
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&channels.to_le_bytes());
+        wav.extend_from_slice(&sample_rate.to_le_bytes());
+        wav.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        wav.extend_from_slice(&block_align.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        wav.resize(wav.len() + data_len as usize, 0);
+        wav
+    }
+
+    #[test]
+    fn fetch_metadata_from_reads_the_technical_properties() {
+        let cases = [(8000, 1, 2, 128), (44100, 2, 3, 1411), (48000, 2, 1, 1536)];
+
+        for (sample_rate, channels, seconds, bitrate) in cases {
+            let path = std::env::temp_dir().join(format!(
+                "vibing-fetch-{}-{}-{}.wav",
+                std::process::id(),
+                sample_rate,
+                channels
+            ));
+            fs::write(&path, wav(sample_rate, channels, seconds)).unwrap();
 
-        if let Some(path_str) = path.to_str() {
-            if let Ok(metadata) = fetch_metadata_from(path_str) {
-                metadata_vec.push(metadata);
-            }
+            let metadata = fetch_metadata_from(path.to_str().unwrap());
+            fs::remove_file(&path).unwrap();
+            let metadata = metadata.unwrap();
+
+            assert_eq!(metadata.duration, Some(seconds as i32), "{sample_rate} Hz");
+            assert_eq!(metadata.codec.as_deref(), Some("pcm"));
+            assert_eq!(metadata.container.as_deref(), Some("wav"));
+            assert_eq!(metadata.bitrate, Some(bitrate), "{sample_rate} Hz");
+            assert_eq!(metadata.sample_rate, Some(sample_rate as i32));
+            assert_eq!(metadata.channels, Some(channels as i16));
+            assert_eq!(metadata.bit_depth, Some(16));
+            assert_eq!(metadata.title, None);
         }
     }
 
-    Ok(metadata_vec)
+    #[test]
+    fn fetch_metadata_from_refuses_files_that_are_not_audio() {
+        let path = std::env::temp_dir().join(format!("vibing-fetch-{}.mp3", std::process::id()));
+        fs::write(&path, b"<!DOCTYPE html><html></html>").unwrap();
+
+        let metadata = fetch_metadata_from(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(metadata, Err(AppError::UnsupportedFormat(_))));
+    }
+
+    #[test]
+    fn normalize_text_composes_diacritics() {
+        let cases = [
+            // e + combining circumflex + combining dot below
+            ("Ng\u{0065}\u{0302}\u{0323}", "Ngệ"),
+            // o + combining horn + combining acute
+            ("S\u{006f}\u{031b}\u{0301}n", "Sớn"),
+            ("Sơn Tùng", "Sơn Tùng"),
+            ("plain", "plain"),
+        ];
+
+        for (text, normalized) in cases {
+            assert_eq!(normalize_text(text), normalized, "{text:?}");
+        }
+    }
 }
@@ -0,0 +1,201 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Number of leading bytes needed to recognize every supported format
+pub const SNIFF_LEN: usize = 64;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AudioFormat {
+    Mp3,
+    Flac,
+    OggVorbis,
+    Opus,
+    Mp4,
+    Wav,
+}
+
+impl AudioFormat {
+    pub const ALL: [AudioFormat; 6] = [
+        AudioFormat::Mp3,
+        AudioFormat::Flac,
+        AudioFormat::OggVorbis,
+        AudioFormat::Opus,
+        AudioFormat::Mp4,
+        AudioFormat::Wav,
+    ];
+
+    /// Recognizes the format from the first bytes of a file
+    pub fn sniff(head: &[u8]) -> Option<AudioFormat> {
+        if head.starts_with(b"ID3") {
+            return Some(AudioFormat::Mp3);
+        }
+
+        // MPEG audio frame sync, the layer bits are never 00 for mp3 (that would be ADTS)
+        if head.len() >= 2 && head[0] == 0xFF && head[1] & 0xE0 == 0xE0 && head[1] & 0x06 != 0 {
+            return Some(AudioFormat::Mp3);
+        }
+
+        if head.starts_with(b"fLaC") {
+            return Some(AudioFormat::Flac);
+        }
+
+        if head.starts_with(b"OggS") {
+            // the first packet starts right after the 27 byte page header and its segment table
+            let segments = *head.get(26)? as usize;
+            let packet = head.get(27 + segments..)?;
+
+            if packet.starts_with(b"\x01vorbis") {
+                return Some(AudioFormat::OggVorbis);
+            }
+            if packet.starts_with(b"OpusHead") {
+                return Some(AudioFormat::Opus);
+            }
+            return None;
+        }
+
+        if head.get(4..8) == Some(b"ftyp") {
+            return Some(AudioFormat::Mp4);
+        }
+
+        if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WAVE") {
+            return Some(AudioFormat::Wav);
+        }
+
+        None
+    }
+
+    pub fn from_extension(extension: &str) -> Option<AudioFormat> {
+        let extension = extension.to_lowercase();
+
+        AudioFormat::ALL
+            .into_iter()
+            .find(|format| format.extensions().contains(&extension.as_str()))
+    }
+
+    pub fn from_path(path: &Path) -> Option<AudioFormat> {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(AudioFormat::from_extension)
+    }
+
+    /// File extensions this format is stored under, the first one being the usual one
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            AudioFormat::Mp3 => &["mp3"],
+            AudioFormat::Flac => &["flac"],
+            AudioFormat::OggVorbis => &["ogg", "oga"],
+            AudioFormat::Opus => &["opus", "ogg"],
+            AudioFormat::Mp4 => &["m4a", "mp4", "m4b"],
+            AudioFormat::Wav => &["wav"],
+        }
+    }
+
+    /// Whether content of this format may be stored under `extension`
+    pub fn matches_extension(&self, extension: &str) -> bool {
+        self.extensions()
+            .contains(&extension.to_lowercase().as_str())
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "audio/mpeg",
+            AudioFormat::Flac => "audio/flac",
+            AudioFormat::OggVorbis => "audio/ogg",
+            AudioFormat::Opus => "audio/ogg; codecs=opus",
+            AudioFormat::Mp4 => "audio/mp4",
+            AudioFormat::Wav => "audio/wav",
+        }
+    }
+
+    pub fn container(&self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "mpeg",
+            AudioFormat::Flac => "flac",
+            AudioFormat::OggVorbis | AudioFormat::Opus => "ogg",
+            AudioFormat::Mp4 => "mp4",
+            AudioFormat::Wav => "wav",
+        }
+    }
+
+    /// The codec usually found in this container, mp4 may also carry alac
+    pub fn default_codec(&self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::Flac => "flac",
+            AudioFormat::OggVorbis => "vorbis",
+            AudioFormat::Opus => "opus",
+            AudioFormat::Mp4 => "aac",
+            AudioFormat::Wav => "pcm",
+        }
+    }
+}
+
+pub fn is_supported_extension(extension: &str) -> bool {
+    AudioFormat::from_extension(extension).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An Ogg page header with one segment, followed by the start of its first packet
+    fn ogg_page(packet: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS".to_vec();
+        page.resize(26, 0);
+        page.extend_from_slice(&[1, 30]);
+        page.extend_from_slice(packet);
+        page
+    }
+
+    #[test]
+    fn sniff_recognizes_the_supported_formats() {
+        let cases: [(Vec<u8>, Option<AudioFormat>); 14] = [
+            (b"ID3\x04\x00\x00".to_vec(), Some(AudioFormat::Mp3)),
+            (vec![0xFF, 0xFB, 0x90, 0x64], Some(AudioFormat::Mp3)),
+            (vec![0xFF, 0xF3, 0x48, 0xC4], Some(AudioFormat::Mp3)),
+            // ADTS aac has the layer bits set to 00
+            (vec![0xFF, 0xF1, 0x50, 0x80], None),
+            (b"fLaC\x00\x00\x00\x22".to_vec(), Some(AudioFormat::Flac)),
+            (
+                ogg_page(b"\x01vorbis\x00\x00"),
+                Some(AudioFormat::OggVorbis),
+            ),
+            (ogg_page(b"OpusHead\x01\x02"), Some(AudioFormat::Opus)),
+            (ogg_page(b"\x7fFLAC\x01\x00"), None),
+            // the segment table is cut off
+            (b"OggS\x00\x02".to_vec(), None),
+            (b"\x00\x00\x00\x20ftypM4A ".to_vec(), Some(AudioFormat::Mp4)),
+            (
+                b"RIFF\x24\x08\x00\x00WAVEfmt ".to_vec(),
+                Some(AudioFormat::Wav),
+            ),
+            (b"RIFF\x24\x08\x00\x00AVI LIST".to_vec(), None),
+            (b"<!DOCTYPE html>".to_vec(), None),
+            (Vec::new(), None),
+        ];
+
+        for (head, expected) in cases {
+            assert_eq!(AudioFormat::sniff(&head), expected, "{head:02x?}");
+        }
+    }
+
+    #[test]
+    fn extensions_are_matched_ignoring_case() {
+        let cases = [
+            (AudioFormat::Mp3, "MP3", true),
+            (AudioFormat::OggVorbis, "oga", true),
+            (AudioFormat::Opus, "ogg", true),
+            (AudioFormat::Mp4, "M4B", true),
+            (AudioFormat::Flac, "mp3", false),
+            (AudioFormat::Wav, "", false),
+        ];
+
+        for (format, extension, expected) in cases {
+            assert_eq!(
+                format.matches_extension(extension),
+                expected,
+                "{format:?} as {extension:?}"
+            );
+        }
+    }
+}
@@ -1,3 +1,9 @@
 pub mod download;
+pub mod moment;
+pub mod scanner;
 pub mod stream_music;
+pub mod suggest;
+pub mod tus;
 pub mod upload;
+pub mod watcher;
+pub mod weather;
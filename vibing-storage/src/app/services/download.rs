@@ -1,11 +1,19 @@
-use crate::app::error::Result;
-use std::path::Path;
-use tokio::fs::File;
+use crate::app::{
+    error::Result,
+    format::{AudioFormat, SNIFF_LEN},
+};
+use std::{io::SeekFrom, path::Path, time::SystemTime};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
 
 #[derive(Debug)]
 pub struct DownloadableFile {
     pub name: String,
     pub content_type: String,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
     pub file: File,
 }
 
@@ -17,21 +25,51 @@ impl DownloadableFile {
             .unwrap_or("unknown")
             .to_string();
 
-        let content_type = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
-            Some("txt") => String::from("text/plain"),
-            Some("pdf") => String::from("application/pdf"),
-            Some("jpg") | Some("jpeg") => String::from("image/jpeg"),
-            Some("png") => String::from("image/png"),
-            Some("mp3") => String::from("audio/mpeg"),
+        let mut file = File::open(path).await?;
+        let metadata = file.metadata().await?;
+
+        // trust the content over the extension, files are often misnamed
+        let mut head = Vec::with_capacity(SNIFF_LEN);
+        (&mut file)
+            .take(SNIFF_LEN as u64)
+            .read_to_end(&mut head)
+            .await?;
+        file.seek(SeekFrom::Start(0)).await?;
+
+        let format = AudioFormat::sniff(&head).or_else(|| AudioFormat::from_path(Path::new(path)));
+
+        let content_type = match (
+            format,
+            Path::new(path).extension().and_then(|ext| ext.to_str()),
+        ) {
+            (Some(format), _) => String::from(format.mime_type()),
+            (None, Some("txt")) => String::from("text/plain"),
+            (None, Some("pdf")) => String::from("application/pdf"),
+            (None, Some("jpg")) | (None, Some("jpeg")) => String::from("image/jpeg"),
+            (None, Some("png")) => String::from("image/png"),
             _ => String::from("application/octet-stream"), // Mặc định cho các file không xác định
         };
 
-        let file = File::open(path).await?;
-
         Ok(Self {
             name,
             content_type,
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
             file,
         })
     }
+
+    /// Strong validator derived from the file size and modification time
+    pub fn etag(&self) -> String {
+        let modified = self
+            .modified
+            .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map_or(0, |duration| duration.as_secs());
+
+        format!("\"{:x}-{:x}\"", self.size, modified)
+    }
+
+    pub fn last_modified(&self) -> Option<String> {
+        self.modified.map(httpdate::fmt_http_date)
+    }
 }
@@ -0,0 +1,167 @@
+use chrono::{DateTime, Datelike, FixedOffset, Timelike, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Hemisphere {
+    #[default]
+    North,
+    South,
+}
+
+/// The local time of a client and the `seasonal` and `daytime` vibes it falls in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Moment {
+    pub local_time: DateTime<FixedOffset>,
+    pub hemisphere: Hemisphere,
+}
+
+impl Moment {
+    /// `timezone` is an IANA name such as `Asia/Ho_Chi_Minh` or an offset such as `+07:00`
+    pub fn at(now: DateTime<Utc>, timezone: &str, hemisphere: Hemisphere) -> Option<Moment> {
+        let local_time = match Tz::from_str(timezone) {
+            Ok(tz) => now.with_timezone(&tz).fixed_offset(),
+            Err(_) => now.with_timezone(&FixedOffset::from_str(timezone).ok()?),
+        };
+
+        Some(Moment {
+            local_time,
+            hemisphere,
+        })
+    }
+
+    /// Meteorological seasons, whole months flipped by six in the southern hemisphere
+    pub fn season(&self) -> &'static str {
+        let month = match self.hemisphere {
+            Hemisphere::North => self.local_time.month(),
+            Hemisphere::South => (self.local_time.month() + 5) % 12 + 1,
+        };
+
+        match month {
+            3..=5 => "spring",
+            6..=8 => "summer",
+            9..=11 => "autumn",
+            _ => "winter",
+        }
+    }
+
+    pub fn daytime(&self) -> &'static str {
+        match self.local_time.hour() {
+            5..=6 => "dawn",
+            7..=10 => "morning",
+            11..=13 => "noon",
+            14..=16 => "afternoon",
+            17..=18 => "dusk",
+            19..=21 => "evening",
+            _ => "night",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn at_places_the_client_in_its_season_and_daytime() {
+        let cases = [
+            (
+                "2025-06-15T12:00:00Z",
+                "Asia/Ho_Chi_Minh",
+                Hemisphere::North,
+                "2025-06-15T19:00:00+07:00",
+                "summer",
+                "evening",
+            ),
+            (
+                "2025-06-15T12:00:00Z",
+                "+07:00",
+                Hemisphere::South,
+                "2025-06-15T19:00:00+07:00",
+                "winter",
+                "evening",
+            ),
+            (
+                "2025-12-31T20:30:00Z",
+                "Asia/Tokyo",
+                Hemisphere::North,
+                "2026-01-01T05:30:00+09:00",
+                "winter",
+                "dawn",
+            ),
+            (
+                "2025-03-01T03:00:00Z",
+                "America/New_York",
+                Hemisphere::South,
+                "2025-02-28T22:00:00-05:00",
+                "summer",
+                "night",
+            ),
+            // daylight saving time
+            (
+                "2025-07-01T16:00:00Z",
+                "America/New_York",
+                Hemisphere::North,
+                "2025-07-01T12:00:00-04:00",
+                "summer",
+                "noon",
+            ),
+            (
+                "2025-09-10T08:00:00Z",
+                "-03:30",
+                Hemisphere::South,
+                "2025-09-10T04:30:00-03:30",
+                "spring",
+                "night",
+            ),
+            (
+                "2025-04-10T15:00:00Z",
+                "UTC",
+                Hemisphere::North,
+                "2025-04-10T15:00:00+00:00",
+                "spring",
+                "afternoon",
+            ),
+            (
+                "2025-11-20T17:45:00Z",
+                "Europe/Paris",
+                Hemisphere::North,
+                "2025-11-20T18:45:00+01:00",
+                "autumn",
+                "dusk",
+            ),
+            (
+                "2025-05-05T10:00:00Z",
+                "+00:00",
+                Hemisphere::North,
+                "2025-05-05T10:00:00+00:00",
+                "spring",
+                "morning",
+            ),
+        ];
+
+        for (now, timezone, hemisphere, local_time, season, daytime) in cases {
+            let moment = Moment::at(now.parse().unwrap(), timezone, hemisphere)
+                .unwrap_or_else(|| panic!("{timezone:?}"));
+
+            assert_eq!(moment.local_time.to_rfc3339(), local_time, "{timezone:?}");
+            assert_eq!(moment.season(), season, "{now} in {timezone:?}");
+            assert_eq!(moment.daytime(), daytime, "{now} in {timezone:?}");
+        }
+    }
+
+    #[test]
+    fn at_refuses_unknown_timezones() {
+        let now = "2025-06-15T12:00:00Z".parse().unwrap();
+
+        for timezone in ["", "Mars/Olympus", "07:00", "+25:00", "GMT+7x"] {
+            assert_eq!(
+                Moment::at(now, timezone, Hemisphere::North),
+                None,
+                "{timezone:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,377 @@
+use crate::{
+    app::{
+        error::{AppError, Result},
+        fetch::fetch_metadata_from,
+        format::AudioFormat,
+    },
+    database::{
+        core::pool::VibingPool,
+        entities::track::{TrackFileState, TrackFull, TrackFullPatch, TrackMetadata},
+    },
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use tokio::sync::Mutex;
+
+/// Only one scan may run at a time, a second one would race on the same rows
+static SCAN_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// How often the progress callback is invoked, in scanned files
+const PROGRESS_INTERVAL: usize = 100;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct ScanReport {
+    pub scanned: usize,
+    pub added: usize,
+    pub updated: usize,
+    pub missing: usize,
+    pub errors: usize,
+}
+
+/// What happened to a single file when it was synchronized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileChange {
+    Added,
+    Updated,
+    Unchanged,
+}
+
+#[derive(Debug, Clone)]
+struct FileEntry {
+    path: String,
+    size: i64,
+    modified_at: Option<DateTime<Utc>>,
+}
+
+/// Walks `dir` recursively and brings the `tracks` rows below it in line with the disk:
+/// new files are added, changed files are re-read and vanished files are flagged missing.
+pub async fn scan<F>(dir: &str, pool: &VibingPool, mut on_progress: F) -> Result<ScanReport>
+where
+    F: FnMut(&ScanReport),
+{
+    let Ok(_guard) = SCAN_LOCK.try_lock() else {
+        return Err(AppError::ScanInProgress);
+    };
+
+    let root = PathBuf::from(dir);
+    let (files, walk_errors) = tokio::task::spawn_blocking(move || walk(&root))
+        .await
+        .map_err(|error| AppError::IoError(error.to_string()))?;
+
+    let mut report = ScanReport {
+        errors: walk_errors,
+        ..Default::default()
+    };
+
+    let prefix = format!("{}/", dir.trim_end_matches('/'));
+    let mut known: HashMap<String, TrackFileState> = TrackFull::get_file_states(&prefix, pool)
+        .await?
+        .into_iter()
+        .map(|state| (state.path.clone(), state))
+        .collect();
+
+    for file in files {
+        report.scanned += 1;
+
+        match known.remove(&file.path) {
+            None => match read_metadata(&file.path).await {
+                Ok(metadata) => match TrackFull::create_from(metadata, pool).await {
+                    Ok(_) => report.added += 1,
+                    Err(error) => {
+                        eprintln!("cannot add {}: {:?}", file.path, error);
+                        report.errors += 1;
+                    }
+                },
+                Err(error) => {
+                    eprintln!("cannot read {}: {:?}", file.path, error);
+                    report.errors += 1;
+                }
+            },
+            Some(state) if has_changed(&state, &file) => {
+                match refresh(state.id, &file.path, pool).await {
+                    Ok(()) => report.updated += 1,
+                    Err(error) => {
+                        eprintln!("cannot update {}: {:?}", file.path, error);
+                        report.errors += 1;
+                    }
+                }
+            }
+            Some(_) => {}
+        }
+
+        if report.scanned.is_multiple_of(PROGRESS_INTERVAL) {
+            on_progress(&report);
+        }
+    }
+
+    // whatever is left was not found on disk anymore
+    for state in known.into_values().filter(|state| !state.missing) {
+        let patch = TrackFullPatch {
+            missing: Some(true),
+            ..Default::default()
+        };
+
+        let result = match TrackFull::get_by_id(state.id, pool).await {
+            Ok(track) => track.apply_patch(patch, pool).await.map(|_| ()),
+            Err(error) => Err(error),
+        };
+
+        match result {
+            Ok(()) => report.missing += 1,
+            Err(error) => {
+                eprintln!("cannot flag {} as missing: {:?}", state.path, error);
+                report.errors += 1;
+            }
+        }
+    }
+
+    on_progress(&report);
+
+    Ok(report)
+}
+
+/// Scans every directory in turn and adds up their reports
+pub async fn scan_all<F>(
+    dirs: &[String],
+    pool: &VibingPool,
+    mut on_progress: F,
+) -> Result<ScanReport>
+where
+    F: FnMut(&ScanReport),
+{
+    let mut total = ScanReport::default();
+
+    for dir in dirs {
+        let report = scan(dir, pool, |report| {
+            on_progress(&ScanReport {
+                scanned: total.scanned + report.scanned,
+                added: total.added + report.added,
+                updated: total.updated + report.updated,
+                missing: total.missing + report.missing,
+                errors: total.errors + report.errors,
+            })
+        })
+        .await?;
+
+        total.scanned += report.scanned;
+        total.added += report.added;
+        total.updated += report.updated;
+        total.missing += report.missing;
+        total.errors += report.errors;
+    }
+
+    Ok(total)
+}
+
+/// Adds the file at `path` to the catalog, or refreshes its track if the file changed
+pub async fn sync_file(path: &str, pool: &VibingPool) -> Result<FileChange> {
+    let metadata = tokio::fs::metadata(path).await?;
+    let file = FileEntry {
+        path: path.to_string(),
+        size: metadata.len() as i64,
+        modified_at: metadata.modified().ok().map(DateTime::<Utc>::from),
+    };
+
+    match TrackFull::get_by_path(path, pool).await? {
+        Some(track) if has_changed(&TrackFileState::from(&track.track), &file) => {
+            let metadata = read_metadata(path).await?;
+            track.apply_patch(metadata.into(), pool).await?;
+            Ok(FileChange::Updated)
+        }
+        Some(_) => Ok(FileChange::Unchanged),
+        None => {
+            let metadata = read_metadata(path).await?;
+            TrackFull::create_from(metadata, pool).await?;
+            Ok(FileChange::Added)
+        }
+    }
+}
+
+/// Re-reads the file of an existing track and applies whatever changed
+async fn refresh(id: i32, path: &str, pool: &VibingPool) -> Result<()> {
+    let metadata = read_metadata(path).await?;
+    let track = TrackFull::get_by_id(id, pool).await?;
+    track.apply_patch(metadata.into(), pool).await?;
+
+    Ok(())
+}
+
+/// Tag reading and duration probing block, keep them off the async workers
+async fn read_metadata(path: &str) -> Result<TrackMetadata> {
+    let path = path.to_string();
+
+    tokio::task::spawn_blocking(move || fetch_metadata_from(&path))
+        .await
+        .map_err(|error| AppError::IoError(error.to_string()))?
+}
+
+fn has_changed(state: &TrackFileState, file: &FileEntry) -> bool {
+    // the database keeps microseconds only
+    let stored = state.modified_at.map(|time| time.timestamp_micros());
+    let current = file.modified_at.map(|time| time.timestamp_micros());
+
+    state.missing || state.file_size != Some(file.size) || stored != current
+}
+
+/// Collects every supported audio file below `root`, skipping hidden entries such as
+/// in-progress uploads. Returns the files and the number of entries that could not be read.
+fn walk(root: &Path) -> (Vec<FileEntry>, usize) {
+    let mut files = Vec::new();
+    let mut errors = 0;
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(error) => {
+                eprintln!("cannot read {}: {}", dir.display(), error);
+                errors += 1;
+                continue;
+            }
+        };
+
+        for entry in entries {
+            let Ok(entry) = entry else {
+                errors += 1;
+                continue;
+            };
+
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                errors += 1;
+                continue;
+            };
+
+            // symlinked directories are not followed so a loop cannot trap the walk
+            if file_type.is_dir() {
+                pending.push(path);
+                continue;
+            }
+
+            if AudioFormat::from_path(&path).is_none() {
+                continue;
+            }
+
+            let (Some(path_str), Ok(metadata)) = (path.to_str(), fs::metadata(&path)) else {
+                errors += 1;
+                continue;
+            };
+
+            if metadata.is_file() {
+                files.push(FileEntry {
+                    path: path_str.to_string(),
+                    size: metadata.len() as i64,
+                    modified_at: metadata.modified().ok().map(DateTime::<Utc>::from),
+                });
+            }
+        }
+    }
+
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    (files, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn has_changed_compares_size_time_and_presence() {
+        let modified_at = DateTime::from_timestamp_micros(1_760_000_000_123_456);
+        let later = DateTime::from_timestamp_micros(1_760_000_100_000_000);
+        // what the file system reports beyond the microseconds the database keeps
+        let nanos_apart = modified_at.map(|time| time + chrono::TimeDelta::nanoseconds(789));
+
+        let state = |file_size, modified_at, missing| TrackFileState {
+            id: 1,
+            path: String::from("/music/song.mp3"),
+            file_size,
+            modified_at,
+            missing,
+        };
+        let file = |size, modified_at| FileEntry {
+            path: String::from("/music/song.mp3"),
+            size,
+            modified_at,
+        };
+
+        let cases = [
+            (
+                state(Some(100), modified_at, false),
+                file(100, modified_at),
+                false,
+            ),
+            (
+                state(Some(100), modified_at, false),
+                file(100, nanos_apart),
+                false,
+            ),
+            (
+                state(Some(100), modified_at, false),
+                file(101, modified_at),
+                true,
+            ),
+            (state(Some(100), modified_at, false), file(100, later), true),
+            (
+                state(Some(100), modified_at, true),
+                file(100, modified_at),
+                true,
+            ),
+            (
+                state(None, modified_at, false),
+                file(100, modified_at),
+                true,
+            ),
+            (state(Some(100), None, false), file(100, modified_at), true),
+        ];
+
+        for (state, file, expected) in cases {
+            assert_eq!(has_changed(&state, &file), expected, "{state:?}");
+        }
+    }
+
+    #[test]
+    fn walk_finds_audio_files_below_visible_directories() {
+        let root = std::env::temp_dir().join(format!("vibing-walk-{}", std::process::id()));
+        let files = [
+            ("b.mp3", true),
+            ("a/Song.FLAC", true),
+            ("a/deep/er/c.ogg", true),
+            ("notes.txt", false),
+            (".hidden.mp3", false),
+            (".tus/upload.part", false),
+            (".cache/d.mp3", false),
+        ];
+
+        for (file, _) in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"audio").unwrap();
+        }
+
+        let (entries, errors) = walk(&root);
+        let found: Vec<String> = entries.iter().map(|entry| entry.path.clone()).collect();
+        fs::remove_dir_all(&root).unwrap();
+
+        let expected: Vec<String> = files
+            .iter()
+            .filter(|(_, found)| *found)
+            .map(|(file, _)| root.join(file).to_string_lossy().into_owned())
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        assert_eq!(found, expected);
+        assert_eq!(errors, 0);
+        assert!(entries.iter().all(|entry| entry.size == 5));
+    }
+}
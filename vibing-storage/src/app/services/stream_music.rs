@@ -1 +1,281 @@
+use crate::app::{error::Result, services::download::DownloadableFile};
+use axum::http::{HeaderMap, header};
+use std::io::SeekFrom;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, Take},
+};
 
+/// The part of a file that should be sent back for a stream request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamRange {
+    /// The whole file, answered with 200
+    Full,
+    /// An inclusive byte range, answered with 206
+    Partial { start: u64, end: u64 },
+    /// A range that lies outside of the file, answered with 416
+    Unsatisfiable,
+}
+
+impl StreamRange {
+    /// Resolves the `Range` and `If-Range` headers against the file being served.
+    /// Malformed or multi-part ranges are ignored, so the whole file is sent instead.
+    pub fn from_headers(headers: &HeaderMap, file: &DownloadableFile) -> Option<Self> {
+        let range = headers.get(header::RANGE)?.to_str().ok()?;
+
+        if let Some(if_range) = headers.get(header::IF_RANGE) {
+            let validator = if_range.to_str().ok()?;
+            if !Self::validator_matches(validator, file) {
+                return Some(StreamRange::Full);
+            }
+        }
+
+        Self::parse(range, file.size)
+    }
+
+    /// Converts a position in seconds into a byte range, assuming a constant bitrate
+    pub fn from_seconds(start_at: i32, duration: i32, size: u64) -> Self {
+        if start_at <= 0 || duration <= 0 {
+            return StreamRange::Full;
+        }
+
+        if start_at >= duration {
+            return StreamRange::Unsatisfiable;
+        }
+
+        let start = size * start_at as u64 / duration as u64;
+
+        Self::from_offset(start, size)
+    }
+
+    pub fn from_offset(start: u64, size: u64) -> Self {
+        if start >= size {
+            return StreamRange::Unsatisfiable;
+        }
+
+        StreamRange::Partial {
+            start,
+            end: size - 1,
+        }
+    }
+
+    /// Whether the whole file is asked for, from its first byte to its last. Players start
+    /// a listen that way, while probes ask for the first few bytes only.
+    pub fn is_whole_file(&self, size: u64) -> bool {
+        match self {
+            StreamRange::Full => true,
+            StreamRange::Partial { start, end } => *start == 0 && end + 1 >= size,
+            StreamRange::Unsatisfiable => false,
+        }
+    }
+
+    fn parse(range: &str, size: u64) -> Option<Self> {
+        let spec = range.trim().strip_prefix("bytes=")?;
+
+        if spec.contains(',') {
+            return None;
+        }
+
+        let (first, last) = spec.split_once('-')?;
+        let (first, last) = (first.trim(), last.trim());
+
+        if first.is_empty() {
+            let suffix: u64 = last.parse().ok()?;
+            if suffix == 0 || size == 0 {
+                return Some(StreamRange::Unsatisfiable);
+            }
+
+            return Some(StreamRange::Partial {
+                start: size.saturating_sub(suffix),
+                end: size - 1,
+            });
+        }
+
+        let start: u64 = first.parse().ok()?;
+        let end = if last.is_empty() {
+            None
+        } else {
+            let end: u64 = last.parse().ok()?;
+            if end < start {
+                return None;
+            }
+            Some(end)
+        };
+
+        if start >= size {
+            return Some(StreamRange::Unsatisfiable);
+        }
+
+        Some(StreamRange::Partial {
+            start,
+            end: end.map_or(size - 1, |end| end.min(size - 1)),
+        })
+    }
+
+    fn validator_matches(validator: &str, file: &DownloadableFile) -> bool {
+        let validator = validator.trim();
+
+        // weak entity tags never match for ranges
+        if validator.starts_with("W/") {
+            return false;
+        }
+
+        if validator.starts_with('"') {
+            return validator == file.etag();
+        }
+
+        file.last_modified()
+            .is_some_and(|last_modified| last_modified == validator)
+    }
+}
+
+/// Positions the file at `start` and limits reading to `length` bytes
+pub async fn read_range(mut file: File, start: u64, length: u64) -> Result<Take<File>> {
+    file.seek(SeekFrom::Start(start)).await?;
+
+    Ok(file.take(length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u64 = 1000;
+
+    #[test]
+    fn parse_resolves_ranges_against_the_file_size() {
+        let cases = [
+            (
+                "bytes=0-499",
+                Some(StreamRange::Partial { start: 0, end: 499 }),
+            ),
+            (
+                "bytes=500-",
+                Some(StreamRange::Partial {
+                    start: 500,
+                    end: 999,
+                }),
+            ),
+            (
+                " bytes=10 - 20 ",
+                Some(StreamRange::Partial { start: 10, end: 20 }),
+            ),
+            (
+                "bytes=900-5000",
+                Some(StreamRange::Partial {
+                    start: 900,
+                    end: 999,
+                }),
+            ),
+            (
+                "bytes=-200",
+                Some(StreamRange::Partial {
+                    start: 800,
+                    end: 999,
+                }),
+            ),
+            (
+                "bytes=-5000",
+                Some(StreamRange::Partial { start: 0, end: 999 }),
+            ),
+            (
+                "bytes=999-999",
+                Some(StreamRange::Partial {
+                    start: 999,
+                    end: 999,
+                }),
+            ),
+            ("bytes=1000-", Some(StreamRange::Unsatisfiable)),
+            ("bytes=1000-1200", Some(StreamRange::Unsatisfiable)),
+            ("bytes=-0", Some(StreamRange::Unsatisfiable)),
+            ("bytes=20-10", None),
+            ("bytes=0-10,20-30", None),
+            ("bytes=a-10", None),
+            ("bytes=0-b", None),
+            ("bytes=-", None),
+            ("bytes=10", None),
+            ("items=0-10", None),
+            ("", None),
+        ];
+
+        for (range, expected) in cases {
+            assert_eq!(StreamRange::parse(range, SIZE), expected, "{range:?}");
+        }
+    }
+
+    #[test]
+    fn parse_finds_nothing_to_send_in_an_empty_file() {
+        let cases = [
+            ("bytes=0-", Some(StreamRange::Unsatisfiable)),
+            ("bytes=-10", Some(StreamRange::Unsatisfiable)),
+        ];
+
+        for (range, expected) in cases {
+            assert_eq!(StreamRange::parse(range, 0), expected, "{range:?}");
+        }
+    }
+
+    #[test]
+    fn from_seconds_maps_a_position_to_bytes() {
+        let cases = [
+            (0, 100, StreamRange::Full),
+            (-5, 100, StreamRange::Full),
+            (10, 0, StreamRange::Full),
+            (
+                50,
+                100,
+                StreamRange::Partial {
+                    start: 500,
+                    end: 999,
+                },
+            ),
+            (
+                99,
+                100,
+                StreamRange::Partial {
+                    start: 990,
+                    end: 999,
+                },
+            ),
+            (100, 100, StreamRange::Unsatisfiable),
+            (150, 100, StreamRange::Unsatisfiable),
+        ];
+
+        for (start_at, duration, expected) in cases {
+            assert_eq!(
+                StreamRange::from_seconds(start_at, duration, SIZE),
+                expected,
+                "{start_at}s of {duration}s"
+            );
+        }
+    }
+
+    #[test]
+    fn is_whole_file_tells_listens_from_probes() {
+        let cases = [
+            (StreamRange::Full, true),
+            (StreamRange::Partial { start: 0, end: 999 }, true),
+            // a range running past the end still covers the whole file
+            (
+                StreamRange::Partial {
+                    start: 0,
+                    end: 5000,
+                },
+                true,
+            ),
+            (StreamRange::Partial { start: 0, end: 1 }, false),
+            (
+                StreamRange::Partial {
+                    start: 100,
+                    end: 999,
+                },
+                false,
+            ),
+            (StreamRange::Unsatisfiable, false),
+        ];
+
+        for (range, expected) in cases {
+            assert_eq!(range.is_whole_file(SIZE), expected, "{range:?}");
+        }
+    }
+}
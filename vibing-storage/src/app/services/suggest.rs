@@ -0,0 +1,126 @@
+use crate::{
+    app::error::Result,
+    database::{core::pool::VibingPool, entities::suggestion::Suggestions},
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Every keystroke asks again, a short lifetime keeps new tracks showing up soon enough
+const CACHE_TTL: Duration = Duration::from_secs(30);
+const CACHE_CAPACITY: usize = 1024;
+
+type CacheKey = (String, i64);
+
+/// Recent suggestions by lowercased prefix and limit, shared by every request
+#[derive(Debug, Clone, Default)]
+pub struct SuggestionCache {
+    entries: Arc<Mutex<HashMap<CacheKey, (Instant, Suggestions)>>>,
+}
+
+impl SuggestionCache {
+    pub async fn get_or_fetch(
+        &self,
+        prefix: &str,
+        limit: i64,
+        pool: &VibingPool,
+    ) -> Result<Suggestions> {
+        let key = (prefix.to_lowercase(), limit);
+
+        if let Some(suggestions) = self.lookup(&key) {
+            return Ok(suggestions);
+        }
+
+        let suggestions = Suggestions::by_prefix(prefix, limit, pool).await?;
+        self.store(key, suggestions.clone());
+
+        Ok(suggestions)
+    }
+
+    fn lookup(&self, key: &CacheKey) -> Option<Suggestions> {
+        let entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+
+        entries
+            .get(key)
+            .filter(|(stored_at, _)| stored_at.elapsed() < CACHE_TTL)
+            .map(|(_, suggestions)| suggestions.clone())
+    }
+
+    fn store(&self, key: CacheKey, suggestions: Suggestions) {
+        let mut entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+
+        if entries.len() >= CACHE_CAPACITY {
+            entries.retain(|_, (stored_at, _)| stored_at.elapsed() < CACHE_TTL);
+        }
+        // still full of fresh entries, start over rather than track their use
+        if entries.len() >= CACHE_CAPACITY {
+            entries.clear();
+        }
+
+        entries.insert(key, (Instant::now(), suggestions));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::entities::suggestion::Suggestion;
+
+    fn suggestions(title: &str) -> Suggestions {
+        Suggestions {
+            titles: vec![Suggestion {
+                value: title.to_string(),
+                count: 1,
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn lookup_finds_what_was_stored() {
+        let cache = SuggestionCache::default();
+        cache.store((String::from("rai"), 5), suggestions("Rain"));
+
+        assert_eq!(
+            cache.lookup(&(String::from("rai"), 5)),
+            Some(suggestions("Rain"))
+        );
+        assert_eq!(cache.lookup(&(String::from("rai"), 10)), None);
+        assert_eq!(cache.lookup(&(String::from("ra"), 5)), None);
+    }
+
+    #[test]
+    fn lookup_skips_stale_entries() {
+        let cache = SuggestionCache::default();
+        let stale = Instant::now().checked_sub(CACHE_TTL).unwrap();
+        cache
+            .entries
+            .lock()
+            .unwrap()
+            .insert((String::from("rai"), 5), (stale, suggestions("Rain")));
+
+        assert_eq!(cache.lookup(&(String::from("rai"), 5)), None);
+    }
+
+    #[test]
+    fn store_makes_room_when_full() {
+        let cache = SuggestionCache::default();
+        for limit in 0..CACHE_CAPACITY as i64 {
+            cache.store((String::from("rai"), limit), suggestions("Rain"));
+        }
+
+        cache.store((String::from("cit"), 5), suggestions("City"));
+
+        let entries = cache.entries.lock().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(entries.contains_key(&(String::from("cit"), 5)));
+    }
+}
@@ -0,0 +1,337 @@
+use crate::{
+    app::{
+        error::{AppError, Result},
+        format::SNIFF_LEN,
+        services::upload::{
+            UploadOverrides, adopt, check_magic_bytes, import_staged, sanitize_file_name,
+            supported_extension,
+        },
+    },
+    config::Configuration,
+    database::{
+        core::pool::VibingPool,
+        entities::{
+            track::TrackFull,
+            upload::{Upload, UploadID, UploadMetadata},
+        },
+    },
+};
+use axum::body::Body;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chrono::{DateTime, Duration, Utc};
+use futures_util::StreamExt;
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+pub const TUS_VERSION: &str = "1.0.0";
+pub const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+
+/// Uploads a PATCH is writing to right now
+static WRITING: Mutex<BTreeSet<UploadID>> = Mutex::new(BTreeSet::new());
+
+/// Result of appending a chunk to an upload
+#[derive(Debug)]
+pub enum Progress {
+    Partial(Upload),
+    Finished(TrackFull),
+}
+
+/// Creates the upload record and an empty part file for it.
+/// `metadata` is the raw `Upload-Metadata` header.
+pub async fn create(length: i64, metadata: Option<&str>, pool: &VibingPool) -> Result<Upload> {
+    let config = Configuration::get();
+
+    if length <= 0 {
+        return Err(AppError::InvalidUpload(String::from("empty upload")));
+    }
+
+    if length as u64 > config.max_upload_size {
+        return Err(AppError::UploadTooLarge);
+    }
+
+    let mut metadata = match metadata {
+        Some(metadata) => parse_metadata(metadata)?,
+        None => HashMap::new(),
+    };
+
+    let file_name = metadata
+        .remove("filename")
+        .as_deref()
+        .and_then(sanitize_file_name)
+        .ok_or_else(|| AppError::InvalidUpload(String::from("missing filename metadata")))?;
+    supported_extension(&file_name)?;
+
+    let upload = Upload::create_from(
+        UploadMetadata {
+            file_name,
+            length,
+            title: metadata.remove("title"),
+            author: metadata.remove("author"),
+            genre: metadata.remove("genre"),
+            expires_at: next_expiration(),
+        },
+        pool,
+    )
+    .await?;
+
+    fs::create_dir_all(staging_dir()).await?;
+    File::create(staging_path(&upload)).await?;
+
+    Ok(upload)
+}
+
+/// Streams `body` into the part file starting at `offset`.
+/// Whatever arrived before a broken connection is kept, so the client can resume from there.
+pub async fn append(
+    upload: Upload,
+    offset: i64,
+    body: Body,
+    pool: &VibingPool,
+) -> Result<Progress> {
+    // a second PATCH racing this one is refused before it writes anything
+    let _lock = WriteLock::acquire(upload.id).ok_or(AppError::UploadOffsetMismatch)?;
+
+    // the offset may have moved since the caller read the upload
+    let upload = Upload::get_by_id(upload.id, pool).await?;
+    if offset != upload.offset {
+        return Err(AppError::UploadOffsetMismatch);
+    }
+
+    let path = staging_path(&upload);
+    let mut file = OpenOptions::new().write(true).open(&path).await?;
+    file.seek(SeekFrom::Start(offset as u64)).await?;
+
+    let mut written: i64 = 0;
+    let mut stream = body.into_data_stream();
+    let mut failure = None;
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(error) => {
+                failure = Some(AppError::InvalidUpload(error.to_string()));
+                break;
+            }
+        };
+
+        if offset + written + chunk.len() as i64 > upload.length {
+            failure = Some(AppError::UploadTooLarge);
+            break;
+        }
+
+        if let Err(error) = file.write_all(&chunk).await {
+            failure = Some(error.into());
+            break;
+        }
+        written += chunk.len() as i64;
+    }
+
+    file.sync_all().await?;
+    drop(file);
+
+    let new_offset = offset + written;
+    if offset < SNIFF_LEN as i64 && (new_offset >= SNIFF_LEN as i64 || new_offset == upload.length)
+    {
+        let mut head = Vec::with_capacity(SNIFF_LEN);
+        File::open(&path)
+            .await?
+            .take(SNIFF_LEN as u64)
+            .read_to_end(&mut head)
+            .await?;
+
+        let extension = supported_extension(&upload.file_name)?;
+        if let Err(error) = check_magic_bytes(&head, &extension) {
+            terminate(upload, pool).await?;
+            return Err(error);
+        }
+    }
+
+    let upload = upload
+        .advance(new_offset, next_expiration(), pool)
+        .await?
+        .ok_or(AppError::UploadOffsetMismatch)?;
+
+    if let Some(error) = failure {
+        return Err(error);
+    }
+
+    if upload.offset == upload.length {
+        return Ok(Progress::Finished(finish(upload, pool).await?));
+    }
+
+    Ok(Progress::Partial(upload))
+}
+
+/// Claims an upload for a single writer, released on drop
+#[derive(Debug)]
+struct WriteLock(UploadID);
+
+impl WriteLock {
+    fn acquire(id: UploadID) -> Option<WriteLock> {
+        let claimed = WRITING
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id);
+
+        // built only once the set is unlocked, dropping it locks the set again
+        claimed.then(|| WriteLock(id))
+    }
+}
+
+impl Drop for WriteLock {
+    fn drop(&mut self) {
+        WRITING
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.0);
+    }
+}
+
+/// Moves a complete upload into the resource directory and registers it as a track
+async fn finish(upload: Upload, pool: &VibingPool) -> Result<TrackFull> {
+    let part = staging_path(&upload);
+    let resource_dir = PathBuf::from(Configuration::get().upload_dir());
+
+    let staged = adopt(&part, &resource_dir, &upload.file_name).await;
+    let overrides = UploadOverrides {
+        title: upload.title.clone(),
+        author: upload.author.clone(),
+        genre: upload.genre.clone(),
+    };
+
+    // the upload is consumed either way, a failed import cannot be resumed
+    terminate(upload, pool).await?;
+
+    import_staged(staged?, overrides, pool).await
+}
+
+/// Removes the upload record and its part file
+pub async fn terminate(upload: Upload, pool: &VibingPool) -> Result<()> {
+    let path = staging_path(&upload);
+    upload.remove(pool).await?;
+
+    match fs::remove_file(path).await {
+        Ok(()) => Ok(()),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Terminates every upload past its expiration date, returning how many were removed
+pub async fn remove_expired(pool: &VibingPool) -> Result<usize> {
+    let expired = Upload::get_expired(pool).await?;
+    let count = expired.len();
+
+    for upload in expired {
+        terminate(upload, pool).await?;
+    }
+
+    Ok(count)
+}
+
+/// Parses `Upload-Metadata`, a comma separated list of keys with base64 encoded values
+pub fn parse_metadata(header: &str) -> Result<HashMap<String, String>> {
+    let mut metadata = HashMap::new();
+
+    for pair in header
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let (key, value) = match pair.split_once(' ') {
+            Some((key, encoded)) => {
+                let decoded = STANDARD
+                    .decode(encoded.trim())
+                    .ok()
+                    .and_then(|bytes| String::from_utf8(bytes).ok())
+                    .ok_or_else(|| {
+                        AppError::InvalidUpload(format!("invalid metadata value for {}", key))
+                    })?;
+                (key, decoded)
+            }
+            None => (pair, String::new()),
+        };
+
+        metadata.insert(key.to_string(), value);
+    }
+
+    Ok(metadata)
+}
+
+pub fn is_expired(upload: &Upload) -> bool {
+    upload.expires_at < Utc::now()
+}
+
+fn next_expiration() -> DateTime<Utc> {
+    Utc::now() + Duration::seconds(Configuration::get().upload_expiration as i64)
+}
+
+fn staging_dir() -> PathBuf {
+    Path::new(Configuration::get().upload_dir()).join(".tus")
+}
+
+fn staging_path(upload: &Upload) -> PathBuf {
+    staging_dir().join(format!("{}.part", upload.id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn write_lock_admits_one_writer_per_upload() {
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let lock = WriteLock::acquire(first).unwrap();
+        assert!(WriteLock::acquire(first).is_none());
+        assert!(WriteLock::acquire(second).is_some());
+
+        drop(lock);
+        assert!(WriteLock::acquire(first).is_some());
+    }
+
+    #[test]
+    fn parse_metadata_decodes_every_pair() {
+        let cases = [
+            ("", vec![]),
+            ("filename c29uZy5tcDM=", vec![("filename", "song.mp3")]),
+            (
+                "filename c29uZy5tcDM=, title SOG6uW4gZ+G6t3AgbOG6oWk=,is_private",
+                vec![
+                    ("filename", "song.mp3"),
+                    ("title", "Hẹn gặp lại"),
+                    ("is_private", ""),
+                ],
+            ),
+            (" author  TnVqYWJlcw== , ,", vec![("author", "Nujabes")]),
+        ];
+
+        for (header, expected) in cases {
+            let expected: HashMap<String, String> = expected
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            assert_eq!(parse_metadata(header).unwrap(), expected, "{header:?}");
+        }
+    }
+
+    #[test]
+    fn parse_metadata_rejects_values_that_are_not_base64_text() {
+        for header in [
+            "filename song.mp3",
+            "title //79",
+            "filename c29uZy5tcDM=,title !",
+        ] {
+            assert!(parse_metadata(header).is_err(), "{header:?}");
+        }
+    }
+}
@@ -1 +1,385 @@
+use crate::{
+    app::{
+        error::{AppError, Result},
+        fetch::{fetch_metadata_from, normalize_text, sniff_format_of},
+        format::{AudioFormat, SNIFF_LEN, is_supported_extension},
+    },
+    database::{core::pool::VibingPool, entities::track::TrackFull},
+};
+use axum::extract::multipart::Field;
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs::{self, File},
+    io::AsyncWriteExt,
+};
 
+/// Client supplied values that take precedence over the tags found in the file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UploadOverrides {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub genre: Option<String>,
+}
+
+/// A file written into the resource directory, removed again on drop unless kept
+#[derive(Debug)]
+pub struct StagedFile {
+    path: PathBuf,
+    kept: bool,
+}
+
+impl StagedFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Leaves the file on disk and returns its path
+    pub fn keep(mut self) -> PathBuf {
+        self.kept = true;
+        std::mem::take(&mut self.path)
+    }
+}
+
+impl Drop for StagedFile {
+    fn drop(&mut self) {
+        if !self.kept {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Streams a multipart file field into `dir`, checking its size and format on the way.
+/// Nothing is left behind in `dir` when this fails.
+pub async fn store_field(mut field: Field<'_>, dir: &Path, max_size: u64) -> Result<StagedFile> {
+    let file_name = field
+        .file_name()
+        .and_then(sanitize_file_name)
+        .ok_or_else(|| AppError::InvalidUpload(String::from("missing file name")))?;
+
+    let extension = supported_extension(&file_name)?;
+
+    let staged = StagedFile {
+        path: dir.join(format!(".{}.part", unique_suffix())),
+        kept: false,
+    };
+
+    let mut file = File::create(&staged.path).await?;
+    let mut written: u64 = 0;
+    let mut head: Vec<u8> = Vec::with_capacity(SNIFF_LEN);
+    let mut sniffed = false;
+
+    while let Some(chunk) = field.chunk().await? {
+        written += chunk.len() as u64;
+        if written > max_size {
+            return Err(AppError::UploadTooLarge);
+        }
+
+        if !sniffed {
+            let missing = SNIFF_LEN - head.len();
+            head.extend_from_slice(&chunk[..missing.min(chunk.len())]);
+
+            if head.len() == SNIFF_LEN {
+                check_magic_bytes(&head, &extension)?;
+                sniffed = true;
+            }
+        }
+
+        file.write_all(&chunk).await?;
+    }
+
+    if !sniffed {
+        check_magic_bytes(&head, &extension)?;
+    }
+
+    file.sync_all().await?;
+    drop(file);
+
+    // the staged part file is removed when it goes out of scope
+    adopt(&staged.path, dir, &file_name).await
+}
+
+/// Links a completely written file into `dir` without overwriting anything already there
+pub async fn adopt(source: &Path, dir: &Path, file_name: &str) -> Result<StagedFile> {
+    let target = link_unique(source, dir, file_name).await?;
+
+    Ok(StagedFile {
+        path: target,
+        kept: false,
+    })
+}
+
+/// Copies an audio file from anywhere on disk into `dir` and registers it as a track
+pub async fn import_file(
+    source: &Path,
+    dir: &Path,
+    overrides: UploadOverrides,
+    pool: &VibingPool,
+) -> Result<TrackFull> {
+    let file_name = source
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(sanitize_file_name)
+        .ok_or_else(|| AppError::InvalidUpload(String::from("missing file name")))?;
+
+    let extension = supported_extension(&file_name)?;
+    if !sniff_format_of(source)?.is_some_and(|format| format.matches_extension(&extension)) {
+        return Err(AppError::UnsupportedFormat(format!(
+            "content is not {} audio",
+            extension
+        )));
+    }
+
+    let part = StagedFile {
+        path: dir.join(format!(".{}.part", unique_suffix())),
+        kept: false,
+    };
+    fs::copy(source, &part.path).await?;
+
+    let staged = adopt(&part.path, dir, &file_name).await?;
+
+    import_staged(staged, overrides, pool).await
+}
+
+/// Reads the tags of a stored upload and registers it as a track.
+/// The file is kept only if the track could be created.
+pub async fn import_staged(
+    staged: StagedFile,
+    overrides: UploadOverrides,
+    pool: &VibingPool,
+) -> Result<TrackFull> {
+    let path = staged
+        .path()
+        .to_str()
+        .ok_or_else(|| AppError::InvalidUpload(String::from("file name is not valid UTF-8")))?
+        .to_string();
+
+    // tag reading and duration probing block, keep them off the async workers
+    let mut metadata = tokio::task::spawn_blocking(move || fetch_metadata_from(&path))
+        .await
+        .map_err(|error| AppError::IoError(error.to_string()))??;
+
+    if let Some(title) = overrides.title {
+        metadata.title = Some(normalize_text(&title));
+    }
+
+    if let Some(author) = overrides.author {
+        metadata.author = Some(normalize_text(&author));
+    }
+
+    if let Some(genre) = overrides.genre {
+        metadata.genre = Some(normalize_text(&genre));
+    }
+
+    let track = TrackFull::create_from(metadata, pool).await?;
+    staged.keep();
+
+    Ok(track)
+}
+
+/// Returns the lowercased extension of `file_name` if it is one we can handle
+pub fn supported_extension(file_name: &str) -> Result<String> {
+    let extension = Path::new(file_name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .unwrap_or_default();
+
+    if is_supported_extension(&extension) {
+        Ok(extension)
+    } else {
+        Err(AppError::UnsupportedFormat(format!(
+            "\"{}\" files are not supported",
+            extension
+        )))
+    }
+}
+
+pub fn check_magic_bytes(head: &[u8], extension: &str) -> Result<()> {
+    let matches =
+        AudioFormat::sniff(head).is_some_and(|format| format.matches_extension(extension));
+
+    if matches {
+        Ok(())
+    } else {
+        Err(AppError::UnsupportedFormat(format!(
+            "content is not {} audio",
+            extension
+        )))
+    }
+}
+
+/// Keeps only the final path component and replaces characters that are awkward on disk
+pub fn sanitize_file_name(name: &str) -> Option<String> {
+    let name = Path::new(name).file_name()?.to_str()?;
+
+    let sanitized: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.' | '(' | ')') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let sanitized = sanitized.trim_start_matches('.').trim().to_string();
+
+    if sanitized.is_empty() {
+        None
+    } else {
+        Some(sanitized)
+    }
+}
+
+/// Hard-links `source` into `dir` under `file_name`, adding a counter when the name is taken.
+/// Where linking is not possible, across filesystems for instance, the file is copied.
+/// Both fail instead of overwriting, so concurrent uploads cannot clobber each other.
+async fn link_unique(source: &Path, dir: &Path, file_name: &str) -> Result<PathBuf> {
+    let stem = Path::new(file_name)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("upload");
+    let extension = Path::new(file_name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default();
+
+    let mut candidate = dir.join(file_name);
+    let mut counter = 1;
+
+    loop {
+        let placed = match fs::hard_link(source, &candidate).await {
+            Err(error) if error.kind() != std::io::ErrorKind::AlreadyExists => {
+                copy_new(source, &candidate).await
+            }
+            linked => linked,
+        };
+
+        match placed {
+            Ok(()) => return Ok(candidate),
+            Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {
+                candidate = dir.join(format!("{} ({}).{}", stem, counter, extension));
+                counter += 1;
+            }
+            Err(error) => return Err(error.into()),
+        }
+    }
+}
+
+/// Copies `source` to a new file at `target`, failing when `target` already exists
+async fn copy_new(source: &Path, target: &Path) -> std::io::Result<()> {
+    let mut reader = File::open(source).await?;
+    let mut writer = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(target)
+        .await?;
+
+    let copied = async {
+        tokio::io::copy(&mut reader, &mut writer).await?;
+        writer.sync_all().await
+    }
+    .await;
+
+    // a partial copy would be taken for a complete file
+    if copied.is_err() {
+        let _ = fs::remove_file(target).await;
+    }
+
+    copied
+}
+
+fn unique_suffix() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos());
+
+    format!("upload-{}-{:x}", std::process::id(), nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_file_name_keeps_a_safe_final_component() {
+        let cases = [
+            ("song.mp3", Some("song.mp3")),
+            ("Hẹn Gặp Lại (Live).flac", Some("Hẹn Gặp Lại (Live).flac")),
+            ("../../etc/passwd", Some("passwd")),
+            ("/music/jazz/take five.mp3", Some("take five.mp3")),
+            ("a;b|c$d.mp3", Some("a_b_c_d.mp3")),
+            (".hidden.mp3", Some("hidden.mp3")),
+            ("  spaced.mp3  ", Some("spaced.mp3")),
+            ("...", None),
+            ("..", None),
+            ("", None),
+        ];
+
+        for (name, expected) in cases {
+            assert_eq!(sanitize_file_name(name).as_deref(), expected, "{name:?}");
+        }
+    }
+
+    #[test]
+    fn supported_extension_is_lowercased() {
+        let cases = [
+            ("song.MP3", Some("mp3")),
+            ("song.flac", Some("flac")),
+            ("song.Opus", Some("opus")),
+            ("song.txt", None),
+            ("song", None),
+        ];
+
+        for (name, expected) in cases {
+            assert_eq!(
+                supported_extension(name).ok().as_deref(),
+                expected,
+                "{name:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn check_magic_bytes_compares_content_and_extension() {
+        let cases: [(&[u8], &str, bool); 5] = [
+            (b"ID3\x04\x00", "mp3", true),
+            (b"fLaC\x00\x00", "FLAC", true),
+            (b"fLaC\x00\x00", "mp3", false),
+            (b"<html>", "mp3", false),
+            (b"", "wav", false),
+        ];
+
+        for (head, extension, expected) in cases {
+            assert_eq!(
+                check_magic_bytes(head, extension).is_ok(),
+                expected,
+                "{head:?} as {extension}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn link_unique_never_overwrites() {
+        let dir = std::env::temp_dir().join(format!("vibing-link-{}", unique_suffix()));
+        fs::create_dir_all(&dir).await.unwrap();
+
+        let source = dir.join(".source.part");
+        fs::write(&source, b"first").await.unwrap();
+        fs::write(dir.join("song.mp3"), b"taken").await.unwrap();
+
+        let expected = ["song (1).mp3", "song (2).mp3"];
+        for name in expected {
+            let target = link_unique(&source, &dir, "song.mp3").await.unwrap();
+            assert_eq!(target, dir.join(name));
+            assert_eq!(fs::read(&target).await.unwrap(), b"first");
+        }
+        assert_eq!(fs::read(dir.join("song.mp3")).await.unwrap(), b"taken");
+
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,302 @@
+use crate::{
+    app::{
+        error::Result,
+        format::AudioFormat,
+        services::scanner::{scan, sync_file},
+    },
+    database::{
+        core::pool::VibingPool,
+        entities::track::{TrackFull, TrackFullPatch},
+    },
+};
+use notify_debouncer_full::{
+    DebounceEventResult, DebouncedEvent, Debouncer, RecommendedCache, new_debouncer,
+    notify::{
+        EventKind, RecommendedWatcher, RecursiveMode,
+        event::{ModifyKind, RenameMode},
+    },
+};
+use std::{
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+
+/// Editors and copy tools touch a file several times in a row, wait for them to settle
+const DEBOUNCE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Keeps watching for as long as it is alive
+pub struct LibraryWatcher {
+    _debouncer: Debouncer<RecommendedWatcher, RecommendedCache>,
+}
+
+/// Starts watching every directory in `dirs` and applies each change below them to the
+/// catalog. Deleted files are flagged missing like a scan would, so their ratings and vibes
+/// come back if the file does.
+pub fn watch(dirs: &[String], pool: VibingPool) -> Result<LibraryWatcher> {
+    let roots: Vec<PathBuf> = dirs.iter().map(PathBuf::from).collect();
+    let (sender, mut receiver) = mpsc::unbounded_channel::<DebounceEventResult>();
+
+    let mut debouncer = new_debouncer(DEBOUNCE_TIMEOUT, None, move |result| {
+        // the receiver only goes away when the runtime shuts down
+        let _ = sender.send(result);
+    })?;
+    for root in &roots {
+        debouncer.watch(root, RecursiveMode::Recursive)?;
+    }
+
+    tokio::spawn(async move {
+        while let Some(result) = receiver.recv().await {
+            match result {
+                Ok(events) => {
+                    for event in events {
+                        let Some(root) = root_of(&roots, &event) else {
+                            continue;
+                        };
+
+                        if let Err(error) = apply(root, &event, &pool).await {
+                            eprintln!("cannot apply {:?}: {:?}", event.event, error);
+                        }
+                    }
+                }
+                Err(errors) => {
+                    for error in errors {
+                        eprintln!("watch error: {:?}", error);
+                    }
+                }
+            }
+        }
+    });
+
+    Ok(LibraryWatcher {
+        _debouncer: debouncer,
+    })
+}
+
+async fn apply(root: &Path, event: &DebouncedEvent, pool: &VibingPool) -> Result<()> {
+    match event.kind {
+        EventKind::Create(_) | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Any) => {
+            for path in &event.paths {
+                upsert(root, path, pool).await?;
+            }
+        }
+        // a touched file may have been replaced in place, sync_file tells whether it changed
+        EventKind::Modify(ModifyKind::Metadata(_)) => {
+            for path in event.paths.iter().filter(|path| path.is_file()) {
+                upsert(root, path, pool).await?;
+            }
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            if let [from, to] = event.paths.as_slice() {
+                rename(root, from, to, pool).await?;
+            }
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) | EventKind::Remove(_) => {
+            for path in &event.paths {
+                forget(path, pool).await?;
+            }
+        }
+        // the other half of the rename happened outside of the library
+        EventKind::Modify(ModifyKind::Name(_)) => {
+            for path in &event.paths {
+                if path.exists() {
+                    upsert(root, path, pool).await?;
+                } else {
+                    forget(path, pool).await?;
+                }
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Adds or refreshes the file at `path`, or everything below it for a directory
+async fn upsert(root: &Path, path: &Path, pool: &VibingPool) -> Result<()> {
+    if is_hidden(root, path) {
+        return Ok(());
+    }
+
+    let Some(path_str) = path.to_str() else {
+        return Ok(());
+    };
+
+    if path.is_dir() {
+        scan(path_str, pool, |_| {}).await?;
+        return Ok(());
+    }
+
+    if AudioFormat::from_path(path).is_none() || !path.is_file() {
+        return Ok(());
+    }
+
+    sync_file(path_str, pool).await?;
+
+    Ok(())
+}
+
+/// Moves the tracks at `from` to `to`, keeping their ratings and vibes
+async fn rename(root: &Path, from: &Path, to: &Path, pool: &VibingPool) -> Result<()> {
+    let (Some(from_str), Some(to_str)) = (from.to_str(), to.to_str()) else {
+        return Ok(());
+    };
+
+    if is_hidden(root, to) {
+        return forget(from, pool).await;
+    }
+
+    // an upload is renamed into the library from a hidden part file
+    if is_hidden(root, from) {
+        return upsert(root, to, pool).await;
+    }
+
+    if to.is_dir() {
+        let from_prefix = format!("{}/", from_str);
+        let to_prefix = format!("{}/", to_str);
+
+        for state in TrackFull::get_file_states(&from_prefix, pool).await? {
+            let Some(rest) = state.path.strip_prefix(&from_prefix) else {
+                continue;
+            };
+
+            move_track(state.id, format!("{}{}", to_prefix, rest), pool).await?;
+        }
+
+        return Ok(());
+    }
+
+    if AudioFormat::from_path(to).is_none() {
+        return forget(from, pool).await;
+    }
+
+    let Some(track) = TrackFull::get_by_path(from_str, pool).await? else {
+        return upsert(root, to, pool).await;
+    };
+
+    // the file that was overwritten by the rename is gone for good
+    if let Some(replaced) = TrackFull::get_by_path(to_str, pool).await? {
+        replaced.remove(pool).await?;
+    }
+
+    move_track(track.track.id, to_str.to_string(), pool).await?;
+    sync_file(to_str, pool).await?;
+
+    Ok(())
+}
+
+async fn move_track(id: i32, path: String, pool: &VibingPool) -> Result<()> {
+    let patch = TrackFullPatch {
+        path: Some(path),
+        ..Default::default()
+    };
+
+    TrackFull::get_by_id(id, pool)
+        .await?
+        .apply_patch(patch, pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Flags the track at `path`, or every track below it, as missing
+async fn forget(path: &Path, pool: &VibingPool) -> Result<()> {
+    let Some(path_str) = path.to_str() else {
+        return Ok(());
+    };
+
+    let mut ids = Vec::new();
+    if let Some(track) = TrackFull::get_by_path(path_str, pool).await? {
+        ids.push(track.track.id);
+    }
+
+    let prefix = format!("{}/", path_str);
+    for state in TrackFull::get_file_states(&prefix, pool).await? {
+        if !state.missing {
+            ids.push(state.id);
+        }
+    }
+
+    for id in ids {
+        let patch = TrackFullPatch {
+            missing: Some(true),
+            ..Default::default()
+        };
+
+        TrackFull::get_by_id(id, pool)
+            .await?
+            .apply_patch(patch, pool)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// The watched directory an event happened in
+fn root_of<'a>(roots: &'a [PathBuf], event: &DebouncedEvent) -> Option<&'a PathBuf> {
+    let path = event.paths.first()?;
+
+    roots.iter().find(|root| path.starts_with(root))
+}
+
+/// Whether any component below `root` is hidden, such as in-progress uploads
+fn is_hidden(root: &Path, path: &Path) -> bool {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+
+    relative.components().any(|component| match component {
+        Component::Normal(name) => name.to_string_lossy().starts_with('.'),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify_debouncer_full::notify::Event;
+    use std::time::Instant;
+
+    #[test]
+    fn root_of_picks_the_watched_directory_of_the_event() {
+        let roots = [PathBuf::from("/music"), PathBuf::from("/uploads")];
+
+        let cases = [
+            ("/music/jazz/take five.mp3", Some("/music")),
+            ("/uploads/song.mp3", Some("/uploads")),
+            ("/musical/song.mp3", None),
+            ("/tmp/song.mp3", None),
+        ];
+
+        for (path, expected) in cases {
+            let event = DebouncedEvent::new(
+                Event::new(EventKind::Any).add_path(PathBuf::from(path)),
+                Instant::now(),
+            );
+
+            assert_eq!(
+                root_of(&roots, &event).map(PathBuf::as_path),
+                expected.map(Path::new),
+                "{path:?}"
+            );
+        }
+
+        let no_path = DebouncedEvent::new(Event::new(EventKind::Any), Instant::now());
+        assert_eq!(root_of(&roots, &no_path), None);
+    }
+
+    #[test]
+    fn is_hidden_looks_below_the_root_only() {
+        let root = Path::new("/home/me/.music");
+
+        let cases = [
+            ("/home/me/.music/song.mp3", false),
+            ("/home/me/.music/jazz/song.mp3", false),
+            ("/home/me/.music/.tus/upload.part", true),
+            ("/home/me/.music/jazz/.song.mp3.part", true),
+            ("/home/me/.music/.cache/jazz/song.mp3", true),
+        ];
+
+        for (path, expected) in cases {
+            assert_eq!(is_hidden(root, Path::new(path)), expected, "{path:?}");
+        }
+    }
+}
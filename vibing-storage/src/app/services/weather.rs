@@ -0,0 +1,35 @@
+use crate::app::error::Result;
+use futures_util::future::{self, BoxFuture, FutureExt};
+
+/// Where the weather is asked for, providers use what they can of it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Place {
+    /// IANA name or UTC offset, as sent by the client
+    pub timezone: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+/// Source of the current weather, reported as the name of a `weather` vibe such as `rainy`.
+/// `None` when the weather is unknown, the context is then made of the time alone.
+pub trait WeatherProvider: Send + Sync {
+    fn current<'a>(&'a self, place: &'a Place) -> BoxFuture<'a, Result<Option<String>>>;
+}
+
+/// Reports the same weather everywhere, the configured one or none
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StaticWeather {
+    weather: Option<String>,
+}
+
+impl StaticWeather {
+    pub fn new(weather: Option<String>) -> Self {
+        StaticWeather { weather }
+    }
+}
+
+impl WeatherProvider for StaticWeather {
+    fn current<'a>(&'a self, _place: &'a Place) -> BoxFuture<'a, Result<Option<String>>> {
+        future::ready(Ok(self.weather.clone())).boxed()
+    }
+}
@@ -0,0 +1,42 @@
+use crate::{
+    app::services::{suggest::SuggestionCache, weather::WeatherProvider},
+    database::core::pool::VibingPool,
+};
+use axum::extract::FromRef;
+use std::sync::Arc;
+
+/// What the handlers share, each of them extracts only the part it needs
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: VibingPool,
+    pub suggestions: SuggestionCache,
+    pub weather: Arc<dyn WeatherProvider>,
+}
+
+impl AppState {
+    pub fn new(pool: VibingPool, weather: Arc<dyn WeatherProvider>) -> Self {
+        AppState {
+            pool,
+            suggestions: SuggestionCache::default(),
+            weather,
+        }
+    }
+}
+
+impl FromRef<AppState> for VibingPool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for SuggestionCache {
+    fn from_ref(state: &AppState) -> Self {
+        state.suggestions.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn WeatherProvider> {
+    fn from_ref(state: &AppState) -> Self {
+        state.weather.clone()
+    }
+}
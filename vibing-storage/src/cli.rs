@@ -0,0 +1,368 @@
+use crate::{
+    app::{
+        api::get::ResponseTrack,
+        error::{AppError, Result},
+        services::{
+            scanner::scan_all,
+            upload::{UploadOverrides, import_file},
+        },
+    },
+    config::{ConfigLayer, Configuration, DatabaseLayer},
+    database::{
+        core::pool::VibingPool,
+        entities::{track::TrackFull, vibe::Vibe},
+    },
+};
+use clap::{Args, Parser, Subcommand};
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "vibing-storage",
+    version,
+    about = "Music storage sorted by vibes"
+)]
+pub struct Cli {
+    /// What to do, serving the API when left out
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub config: ConfigArgs,
+}
+
+/// Flags overriding the configuration file and the `VIBING_*` environment variables
+#[derive(Debug, Args, Clone, Default)]
+pub struct ConfigArgs {
+    /// Configuration file, TOML or JSON
+    #[arg(long = "config", global = true)]
+    pub file: Option<PathBuf>,
+    #[arg(long, global = true)]
+    pub bind_address: Option<IpAddr>,
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+    /// Library directory, may be repeated
+    #[arg(long = "resource-dir", global = true)]
+    pub resource_dirs: Vec<String>,
+    #[arg(long, global = true)]
+    pub database_url: Option<String>,
+    #[arg(long = "db-max-connections", global = true)]
+    pub max_connections: Option<u32>,
+    /// Seconds to wait for a free database connection
+    #[arg(long = "db-acquire-timeout", global = true)]
+    pub acquire_timeout: Option<u64>,
+    /// Seconds a single statement may run
+    #[arg(long = "db-statement-timeout", global = true)]
+    pub statement_timeout: Option<u64>,
+    /// Origin allowed to call the API from a browser, may be repeated
+    #[arg(long = "cors-origin", global = true)]
+    pub cors_origins: Vec<String>,
+    /// Largest accepted upload in bytes
+    #[arg(long, global = true)]
+    pub max_upload_size: Option<u64>,
+    /// Do not watch the resource directories for changes
+    #[arg(long, global = true)]
+    pub no_watch: bool,
+}
+
+impl From<ConfigArgs> for ConfigLayer {
+    fn from(args: ConfigArgs) -> Self {
+        ConfigLayer {
+            bind_address: args.bind_address,
+            port: args.port,
+            resource_dirs: (!args.resource_dirs.is_empty()).then_some(args.resource_dirs),
+            database: DatabaseLayer {
+                url: args.database_url,
+                max_connections: args.max_connections,
+                acquire_timeout: args.acquire_timeout,
+                statement_timeout: args.statement_timeout,
+                ..Default::default()
+            },
+            cors_origins: (!args.cors_origins.is_empty()).then_some(args.cors_origins),
+            max_upload_size: args.max_upload_size,
+            watch: args.no_watch.then_some(false),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Serve the HTTP API
+    Serve {
+        /// Apply pending migrations before serving
+        #[arg(long)]
+        migrate: bool,
+    },
+    /// Apply, revert or list database migrations
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateAction,
+    },
+    /// Bring the catalog in line with a directory, every resource directory by default
+    Scan { dir: Option<String> },
+    /// Copy audio files into the resource directory and add them to the catalog
+    Import {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Title to use instead of the one found in the tags
+        #[arg(long)]
+        title: Option<String>,
+        /// Author to use instead of the one found in the tags
+        #[arg(long)]
+        author: Option<String>,
+        /// Genre to use instead of the one found in the tags
+        #[arg(long)]
+        genre: Option<String>,
+    },
+    /// Write the whole catalog as JSON
+    Export {
+        /// File to write to instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// List or add vibes
+    Vibes {
+        #[command(subcommand)]
+        action: VibesAction,
+    },
+    /// Inspect or delete tracks
+    Tracks {
+        #[command(subcommand)]
+        action: TracksAction,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MigrateAction {
+    /// Apply every pending migration
+    Up,
+    /// Revert the latest migrations
+    Down {
+        #[arg(default_value_t = 1)]
+        steps: usize,
+    },
+    /// List migrations and whether they are applied
+    Status,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum VibesAction {
+    List,
+    Add {
+        name: String,
+        #[arg(long)]
+        group: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TracksAction {
+    Show {
+        id: i32,
+    },
+    Delete {
+        id: i32,
+        /// Also delete the audio file, otherwise the next scan adds it back
+        #[arg(long)]
+        remove_file: bool,
+    },
+}
+
+/// Runs every command but `serve`, which main owns together with the router
+pub async fn run(command: Command, pool: &VibingPool) -> Result<()> {
+    match command {
+        Command::Serve { .. } => {}
+        Command::Migrate { action } => migrate(action, pool).await,
+        Command::Scan { dir } => {
+            let dirs = match dir {
+                Some(dir) => vec![dir],
+                None => Configuration::get().resource_dirs.clone(),
+            };
+            let report = scan_all(&dirs, pool, |report| {
+                println!(
+                    "scanned {} files: {} added, {} updated, {} errors",
+                    report.scanned, report.added, report.updated, report.errors
+                );
+            })
+            .await?;
+
+            println!(
+                "done: {} scanned, {} added, {} updated, {} missing, {} errors",
+                report.scanned, report.added, report.updated, report.missing, report.errors
+            );
+        }
+        Command::Import {
+            files,
+            title,
+            author,
+            genre,
+        } => {
+            let resource_dir = PathBuf::from(Configuration::get().upload_dir());
+            let overrides = UploadOverrides {
+                title,
+                author,
+                genre,
+            };
+
+            for file in files {
+                let track = import_file(&file, &resource_dir, overrides.clone(), pool).await?;
+                println!(
+                    "{} -> #{} {}",
+                    file.display(),
+                    track.track.id,
+                    track.track.path
+                );
+            }
+        }
+        Command::Export { output } => {
+            let tracks: Vec<ResponseTrack> = TrackFull::get_all(pool)
+                .await?
+                .into_iter()
+                .map(ResponseTrack::from)
+                .collect();
+
+            write_json(&tracks, output.as_deref())?;
+        }
+        Command::Vibes { action } => match action {
+            VibesAction::List => {
+                let mut vibes = Vibe::get_all(pool).await?;
+                vibes.sort_by(|a, b| (&a.group_name, &a.name).cmp(&(&b.group_name, &b.name)));
+
+                for vibe in vibes {
+                    println!(
+                        "{:>4}  {:<12}  {}",
+                        vibe.id,
+                        vibe.group_name.as_deref().unwrap_or("-"),
+                        vibe.name
+                    );
+                }
+            }
+            VibesAction::Add { name, group } => {
+                let vibe = Vibe::create(&name, group.as_deref(), pool).await?;
+                println!("added vibe #{} {}", vibe.id, vibe.name);
+            }
+        },
+        Command::Tracks { action } => match action {
+            TracksAction::Show { id } => {
+                let track = TrackFull::get_by_id(id, pool).await?;
+                write_json(&ResponseTrack::from(track), None)?;
+            }
+            TracksAction::Delete { id, remove_file } => {
+                let track = TrackFull::get_by_id(id, pool).await?;
+                let path = track.track.path.clone();
+                track.remove(pool).await?;
+
+                if remove_file {
+                    tokio::fs::remove_file(&path).await?;
+                }
+                println!("deleted track #{} {}", id, path);
+            }
+        },
+    }
+
+    Ok(())
+}
+
+async fn migrate(action: MigrateAction, pool: &VibingPool) {
+    match action {
+        MigrateAction::Up => {
+            pool.migrate_up().await;
+            println!("database is up to date");
+        }
+        MigrateAction::Down { steps } => {
+            pool.migrate_down(steps).await;
+            println!("reverted {} migrations", steps);
+        }
+        MigrateAction::Status => {
+            for status in pool.migration_status().await {
+                println!(
+                    "{}  {:<8}  {}",
+                    status.version,
+                    if status.applied { "applied" } else { "pending" },
+                    status.description
+                );
+            }
+        }
+    }
+}
+
+fn write_json<T: serde::Serialize>(value: &T, output: Option<&Path>) -> Result<()> {
+    let json = serde_json::to_string_pretty(value)
+        .map_err(|error| AppError::IoError(error.to_string()))?;
+
+    match output {
+        Some(path) => std::fs::write(path, json + "\n")?,
+        None => println!("{}", json),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn the_command_line_is_well_formed() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn parse_reads_subcommands() {
+        let cases = [
+            ("vibing-storage", "None"),
+            ("vibing-storage serve", "Some(Serve { migrate: false })"),
+            (
+                "vibing-storage serve --migrate",
+                "Some(Serve { migrate: true })",
+            ),
+            (
+                "vibing-storage migrate down",
+                "Some(Migrate { action: Down { steps: 1 } })",
+            ),
+            (
+                "vibing-storage migrate down 3",
+                "Some(Migrate { action: Down { steps: 3 } })",
+            ),
+            ("vibing-storage scan", "Some(Scan { dir: None })"),
+            (
+                "vibing-storage scan music",
+                "Some(Scan { dir: Some(\"music\") })",
+            ),
+            (
+                "vibing-storage vibes add rainy --group weather",
+                "Some(Vibes { action: Add { name: \"rainy\", group: Some(\"weather\") } })",
+            ),
+            (
+                "vibing-storage tracks delete 5 --remove-file",
+                "Some(Tracks { action: Delete { id: 5, remove_file: true } })",
+            ),
+        ];
+
+        for (line, expected) in cases {
+            let cli =
+                Cli::try_parse_from(line.split(' ')).unwrap_or_else(|error| panic!("{error}"));
+            assert_eq!(format!("{:?}", cli.command), expected, "{line:?}");
+        }
+    }
+
+    #[test]
+    fn parse_rejects_malformed_commands() {
+        let cases = [
+            "vibing-storage import",
+            "vibing-storage migrate",
+            "vibing-storage migrate down many",
+            "vibing-storage tracks show seven",
+            "vibing-storage play",
+        ];
+
+        for line in cases {
+            assert!(Cli::try_parse_from(line.split(' ')).is_err(), "{line:?}");
+        }
+    }
+}
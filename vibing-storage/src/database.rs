@@ -1,3 +1,5 @@
 pub mod core;
 pub mod entities;
 pub mod error;
+pub mod filter;
+pub mod sort;
@@ -1,44 +1,113 @@
-use sqlx::{Pool, Postgres, Transaction, pool::PoolConnection, postgres::PgPoolOptions};
+use sqlx::{
+    Pool, Postgres, Transaction,
+    migrate::{Migrate, Migrator},
+    pool::PoolConnection,
+    postgres::{PgConnectOptions, PgPoolOptions},
+};
+use std::{str::FromStr, time::Duration};
 
-use crate::{config, database::error::Result};
+use crate::{config::Configuration, database::error::Result};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
 
 #[derive(Clone)]
 pub struct VibingPool {
     connection_pool: Pool<Postgres>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+}
+
 // some Result type is uneccessary because failures here are fatal, which should terminate the service immediately
 
 impl VibingPool {
     pub async fn get() -> Self {
+        let config = &Configuration::get().database;
+
+        // the default of 0.6 misses a single typo in a short word, such as "nujbes"
+        let mut connect_options = PgConnectOptions::from_str(&config.url)
+            .expect("invalid vibing-storage database url")
+            .options([("pg_trgm.word_similarity_threshold", "0.4")]);
+        if let Some(timeout) = config.statement_timeout {
+            connect_options =
+                connect_options.options([("statement_timeout", format!("{}s", timeout))]);
+        }
+
         Self {
             connection_pool: PgPoolOptions::new()
-                .max_connections(20)
-                .connect(&config::database_url())
+                .max_connections(config.max_connections)
+                .min_connections(config.min_connections)
+                .acquire_timeout(Duration::from_secs(config.acquire_timeout))
+                .idle_timeout(Duration::from_secs(config.idle_timeout))
+                .connect_with(connect_options)
                 .await
                 .expect("Failed to connect to vibing-storage database"),
         }
     }
 
-    pub async fn init() -> Self {
-        let pool = Self {
-            connection_pool: PgPoolOptions::new()
-                .max_connections(20)
-                .connect(&config::database_url())
-                .await
-                .expect("Failed to connect to vibing-storage database"),
-        };
+    /// Applies every pending migration
+    pub async fn migrate_up(&self) {
+        MIGRATOR
+            .run(&self.connection_pool)
+            .await
+            .expect("cannot migrate database");
+    }
+
+    /// Reverts the last `steps` applied migrations
+    pub async fn migrate_down(&self, steps: usize) {
+        let mut applied: Vec<i64> = self
+            .migration_status()
+            .await
+            .into_iter()
+            .filter(|status| status.applied)
+            .map(|status| status.version)
+            .collect();
+        applied.sort();
 
-        pool.run_migrations().await;
+        // everything above the target version is reverted
+        let target = match applied.len().checked_sub(steps + 1) {
+            Some(index) => applied[index],
+            None => 0,
+        };
 
-        pool
+        MIGRATOR
+            .undo(&self.connection_pool, target)
+            .await
+            .expect("cannot revert database migration");
     }
 
-    async fn run_migrations(&self) {
-        sqlx::migrate!("./migrations")
-            .run(&self.connection_pool)
+    /// Lists every known migration and whether it has been applied
+    pub async fn migration_status(&self) -> Vec<MigrationStatus> {
+        let mut connection = self
+            .connection_pool
+            .acquire()
             .await
-            .expect("cannot migrate database");
+            .expect("Failed to connect to vibing-storage database");
+
+        connection
+            .ensure_migrations_table()
+            .await
+            .expect("cannot read database migrations");
+        let applied = connection
+            .list_applied_migrations()
+            .await
+            .expect("cannot read database migrations");
+
+        MIGRATOR
+            .iter()
+            .filter(|migration| !migration.migration_type.is_down_migration())
+            .map(|migration| MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                applied: applied
+                    .iter()
+                    .any(|applied| applied.version == migration.version),
+            })
+            .collect()
     }
 
     pub fn get_inner(&self) -> &Pool<Postgres> {
@@ -58,3 +127,21 @@ impl VibingPool {
         Ok(transaction)
     }
 }
+
+#[cfg(test)]
+impl VibingPool {
+    /// Migrates the empty database a `#[sqlx::test(migrations = false)]` is handed
+    pub async fn for_test(connection_pool: Pool<Postgres>) -> Self {
+        // the initial migration expects citext to be installed already
+        sqlx::query("CREATE EXTENSION IF NOT EXISTS citext")
+            .execute(&connection_pool)
+            .await
+            .expect("cannot install citext");
+        MIGRATOR
+            .run(&connection_pool)
+            .await
+            .expect("cannot migrate database");
+
+        Self { connection_pool }
+    }
+}
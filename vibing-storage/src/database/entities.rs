@@ -1,17 +1,45 @@
 use serde::{Deserialize, Serialize};
 
-use crate::database::{core::pool::VibingPool, error::Result};
+use crate::database::{core::pool::VibingPool, entities::facet::Facets, error::Result};
 
+pub mod facet;
+pub mod suggestion;
 pub mod track;
+pub mod track_vibe;
+pub mod upload;
 pub mod vibe;
+pub mod vibe_group;
+pub mod vibe_rule;
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
 pub struct Page<T> {
     pub items: Vec<T>,
     pub total_items: i64,
     pub total_page: i32,
+    /// 0 when the page was reached through a cursor
     pub page_num: i32,
     pub page_size: i32,
+    /// Counts over every matching item, when asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub facets: Option<Facets>,
+    /// Resumes the listing after the last item, absent on the last page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Converts the items, keeping the page position
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            total_items: self.total_items,
+            total_page: self.total_page,
+            page_num: self.page_num,
+            page_size: self.page_size,
+            facets: self.facets,
+            next_cursor: self.next_cursor,
+        }
+    }
 }
 
 #[allow(async_fn_in_trait)]
@@ -0,0 +1,245 @@
+use crate::database::{
+    core::pool::VibingPool, entities::track::push_select_filtered, error::Result,
+    filter::FilterExpr,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, QueryBuilder};
+
+/// Most authors and genres listed, the ones with the most tracks first
+const FACET_LIMIT: i64 = 20;
+
+/// Upper bounds of the duration buckets in seconds, the last bucket has none
+const DURATION_BUCKETS: [i32; 3] = [120, 240, 360];
+
+/// How many of the tracks matching a filter fall in each value, to narrow it down further
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct Facets {
+    pub vibes: Vec<VibeFacet>,
+    pub vibe_groups: Vec<FacetCount>,
+    pub authors: Vec<FacetCount>,
+    pub genres: Vec<FacetCount>,
+    pub durations: Vec<DurationFacet>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct FacetCount {
+    pub value: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct VibeFacet {
+    pub id: i32,
+    pub name: String,
+    pub group_name: Option<String>,
+    pub count: i64,
+}
+
+/// Inclusive bounds in seconds, the same shape as a `duration` filter
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct DurationFacet {
+    pub min: Option<i32>,
+    pub max: Option<i32>,
+    pub count: i64,
+}
+
+#[derive(Debug, FromRow)]
+struct FacetRow {
+    kind: String,
+    id: Option<i32>,
+    value: Option<String>,
+    group_name: Option<String>,
+    count: i64,
+}
+
+impl Facets {
+    /// Counts every facet of the tracks on disk matching `filter` in one query
+    pub async fn for_filter(filter: &FilterExpr, pool: &VibingPool) -> Result<Facets> {
+        let mut query_builder = QueryBuilder::new("WITH matched AS (");
+        push_select_filtered(
+            &mut query_builder,
+            "t.track_id, t.author, t.genre, t.duration",
+            filter,
+        );
+
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .enumerate()
+            .map(|(index, max)| format!("WHEN m.duration < {} THEN {}", max, index))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        query_builder.push(format!(
+            r#")
+            SELECT 'vibe' AS kind, vb.vibe_id AS id, vb.name::TEXT AS value,
+                vb.group_name::TEXT AS group_name, COUNT(*) AS count
+            FROM matched m
+            JOIN tracks_with_vibes twv ON twv.track = m.track_id
+            JOIN vibes vb ON vb.vibe_id = twv.vibe
+            GROUP BY vb.vibe_id
+            UNION ALL
+            SELECT 'vibe_group', NULL, vb.group_name::TEXT, NULL, COUNT(DISTINCT m.track_id)
+            FROM matched m
+            JOIN tracks_with_vibes twv ON twv.track = m.track_id
+            JOIN vibes vb ON vb.vibe_id = twv.vibe
+            WHERE vb.group_name IS NOT NULL
+            GROUP BY vb.group_name
+            UNION ALL
+            (SELECT 'author', NULL, m.author, NULL, COUNT(*)
+            FROM matched m
+            WHERE m.author IS NOT NULL
+            GROUP BY m.author
+            ORDER BY COUNT(*) DESC, m.author
+            LIMIT {limit})
+            UNION ALL
+            (SELECT 'genre', NULL, m.genre, NULL, COUNT(*)
+            FROM matched m
+            WHERE m.genre IS NOT NULL
+            GROUP BY m.genre
+            ORDER BY COUNT(*) DESC, m.genre
+            LIMIT {limit})
+            UNION ALL
+            SELECT 'duration', CASE {bucket} ELSE {last} END, NULL, NULL, COUNT(*)
+            FROM matched m
+            WHERE m.duration IS NOT NULL
+            GROUP BY 2
+            "#,
+            limit = FACET_LIMIT,
+            bucket = bucket,
+            last = DURATION_BUCKETS.len(),
+        ));
+
+        let rows: Vec<FacetRow> = query_builder
+            .build_query_as()
+            .fetch_all(pool.get_inner())
+            .await?;
+
+        Ok(Facets::from_rows(rows))
+    }
+
+    /// Sorts the counted rows into their facet, most tracks first
+    fn from_rows(rows: Vec<FacetRow>) -> Facets {
+        let mut facets = Facets::default();
+        let mut durations = vec![0; DURATION_BUCKETS.len() + 1];
+
+        for row in rows {
+            match row.kind.as_str() {
+                "vibe" => facets.vibes.push(VibeFacet {
+                    id: row.id.unwrap_or_default(),
+                    name: row.value.unwrap_or_default(),
+                    group_name: row.group_name,
+                    count: row.count,
+                }),
+                "vibe_group" => facets.vibe_groups.push(FacetCount {
+                    value: row.value.unwrap_or_default(),
+                    count: row.count,
+                }),
+                "author" => facets.authors.push(FacetCount {
+                    value: row.value.unwrap_or_default(),
+                    count: row.count,
+                }),
+                "genre" => facets.genres.push(FacetCount {
+                    value: row.value.unwrap_or_default(),
+                    count: row.count,
+                }),
+                "duration" => {
+                    if let Some(count) = row.id.and_then(|index| durations.get_mut(index as usize))
+                    {
+                        *count = row.count;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        facets
+            .vibes
+            .sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+        facets
+            .vibe_groups
+            .sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+
+        // every bucket is listed, the empty ones included, so they keep their place
+        facets.durations = durations
+            .into_iter()
+            .enumerate()
+            .map(|(index, count)| DurationFacet {
+                min: index
+                    .checked_sub(1)
+                    .map(|previous| DURATION_BUCKETS[previous]),
+                max: DURATION_BUCKETS.get(index).map(|max| max - 1),
+                count,
+            })
+            .collect();
+
+        facets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(kind: &str, id: Option<i32>, value: Option<&str>, count: i64) -> FacetRow {
+        FacetRow {
+            kind: kind.to_string(),
+            id,
+            value: value.map(str::to_string),
+            group_name: None,
+            count,
+        }
+    }
+
+    #[test]
+    fn from_rows_sorts_rows_into_facets() {
+        let facets = Facets::from_rows(vec![
+            row("vibe", Some(2), Some("rainy"), 1),
+            row("vibe", Some(1), Some("calm"), 3),
+            row("vibe", Some(3), Some("chill"), 3),
+            row("vibe_group", None, Some("weather"), 1),
+            row("vibe_group", None, Some("mood"), 4),
+            row("author", None, Some("Nujabes"), 2),
+            row("genre", None, Some("Jazz"), 2),
+            row("unknown", None, Some("ignored"), 9),
+        ]);
+
+        let vibes: Vec<_> = facets.vibes.iter().map(|vibe| vibe.id).collect();
+        let groups: Vec<_> = facets
+            .vibe_groups
+            .iter()
+            .map(|group| group.value.as_str())
+            .collect();
+
+        assert_eq!(vibes, [1, 3, 2]);
+        assert_eq!(groups, ["mood", "weather"]);
+        assert_eq!(
+            facets.authors,
+            [FacetCount {
+                value: String::from("Nujabes"),
+                count: 2
+            }]
+        );
+        assert_eq!(facets.genres.len(), 1);
+    }
+
+    #[test]
+    fn from_rows_lists_every_duration_bucket() {
+        let facets = Facets::from_rows(vec![
+            row("duration", Some(1), None, 5),
+            row("duration", Some(3), None, 2),
+            // out of range buckets are dropped
+            row("duration", Some(9), None, 7),
+        ]);
+
+        let bucket = |min, max, count| DurationFacet { min, max, count };
+        assert_eq!(
+            facets.durations,
+            [
+                bucket(None, Some(119), 0),
+                bucket(Some(120), Some(239), 5),
+                bucket(Some(240), Some(359), 0),
+                bucket(Some(360), None, 2),
+            ]
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use crate::database::{core::pool::VibingPool, error::Result};
+use serde::{Deserialize, Serialize};
+
+/// A completion and the number of tracks on disk it leads to
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct Suggestion {
+    pub value: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct VibeSuggestion {
+    pub id: i32,
+    pub name: String,
+    pub group_name: Option<String>,
+    pub count: i64,
+}
+
+/// Completions of a prefix by kind, the most common first
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct Suggestions {
+    pub titles: Vec<Suggestion>,
+    pub authors: Vec<Suggestion>,
+    pub genres: Vec<Suggestion>,
+    pub vibes: Vec<VibeSuggestion>,
+}
+
+// The prefix is matched as a range of the byte-wise ordering rather than with LIKE, which
+// the cached generic plan of a prepared statement cannot serve from an index.
+// chr(1114111) is the last code point, it sorts after anything following the prefix.
+
+impl Suggestions {
+    /// Up to `limit` values of each kind starting with `prefix`, ignoring case and accents
+    pub async fn by_prefix(prefix: &str, limit: i64, pool: &VibingPool) -> Result<Suggestions> {
+        let titles = sqlx::query_as!(
+            Suggestion,
+            r#"
+            SELECT title AS "value!", COUNT(*) AS "count!"
+            FROM tracks
+            WHERE NOT missing
+                AND LOWER(fold_accents(title)) ~>=~ LOWER(fold_accents($1))
+                AND LOWER(fold_accents(title)) ~<~ (LOWER(fold_accents($1)) || chr(1114111))
+            GROUP BY title
+            ORDER BY COUNT(*) DESC, SUM(download_count) DESC, title
+            LIMIT $2
+            "#,
+            prefix,
+            limit
+        )
+        .fetch_all(pool.get_inner())
+        .await?;
+
+        let authors = sqlx::query_as!(
+            Suggestion,
+            r#"
+            SELECT author AS "value!", COUNT(*) AS "count!"
+            FROM tracks
+            WHERE NOT missing
+                AND LOWER(fold_accents(author)) ~>=~ LOWER(fold_accents($1))
+                AND LOWER(fold_accents(author)) ~<~ (LOWER(fold_accents($1)) || chr(1114111))
+            GROUP BY author
+            ORDER BY COUNT(*) DESC, author
+            LIMIT $2
+            "#,
+            prefix,
+            limit
+        )
+        .fetch_all(pool.get_inner())
+        .await?;
+
+        let genres = sqlx::query_as!(
+            Suggestion,
+            r#"
+            SELECT genre AS "value!", COUNT(*) AS "count!"
+            FROM tracks
+            WHERE NOT missing
+                AND LOWER(fold_accents(genre)) ~>=~ LOWER(fold_accents($1))
+                AND LOWER(fold_accents(genre)) ~<~ (LOWER(fold_accents($1)) || chr(1114111))
+            GROUP BY genre
+            ORDER BY COUNT(*) DESC, genre
+            LIMIT $2
+            "#,
+            prefix,
+            limit
+        )
+        .fetch_all(pool.get_inner())
+        .await?;
+
+        // vibes nobody tagged a track with yet are still worth completing
+        let vibes = sqlx::query_as!(
+            VibeSuggestion,
+            r#"
+            SELECT
+                vb.vibe_id AS id, vb.name AS "name: String", vb.group_name AS "group_name: String",
+                COUNT(t.track_id) AS "count!"
+            FROM vibes vb
+            LEFT JOIN tracks_with_vibes twv ON twv.vibe = vb.vibe_id
+            LEFT JOIN tracks t ON t.track_id = twv.track AND NOT t.missing
+            WHERE LOWER(fold_accents(vb.name::TEXT)) ~>=~ LOWER(fold_accents($1))
+                AND LOWER(fold_accents(vb.name::TEXT)) ~<~ (LOWER(fold_accents($1)) || chr(1114111))
+            GROUP BY vb.vibe_id
+            ORDER BY COUNT(t.track_id) DESC, vb.name
+            LIMIT $2
+            "#,
+            prefix,
+            limit
+        )
+        .fetch_all(pool.get_inner())
+        .await?;
+
+        Ok(Suggestions {
+            titles,
+            authors,
+            genres,
+            vibes,
+        })
+    }
+}
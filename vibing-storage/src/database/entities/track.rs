@@ -1,10 +1,15 @@
 use crate::database::{
     core::pool::VibingPool,
-    entities::{Page, Paginate, vibe::Vibe},
-    error::Result,
+    entities::{
+        Page, Paginate, facet::Facets, vibe::Vibe, vibe_group::check_exclusive, vibe_rule::VibeRule,
+    },
+    error::{DatabaseError, Result},
+    filter::FilterExpr,
+    sort::{Cursor, TrackOrder},
 };
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, QueryBuilder};
+use sqlx::{FromRow, Postgres, QueryBuilder, postgres::PgRow};
 use std::collections::HashSet;
 
 pub type TrackID = i32;
@@ -21,6 +26,19 @@ pub struct Track {
     pub vote_count: i32,
     pub total_rating: i64,
     pub download_count: i32,
+    /// Streams started from the beginning of the file
+    pub play_count: i32,
+    pub codec: Option<String>,
+    pub container: Option<String>,
+    pub bitrate: Option<i32>,
+    pub sample_rate: Option<i32>,
+    pub channels: Option<i16>,
+    pub bit_depth: Option<i16>,
+    pub file_size: Option<i64>,
+    pub modified_at: Option<DateTime<Utc>>,
+    /// Set when the file disappeared from disk during the last scan
+    pub missing: bool,
+    pub added_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq, FromRow)]
@@ -38,8 +56,18 @@ pub struct TrackFullPatch {
     pub duration: Option<i32>,
     pub new_vote: Option<u8>,
     pub new_download: bool,
+    pub new_play: bool,
     pub add_vibes: Option<Vec<VibeID>>,
     pub remove_vibes: Option<Vec<VibeID>>,
+    pub codec: Option<String>,
+    pub container: Option<String>,
+    pub bitrate: Option<i32>,
+    pub sample_rate: Option<i32>,
+    pub channels: Option<i16>,
+    pub bit_depth: Option<i16>,
+    pub file_size: Option<i64>,
+    pub modified_at: Option<DateTime<Utc>>,
+    pub missing: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
@@ -49,41 +77,144 @@ pub struct TrackMetadata {
     pub author: Option<String>,
     pub genre: Option<String>,
     pub duration: Option<i32>,
+    pub codec: Option<String>,
+    pub container: Option<String>,
+    pub bitrate: Option<i32>,
+    pub sample_rate: Option<i32>,
+    pub channels: Option<i16>,
+    pub bit_depth: Option<i16>,
+    pub file_size: Option<i64>,
+    pub modified_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
 pub struct TrackFilter {
     pub pattern: Option<String>,
     pub author: Option<String>,
     pub vibes: Option<Vec<VibeID>>,
+    /// How `vibes` is matched, any of them by default
+    pub vibe_match: Option<VibeMatch>,
+    /// A vibe of `vibes` only counts when its confidence is at least this, from 0 to 1
+    pub min_vibe_confidence: Option<f64>,
+    /// Tracks with any of these vibes are left out
+    pub exclude_vibes: Option<Vec<VibeID>>,
+    /// Tracks need at least one vibe of any of these groups
+    pub vibe_groups: Option<Vec<String>>,
+    /// Tracks with a vibe of any of these groups are left out
+    pub exclude_vibe_groups: Option<Vec<String>>,
     pub limit: Option<i32>,
     pub order_by: Option<String>,
+    pub lossless: Option<bool>,
+    /// In kbps
+    pub min_bitrate: Option<i32>,
+    pub max_bitrate: Option<i32>,
+    pub min_sample_rate: Option<i32>,
+    pub min_bit_depth: Option<i16>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VibeMatch {
+    /// The track has at least one of the vibes
+    #[default]
+    Any,
+    /// The track has every one of the vibes
+    All,
+    /// The track has none of the vibes
+    None,
+}
+
+/// What the scanner needs to know to tell whether a file changed on disk
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq, FromRow)]
+pub struct TrackFileState {
+    pub id: TrackID,
+    pub path: String,
+    pub file_size: Option<i64>,
+    pub modified_at: Option<DateTime<Utc>>,
+    pub missing: bool,
+}
+
+impl From<&Track> for TrackFileState {
+    fn from(track: &Track) -> Self {
+        TrackFileState {
+            id: track.id,
+            path: track.path.clone(),
+            file_size: track.file_size,
+            modified_at: track.modified_at,
+            missing: track.missing,
+        }
+    }
+}
+
+/// Codecs that keep every sample of the original recording
+pub const LOSSLESS_CODECS: [&str; 3] = ["flac", "alac", "pcm"];
+
+impl From<TrackMetadata> for TrackFullPatch {
+    /// A patch that brings a track in line with freshly read file metadata
+    fn from(metadata: TrackMetadata) -> Self {
+        TrackFullPatch {
+            path: Some(metadata.path),
+            title: metadata.title,
+            author: metadata.author,
+            genre: metadata.genre,
+            duration: metadata.duration,
+            codec: metadata.codec,
+            container: metadata.container,
+            bitrate: metadata.bitrate,
+            sample_rate: metadata.sample_rate,
+            channels: metadata.channels,
+            bit_depth: metadata.bit_depth,
+            file_size: metadata.file_size,
+            modified_at: metadata.modified_at,
+            missing: Some(false),
+            ..Default::default()
+        }
+    }
 }
 
 impl TrackFull {
+    /// Registers a track and tags it following the vibe rules, all or nothing
     pub async fn create_from(metadata: TrackMetadata, pool: &VibingPool) -> Result<TrackFull> {
+        let mut tx = pool.transaction().await?;
+
         let track = sqlx::query_as!(
             Track,
             r#" 
-            INSERT INTO tracks (path, title, author, genre, duration)
-            VALUES ($1, $2, $3, $4, $5)
+            INSERT INTO tracks (
+                path, title, author, genre, duration, codec, container,
+                bitrate, sample_rate, channels, bit_depth, file_size, modified_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
             RETURNING 
                 track_id AS id, path, title, author, genre,
-                duration, vote_count, total_rating, download_count
+                duration, vote_count, total_rating, download_count, play_count,
+                codec, container, bitrate, sample_rate,
+                channels, bit_depth, file_size, modified_at, missing, added_at
             "#,
             metadata.path,
             metadata.title,
             metadata.author,
             metadata.genre,
-            metadata.duration
+            metadata.duration,
+            metadata.codec,
+            metadata.container,
+            metadata.bitrate,
+            metadata.sample_rate,
+            metadata.channels,
+            metadata.bit_depth,
+            metadata.file_size,
+            metadata.modified_at
         )
-        .fetch_one(pool.get_inner())
+        .fetch_one(&mut *tx)
         .await?;
 
-        Ok(TrackFull {
-            track,
-            vibes: Vec::new(),
-        })
+        VibeRule::apply_in(Some(&[track.id]), false, &mut tx).await?;
+
+        tx.commit().await?;
+
+        let vibes = Vibe::get_by_track_id(track.id, pool).await?;
+
+        Ok(TrackFull { track, vibes })
     }
 
     pub async fn get_by_id(id: i32, pool: &VibingPool) -> Result<TrackFull> {
@@ -92,7 +223,9 @@ impl TrackFull {
             r#" 
             SELECT
                 track_id AS id, path, title, author, genre,
-                duration, vote_count, total_rating, download_count
+                duration, vote_count, total_rating, download_count, play_count,
+                codec, container, bitrate, sample_rate,
+                channels, bit_depth, file_size, modified_at, missing, added_at
             FROM tracks
             WHERE track_id = $1
             "#,
@@ -112,7 +245,9 @@ impl TrackFull {
             r#" 
             SELECT
                 track_id AS id, path, title, author, genre,
-                duration, vote_count, total_rating, download_count
+                duration, vote_count, total_rating, download_count, play_count,
+                codec, container, bitrate, sample_rate,
+                channels, bit_depth, file_size, modified_at, missing, added_at
             FROM tracks
             WHERE title = $1
             "#,
@@ -126,91 +261,76 @@ impl TrackFull {
         Ok(TrackFull { track, vibes })
     }
 
-    pub async fn get_all(pool: &VibingPool) -> Result<Vec<TrackFull>> {
-        let tracks: Vec<Track> = sqlx::query_as!(
+    pub async fn get_by_path(path: &str, pool: &VibingPool) -> Result<Option<TrackFull>> {
+        let track = sqlx::query_as!(
             Track,
             r#" 
             SELECT
                 track_id AS id, path, title, author, genre,
-                duration, vote_count, total_rating, download_count
+                duration, vote_count, total_rating, download_count, play_count,
+                codec, container, bitrate, sample_rate,
+                channels, bit_depth, file_size, modified_at, missing, added_at
             FROM tracks
-            "#
+            WHERE path = $1
+            "#,
+            path
         )
-        .fetch_all(pool.get_inner())
+        .fetch_optional(pool.get_inner())
         .await?;
 
-        if tracks.is_empty() {
-            return Ok(Vec::new());
-        }
+        let Some(track) = track else {
+            return Ok(None);
+        };
 
-        let track_ids: Vec<i32> = tracks.iter().map(|track| track.id).collect();
-        let mut vibes_map = Vibe::get_by_track_ids(&track_ids, pool).await?;
-
-        let full_tracks = tracks
-            .into_iter()
-            .map(|track| {
-                let vibes = vibes_map.remove(&track.id).unwrap_or_default();
-                TrackFull { track, vibes }
-            })
-            .collect();
+        let vibes = Vibe::get_by_track_id(track.id, pool).await?;
 
-        Ok(full_tracks)
+        Ok(Some(TrackFull { track, vibes }))
     }
 
-    pub async fn get_by_filter(filter: TrackFilter, pool: &VibingPool) -> Result<Vec<TrackFull>> {
-        let mut query_builder: QueryBuilder<sqlx::Postgres> = QueryBuilder::new(
+    pub async fn get_all(pool: &VibingPool) -> Result<Vec<TrackFull>> {
+        let tracks: Vec<Track> = sqlx::query_as!(
+            Track,
             r#" 
-            SELECT DISTINCT
-                t.track_id AS id, t.path, t.title, t.author, t.genre,
-                t.duration, t.vote_count, t.total_rating, t.download_count
-            FROM tracks t
-            "#,
-        );
-
-        if let Some(ref vibes) = filter.vibes {
-            if !vibes.is_empty() {
-                query_builder.push(
-                    r#" 
-                    JOIN tracks_with_vibes twv ON t.track_id = twv.track
-                    JOIN vibes vb ON twv.vibe = vb.vibe_id
-                    "#,
-                );
-            }
-        }
+            SELECT
+                track_id AS id, path, title, author, genre,
+                duration, vote_count, total_rating, download_count, play_count,
+                codec, container, bitrate, sample_rate,
+                channels, bit_depth, file_size, modified_at, missing, added_at
+            FROM tracks
+            "#
+        )
+        .fetch_all(pool.get_inner())
+        .await?;
 
-        query_builder.push(" WHERE TRUE");
+        Self::with_vibes(tracks, pool).await
+    }
 
-        if let Some(pattern) = filter.pattern {
-            query_builder.push(" AND (t.title ILIKE ");
-            query_builder.push_bind(format!("%{}%", pattern));
-            query_builder.push(" OR t.author ILIKE ");
-            query_builder.push_bind(format!("%{}%", pattern));
-            query_builder.push(") ");
-        }
+    /// Returns the file state of every track stored below `dir`
+    pub async fn get_file_states(dir: &str, pool: &VibingPool) -> Result<Vec<TrackFileState>> {
+        let prefix = format!("{}%", dir.replace('%', "\\%").replace('_', "\\_"));
 
-        if let Some(author) = filter.author {
-            query_builder.push(" AND t.author = ").push_bind(author);
-        }
+        Ok(sqlx::query_as!(
+            TrackFileState,
+            r#"
+            SELECT track_id AS id, path, file_size, modified_at, missing
+            FROM tracks
+            WHERE path LIKE $1
+            "#,
+            prefix
+        )
+        .fetch_all(pool.get_inner())
+        .await?)
+    }
 
-        if let Some(vibes) = &filter.vibes {
-            if !vibes.is_empty() {
-                query_builder
-                    .push(" AND vb.name = ANY(")
-                    .push_bind(vibes)
-                    .push(")");
-            }
-        }
+    pub async fn get_by_filter(
+        filter: TrackFilter,
+        order: &TrackOrder,
+        pool: &VibingPool,
+    ) -> Result<Vec<TrackFull>> {
+        let filter_expr = FilterExpr::from(&filter);
+        let mut query_builder = select_filtered(TRACK_COLUMNS, &filter_expr);
 
-        if let Some(order_by) = filter.order_by {
-            let valid_columns = ["rating", "most download"];
-            if order_by == valid_columns[0] {
-                query_builder.push(" ORDER BY (CASE WHEN t.vote_count > 0 THEN t.total_rating::FLOAT / t.vote_count ELSE 0 END) DESC");
-            } else if order_by == valid_columns[1] {
-                query_builder.push(" ORDER BY t.download_count DESC");
-            } else {
-                // invalid query, reject to prevent sql injection
-            }
-        }
+        order.push_order_by(&mut query_builder, &filter_expr);
 
         if let Some(limit) = filter.limit {
             query_builder.push(" LIMIT ").push_bind(limit as i64);
@@ -221,6 +341,11 @@ impl TrackFull {
             .fetch_all(pool.get_inner())
             .await?;
 
+        Self::with_vibes(tracks, pool).await
+    }
+
+    /// Attaches their vibes to `tracks`, keeping their order
+    async fn with_vibes(tracks: Vec<Track>, pool: &VibingPool) -> Result<Vec<TrackFull>> {
         if tracks.is_empty() {
             return Ok(Vec::new());
         }
@@ -244,6 +369,22 @@ impl TrackFull {
         patch: TrackFullPatch,
         pool: &VibingPool,
     ) -> Result<TrackFull> {
+        let mut tx = pool.transaction().await?;
+
+        // patches of the same track wait for each other, so the exclusive group check
+        // below sees every vibe the track ends up with
+        sqlx::query!(
+            "
+            SELECT track_id
+            FROM tracks
+            WHERE track_id = $1
+            FOR UPDATE
+            ",
+            self.track.id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
         // --- 1. Handle track metadata updates (path, title, author, etc.) ---
         let mut update_query: QueryBuilder<sqlx::Postgres> =
             QueryBuilder::new("UPDATE tracks SET ");
@@ -306,62 +447,203 @@ impl TrackFull {
             has_updates = true;
         }
 
+        if patch.new_play {
+            separated.push("play_count = play_count + 1");
+            self.track.play_count += 1;
+            has_updates = true;
+        }
+
+        if let Some(codec) = patch.codec {
+            separated
+                .push("codec = ")
+                .push_bind_unseparated(codec.clone());
+            self.track.codec = Some(codec);
+            has_updates = true;
+        }
+
+        if let Some(container) = patch.container {
+            separated
+                .push("container = ")
+                .push_bind_unseparated(container.clone());
+            self.track.container = Some(container);
+            has_updates = true;
+        }
+
+        if let Some(bitrate) = patch.bitrate {
+            separated.push("bitrate = ").push_bind_unseparated(bitrate);
+            self.track.bitrate = Some(bitrate);
+            has_updates = true;
+        }
+
+        if let Some(sample_rate) = patch.sample_rate {
+            separated
+                .push("sample_rate = ")
+                .push_bind_unseparated(sample_rate);
+            self.track.sample_rate = Some(sample_rate);
+            has_updates = true;
+        }
+
+        if let Some(channels) = patch.channels {
+            separated
+                .push("channels = ")
+                .push_bind_unseparated(channels);
+            self.track.channels = Some(channels);
+            has_updates = true;
+        }
+
+        if let Some(bit_depth) = patch.bit_depth {
+            separated
+                .push("bit_depth = ")
+                .push_bind_unseparated(bit_depth);
+            self.track.bit_depth = Some(bit_depth);
+            has_updates = true;
+        }
+
+        if let Some(file_size) = patch.file_size {
+            separated
+                .push("file_size = ")
+                .push_bind_unseparated(file_size);
+            self.track.file_size = Some(file_size);
+            has_updates = true;
+        }
+
+        if let Some(modified_at) = patch.modified_at {
+            separated
+                .push("modified_at = ")
+                .push_bind_unseparated(modified_at);
+            self.track.modified_at = Some(modified_at);
+            has_updates = true;
+        }
+
+        if let Some(missing) = patch.missing {
+            separated.push("missing = ").push_bind_unseparated(missing);
+            self.track.missing = missing;
+            has_updates = true;
+        }
+
         // Only execute update if there were changes to track metadata
         if has_updates {
             update_query
                 .push(" WHERE track_id = ")
                 .push_bind(self.track.id);
-            update_query.build().execute(pool.get_inner()).await?;
+            update_query.build().execute(&mut *tx).await?;
         }
 
         // --- 2. Handle vibe removal ---
-        if let Some(remove_vibes) = patch.remove_vibes {
-            if !remove_vibes.is_empty() {
-                let mut remove_query: QueryBuilder<sqlx::Postgres> =
-                    QueryBuilder::new("DELETE FROM tracks_with_vibes WHERE track = ");
+        if let Some(mut remove_vibes) = patch.remove_vibes
+            && !remove_vibes.is_empty()
+        {
+            remove_vibes.sort_unstable();
+            remove_vibes.dedup();
+
+            // curated vibes are only taken off once unlocked
+            let locked = sqlx::query_scalar!(
+                "
+                SELECT vb.name
+                FROM tracks_with_vibes twv
+                JOIN vibes vb ON vb.vibe_id = twv.vibe
+                WHERE twv.track = $1 AND twv.vibe = ANY($2) AND twv.locked
+                ORDER BY vb.name
+                LIMIT 1
+                ",
+                self.track.id,
+                &remove_vibes
+            )
+            .fetch_optional(&mut *tx)
+            .await?;
 
-                remove_query.push_bind(self.track.id);
-                remove_query.push(" AND vibe IN (");
+            if let Some(name) = locked {
+                return Err(DatabaseError::LockedVibe(name));
+            }
 
-                let mut separated = remove_query.separated(", ");
+            let mut remove_query: QueryBuilder<sqlx::Postgres> =
+                QueryBuilder::new("DELETE FROM tracks_with_vibes WHERE track = ");
 
-                for vibe_id in &remove_vibes {
-                    separated.push_bind(vibe_id);
-                }
-                remove_query.push(")");
+            remove_query.push_bind(self.track.id);
+            remove_query.push(" AND NOT locked AND vibe IN (");
 
-                remove_query.build().execute(pool.get_inner()).await?;
+            let mut separated = remove_query.separated(", ");
 
-                // Update local state
-                let remove_set: HashSet<i32> = remove_vibes.into_iter().collect();
-                self.vibes.retain(|v| !remove_set.contains(&(v.id)));
+            for vibe_id in &remove_vibes {
+                separated.push_bind(vibe_id);
             }
+            remove_query.push(")");
+
+            remove_query.build().execute(&mut *tx).await?;
+
+            // the vibe rules leave them off from now on
+            sqlx::query!(
+                "
+                INSERT INTO suppressed_vibes (track, vibe)
+                SELECT $1, vibe_id FROM vibes WHERE vibe_id = ANY($2)
+                ON CONFLICT DO NOTHING
+                ",
+                self.track.id,
+                &remove_vibes
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            // Update local state
+            let remove_set: HashSet<i32> = remove_vibes.into_iter().collect();
+            self.vibes.retain(|v| !remove_set.contains(&(v.id)));
         }
 
         // --- 3. Handle vibe addition ---
-        if let Some(add_vibes) = patch.add_vibes {
-            if !add_vibes.is_empty() {
-                let mut add_query: QueryBuilder<sqlx::Postgres> =
-                    QueryBuilder::new("INSERT INTO tracks_with_vibes (track, vibe) ");
-
-                add_query.push_values(add_vibes.iter(), |mut b, vibe_id| {
-                    b.push_bind(self.track.id).push_bind(vibe_id);
-                });
-
-                add_query.build().execute(pool.get_inner()).await?;
-
-                let added_vibes = sqlx::query_as!(
-                    Vibe,
-                    r#"SELECT vibe_id as id, name, group_name FROM vibes WHERE vibe_id = ANY($1)"#,
-                    &add_vibes
-                )
-                .fetch_all(pool.get_inner())
-                .await?;
-
-                self.vibes.extend(added_vibes);
-            }
+        if let Some(mut add_vibes) = patch.add_vibes
+            && !add_vibes.is_empty()
+        {
+            // an upsert cannot touch the same row twice, a vibe listed twice is added once
+            add_vibes.sort_unstable();
+            add_vibes.dedup();
+
+            let mut add_query: QueryBuilder<sqlx::Postgres> =
+                QueryBuilder::new("INSERT INTO tracks_with_vibes (track, vibe) ");
+
+            add_query.push_values(add_vibes.iter(), |mut b, vibe_id| {
+                b.push_bind(self.track.id).push_bind(vibe_id);
+            });
+
+            // a vibe a rule added becomes a hand-picked one, the rule no longer takes it back
+            add_query.push(" ON CONFLICT (track, vibe) DO UPDATE SET rule = NULL");
+
+            add_query.build().execute(&mut *tx).await?;
+
+            sqlx::query!(
+                "
+                DELETE FROM suppressed_vibes
+                WHERE track = $1 AND vibe = ANY($2)
+                ",
+                self.track.id,
+                &add_vibes
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            let added_vibes = sqlx::query_as!(
+                Vibe,
+                r#"SELECT vibe_id as id, name, group_name FROM vibes WHERE vibe_id = ANY($1)"#,
+                &add_vibes
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let group_names: Vec<String> = added_vibes
+                .iter()
+                .filter_map(|vibe| vibe.group_name.clone())
+                .collect();
+            check_exclusive(&group_names, Some(self.track.id), &mut tx).await?;
+
+            let carried: HashSet<i32> = self.vibes.iter().map(|vibe| vibe.id).collect();
+            self.vibes.extend(
+                added_vibes
+                    .into_iter()
+                    .filter(|vibe| !carried.contains(&vibe.id)),
+            );
         }
 
+        tx.commit().await?;
+
         Ok(self)
     }
 
@@ -408,149 +690,147 @@ impl TrackFull {
     }
 }
 
+/// Every column of `Track`, selected from `tracks t`
+const TRACK_COLUMNS: &str = r#"
+    t.track_id AS id, t.path, t.title, t.author, t.genre,
+    t.duration, t.vote_count, t.total_rating, t.download_count, t.play_count,
+    t.codec, t.container, t.bitrate, t.sample_rate,
+    t.channels, t.bit_depth, t.file_size, t.modified_at, t.missing, t.added_at
+"#;
+
+/// Starts a query selecting `columns` from the tracks on disk that match `filter`.
+/// Counting, paging and facets all go through here so they always agree.
+fn select_filtered(columns: &str, filter: &FilterExpr) -> QueryBuilder<'static, Postgres> {
+    let mut query_builder = QueryBuilder::new("");
+    push_select_filtered(&mut query_builder, columns, filter);
+
+    query_builder
+}
+
+/// Appends the query of `select_filtered`, to nest it in a larger one
+pub(crate) fn push_select_filtered(
+    query_builder: &mut QueryBuilder<Postgres>,
+    columns: &str,
+    filter: &FilterExpr,
+) {
+    query_builder.push("SELECT ").push(columns);
+    push_from_filtered(query_builder, filter);
+}
+
+/// Appends the `FROM` and `WHERE` clauses of `select_filtered`, for selects that add
+/// computed columns of their own
+fn push_from_filtered(query_builder: &mut QueryBuilder<Postgres>, filter: &FilterExpr) {
+    query_builder.push(" FROM tracks t WHERE NOT t.missing AND ");
+    filter.push_sql(query_builder);
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct TrackPaginationParams {
     pub page_num: i32,
     pub page_size: i32,
-    pub filter: TrackFilter,
+    pub filter: FilterExpr,
+    pub order: TrackOrder,
+    /// Resumes after the last track of a previous page instead of skipping
+    /// `page_num - 1` pages, `page_num` is then ignored
+    pub cursor: Option<Cursor>,
+    /// Caps the size of the page
+    pub limit: Option<i32>,
+    /// Also count the facets of every matching track
+    pub facets: bool,
 }
 
 impl Paginate<TrackPaginationParams> for TrackFull {
     async fn page(params: &TrackPaginationParams, pool: &VibingPool) -> Result<Page<Self>> {
-        // --- 1. Build the base query for both counting and fetching data ---
-        let mut count_query_builder: QueryBuilder<sqlx::Postgres> =
-            QueryBuilder::new("SELECT COUNT(DISTINCT t.track_id) as count FROM tracks t");
-        let mut query_builder: QueryBuilder<sqlx::Postgres> = QueryBuilder::new(
-            r#" 
-            SELECT DISTINCT
-                t.track_id AS id, t.path, t.title, t.author, t.genre,
-                t.duration, t.vote_count, t.total_rating, t.download_count
-            FROM tracks t
-            "#,
-        );
-
-        if let Some(vibes) = &params.filter.vibes {
-            if !vibes.is_empty() {
-                let join_sql = r#" 
-                    JOIN tracks_with_vibes twv ON t.track_id = twv.track
-                    JOIN vibes vb ON twv.vibe = vb.vibe_id
-                    "#;
-                count_query_builder.push(join_sql);
-                query_builder.push(join_sql);
-            }
-        }
-
-        count_query_builder.push(" WHERE TRUE");
-        query_builder.push(" WHERE TRUE");
-
-        if let Some(pattern) = &params.filter.pattern {
-            let pattern_sql = format!("%{}%", pattern);
-            count_query_builder
-                .push(" AND (t.title ILIKE ")
-                .push_bind(pattern_sql.clone())
-                .push(" OR t.author ILIKE ")
-                .push_bind(pattern_sql.clone())
-                .push(") ");
-            query_builder
-                .push(" AND (t.title ILIKE ")
-                .push_bind(pattern_sql.clone())
-                .push(" OR t.author ILIKE ")
-                .push_bind(pattern_sql)
-                .push(") ");
-        }
-
-        if let Some(author) = &params.filter.author {
-            count_query_builder
-                .push(" AND t.author = ")
-                .push_bind(author.clone());
-            query_builder
-                .push(" AND t.author = ")
-                .push_bind(author.clone());
-        }
-
-        if let Some(vibes) = &params.filter.vibes {
-            if !vibes.is_empty() {
-                count_query_builder
-                    .push(" AND vb.name = ANY(")
-                    .push_bind(vibes.clone())
-                    .push(")");
-                query_builder
-                    .push(" AND vb.name = ANY(")
-                    .push_bind(vibes.clone())
-                    .push(")");
-            }
-        }
-
-        // --- 2. Execute the COUNT query ---
         #[derive(Debug, FromRow)]
         struct Count {
             count: i64,
         }
 
-        let total_items = count_query_builder
+        let total_items = select_filtered("COUNT(*) AS count", &params.filter)
             .build_query_as::<Count>()
             .fetch_one(pool.get_inner())
             .await?
             .count;
 
+        let facets = if params.facets {
+            Some(Facets::for_filter(&params.filter, pool).await?)
+        } else {
+            None
+        };
+
+        let page_num = if params.cursor.is_some() {
+            0
+        } else {
+            params.page_num
+        };
+
         if total_items == 0 {
-            return Ok(Page::default());
+            return Ok(Page {
+                page_num,
+                page_size: params.page_size,
+                facets,
+                ..Default::default()
+            });
         }
 
-        // --- 3. Apply ordering and pagination to the main query ---
-        if let Some(order_by) = &params.filter.order_by {
-            let valid_columns = ["rating", "most download"];
-            if order_by == valid_columns[0] {
-                query_builder.push(" ORDER BY (CASE WHEN t.vote_count > 0 THEN t.total_rating::FLOAT / t.vote_count ELSE 0 END) DESC");
-            } else if order_by == valid_columns[1] {
-                query_builder.push(" ORDER BY t.download_count DESC");
-            }
+        let mut query_builder = QueryBuilder::new("SELECT ");
+        query_builder.push(TRACK_COLUMNS);
+        params
+            .order
+            .push_columns(&mut query_builder, &params.filter);
+        push_from_filtered(&mut query_builder, &params.filter);
+
+        if let Some(cursor) = &params.cursor {
+            query_builder.push(" AND ");
+            params
+                .order
+                .push_after(&mut query_builder, &params.filter, cursor);
         }
 
+        params
+            .order
+            .push_order_by(&mut query_builder, &params.filter);
+
         let limit = params
-            .filter
             .limit
             .map_or(params.page_size, |l| l.min(params.page_size));
-        query_builder.push(" LIMIT ").push_bind(limit);
-
-        let offset = (params.page_num - 1) * params.page_size;
-        query_builder.push(" OFFSET ").push_bind(offset);
-
-        // --- 4. Execute the main query to get the items for the page ---
-        let tracks: Vec<Track> = query_builder
-            .build_query_as()
-            .fetch_all(pool.get_inner())
-            .await?;
+        // one more track tells whether a next page exists
+        query_builder.push(" LIMIT ").push_bind(limit + 1);
 
-        if tracks.is_empty() {
-            println!("empty case");
-            return Ok(Page {
-                total_items,
-                total_page: (total_items as f64 / params.page_size as f64).ceil() as i32,
-                page_num: params.page_num,
-                page_size: params.page_size,
-                ..Default::default()
-            });
+        if params.cursor.is_none() {
+            // a large page number times the page size does not fit an i32
+            let offset = (i64::from(params.page_num) - 1) * i64::from(params.page_size);
+            query_builder.push(" OFFSET ").push_bind(offset);
         }
 
-        // --- 5. Fetch related vibes and construct the final TrackFull objects ---
-        let track_ids: Vec<i32> = tracks.iter().map(|t| t.id).collect();
-        let mut vibes_map = Vibe::get_by_track_ids(&track_ids, pool).await?;
+        let mut rows: Vec<PgRow> = query_builder.build().fetch_all(pool.get_inner()).await?;
 
-        let full_tracks: Vec<TrackFull> = tracks
-            .into_iter()
-            .map(|track| {
-                let vibes = vibes_map.remove(&track.id).unwrap_or_default();
-                TrackFull { track, vibes }
-            })
-            .collect();
+        let next_cursor = if rows.len() > limit as usize {
+            rows.truncate(limit as usize);
+            match rows.last() {
+                Some(row) => {
+                    let last = Track::from_row(row)?;
+                    Some(params.order.cursor_after(last.id, row)?.encode())
+                }
+                None => None,
+            }
+        } else {
+            None
+        };
+
+        let tracks = rows
+            .iter()
+            .map(Track::from_row)
+            .collect::<sqlx::Result<Vec<Track>>>()?;
 
-        // --- 6. Construct the final Page object ---
         Ok(Page {
-            items: full_tracks,
+            items: Self::with_vibes(tracks, pool).await?,
             total_items,
             total_page: (total_items as f64 / params.page_size as f64).ceil() as i32,
-            page_num: params.page_num,
+            page_num,
             page_size: params.page_size,
+            facets,
+            next_cursor,
         })
     }
 }
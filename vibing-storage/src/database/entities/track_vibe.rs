@@ -0,0 +1,471 @@
+use crate::database::{
+    core::pool::VibingPool,
+    entities::vibe_group::check_exclusive,
+    error::{DatabaseError, Result},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, FromRow, PgConnection};
+
+/// Down votes beyond the up votes that take an unlocked vibe off a track
+pub const VOTES_TO_REMOVE: i32 = 3;
+
+/// Up votes beyond the down votes that add a vibe listeners proposed for a track
+pub const VOTES_TO_ADD: i32 = 3;
+
+/// A vibe of a track and what listeners think of it
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, FromRow)]
+pub struct TrackVibe {
+    pub id: i32,
+    pub name: String,
+    pub group_name: Option<String>,
+    pub up_votes: i32,
+    pub down_votes: i32,
+    /// From 0 to 1, how sure listeners are the vibe fits, 1 for a locked vibe
+    pub confidence: f32,
+    /// Curated, votes never take it off
+    pub locked: bool,
+    /// The rule that added the vibe, none when it was added by hand
+    pub rule: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VibeVote {
+    Up,
+    Down,
+}
+
+impl TrackVibe {
+    pub async fn get_by_track_id(track: i32, pool: &VibingPool) -> Result<Vec<TrackVibe>> {
+        Ok(sqlx::query_as!(
+            TrackVibe,
+            r#"
+            SELECT
+                vb.vibe_id AS id, vb.name, vb.group_name,
+                twv.up_votes, twv.down_votes, twv.confidence AS "confidence!",
+                twv.locked, twv.rule
+            FROM tracks_with_vibes twv
+            JOIN vibes vb ON vb.vibe_id = twv.vibe
+            WHERE twv.track = $1
+            ORDER BY twv.confidence DESC, vb.name
+            "#,
+            track
+        )
+        .fetch_all(pool.get_inner())
+        .await?)
+    }
+
+    /// Records the vote of `voter`, replacing an earlier one of theirs. A vibe the track
+    /// does not carry is added once enough listeners voted for it, unless the track carries
+    /// another vibe of its exclusive group. Returns `None` while the track does not carry
+    /// the vibe, as when down votes took it off.
+    pub async fn vote(
+        track: i32,
+        vibe: i32,
+        voter: &str,
+        vote: VibeVote,
+        pool: &VibingPool,
+    ) -> Result<Option<TrackVibe>> {
+        let mut tx = pool.transaction().await?;
+
+        // votes on a track are counted one after the other
+        sqlx::query!(
+            "
+            SELECT track_id FROM tracks
+            WHERE track_id = $1
+            FOR UPDATE
+            ",
+            track
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(DatabaseError::NotFound)?;
+
+        sqlx::query!(
+            "
+            INSERT INTO vibe_votes (track, vibe, voter, up)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (track, vibe, voter) DO UPDATE
+            SET up = EXCLUDED.up, voted_at = now()
+            ",
+            track,
+            vibe,
+            voter,
+            vote == VibeVote::Up
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let tally = sqlx::query!(
+            r#"
+            SELECT
+                COUNT(*) FILTER (WHERE up)::INT AS "up_votes!",
+                COUNT(*) FILTER (WHERE NOT up)::INT AS "down_votes!"
+            FROM vibe_votes
+            WHERE track = $1 AND vibe = $2
+            "#,
+            track,
+            vibe
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let carried = sqlx::query!(
+            "
+            UPDATE tracks_with_vibes
+            SET up_votes = $3, down_votes = $4
+            WHERE track = $1 AND vibe = $2
+            ",
+            track,
+            vibe,
+            tally.up_votes,
+            tally.down_votes
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            > 0;
+
+        if !carried && tally.up_votes - tally.down_votes >= VOTES_TO_ADD {
+            // a vibe breaking an exclusive group is left off, the vote still counts
+            let mut savepoint = tx.begin().await?;
+
+            sqlx::query!(
+                "
+                INSERT INTO tracks_with_vibes (track, vibe, up_votes, down_votes)
+                VALUES ($1, $2, $3, $4)
+                ",
+                track,
+                vibe,
+                tally.up_votes,
+                tally.down_votes
+            )
+            .execute(&mut *savepoint)
+            .await?;
+
+            match check_vibe_group(track, vibe, &mut savepoint).await {
+                Ok(()) => savepoint.commit().await?,
+                Err(DatabaseError::ExclusiveViolation(_)) => savepoint.rollback().await?,
+                Err(error) => return Err(error),
+            }
+        }
+
+        // the votes stay, the same listeners cannot bring it back on their own
+        let removed = sqlx::query!(
+            "
+            DELETE FROM tracks_with_vibes
+            WHERE track = $1 AND vibe = $2
+                AND NOT locked AND down_votes - up_votes >= $3
+            ",
+            track,
+            vibe,
+            VOTES_TO_REMOVE
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            > 0;
+
+        // and the vibe rules leave it off
+        if removed {
+            sqlx::query!(
+                "
+                INSERT INTO suppressed_vibes (track, vibe)
+                VALUES ($1, $2)
+                ON CONFLICT DO NOTHING
+                ",
+                track,
+                vibe
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let track_vibe = Self::fetch(track, vibe, &mut tx).await?;
+
+        tx.commit().await?;
+
+        Ok(track_vibe)
+    }
+
+    /// Locking adds the vibe when the track does not carry it, and makes it a hand-picked
+    /// one that rules no longer take back
+    pub async fn set_locked(
+        track: i32,
+        vibe: i32,
+        locked: bool,
+        pool: &VibingPool,
+    ) -> Result<TrackVibe> {
+        let mut tx = pool.transaction().await?;
+
+        if locked {
+            sqlx::query!(
+                "
+                INSERT INTO tracks_with_vibes (track, vibe, locked)
+                VALUES ($1, $2, TRUE)
+                ON CONFLICT (track, vibe) DO UPDATE
+                SET locked = TRUE, rule = NULL
+                ",
+                track,
+                vibe
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            check_vibe_group(track, vibe, &mut tx).await?;
+
+            sqlx::query!(
+                "
+                DELETE FROM suppressed_vibes
+                WHERE track = $1 AND vibe = $2
+                ",
+                track,
+                vibe
+            )
+            .execute(&mut *tx)
+            .await?;
+        } else {
+            sqlx::query!(
+                "
+                UPDATE tracks_with_vibes
+                SET locked = FALSE
+                WHERE track = $1 AND vibe = $2
+                ",
+                track,
+                vibe
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let track_vibe = Self::fetch(track, vibe, &mut tx)
+            .await?
+            .ok_or(DatabaseError::NotFound)?;
+
+        tx.commit().await?;
+
+        Ok(track_vibe)
+    }
+
+    async fn fetch(
+        track: i32,
+        vibe: i32,
+        connection: &mut PgConnection,
+    ) -> Result<Option<TrackVibe>> {
+        Ok(sqlx::query_as!(
+            TrackVibe,
+            r#"
+            SELECT
+                vb.vibe_id AS id, vb.name, vb.group_name,
+                twv.up_votes, twv.down_votes, twv.confidence AS "confidence!",
+                twv.locked, twv.rule
+            FROM tracks_with_vibes twv
+            JOIN vibes vb ON vb.vibe_id = twv.vibe
+            WHERE twv.track = $1 AND twv.vibe = $2
+            "#,
+            track,
+            vibe
+        )
+        .fetch_optional(connection)
+        .await?)
+    }
+}
+
+/// Fails when adding `vibe` gave `track` a second vibe of an exclusive group
+async fn check_vibe_group(track: i32, vibe: i32, connection: &mut PgConnection) -> Result<()> {
+    let group_name = sqlx::query_scalar!(
+        r#"
+        SELECT group_name::TEXT AS "group_name!"
+        FROM vibes
+        WHERE vibe_id = $1 AND group_name IS NOT NULL
+        "#,
+        vibe
+    )
+    .fetch_optional(&mut *connection)
+    .await?;
+
+    match group_name {
+        Some(group_name) => check_exclusive(&[group_name], Some(track), connection).await,
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::{Pool, Postgres};
+
+    /// A new track carrying `vibes`
+    async fn track_with_vibes(vibes: &[&str], pool: &VibingPool) -> i32 {
+        let track =
+            sqlx::query_scalar("INSERT INTO tracks (path) VALUES ('a.mp3') RETURNING track_id")
+                .fetch_one(pool.get_inner())
+                .await
+                .unwrap();
+        sqlx::query(
+            "
+            INSERT INTO tracks_with_vibes (track, vibe)
+            SELECT $1, vibe_id FROM vibes WHERE name = ANY($2::TEXT[]::CITEXT[])
+            ",
+        )
+        .bind(track)
+        .bind(vibes)
+        .execute(pool.get_inner())
+        .await
+        .unwrap();
+
+        track
+    }
+
+    async fn vibe_id(name: &str, pool: &VibingPool) -> i32 {
+        sqlx::query_scalar("SELECT vibe_id FROM vibes WHERE name = $1::TEXT::CITEXT")
+            .bind(name)
+            .fetch_one(pool.get_inner())
+            .await
+            .unwrap()
+    }
+
+    async fn votes(
+        track: i32,
+        vibe: i32,
+        votes: &[(&str, VibeVote)],
+        pool: &VibingPool,
+    ) -> Option<TrackVibe> {
+        let mut track_vibe = None;
+        for (voter, vote) in votes {
+            track_vibe = TrackVibe::vote(track, vibe, voter, *vote, pool)
+                .await
+                .unwrap();
+        }
+
+        track_vibe
+    }
+
+    /// Lower bound of the Wilson score interval the `confidence` column holds
+    fn wilson(up_votes: i32, down_votes: i32) -> f64 {
+        let n = f64::from(up_votes + down_votes) + 1.0;
+        let up = f64::from(up_votes) + 1.0;
+        let down = f64::from(down_votes);
+
+        (up / n + 1.9208 / n - 1.96 * (up * down / n.powi(3) + 0.9604 / n.powi(2)).sqrt())
+            / (1.0 + 3.8416 / n)
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn vote_adds_a_vibe_once_enough_listeners_agree(pool: Pool<Postgres>) {
+        let pool = VibingPool::for_test(pool).await;
+        let track = track_with_vibes(&[], &pool).await;
+        let rainy = vibe_id("rainy", &pool).await;
+
+        // a listener voting again replaces their vote
+        let before = votes(
+            track,
+            rainy,
+            &[
+                ("a", VibeVote::Up),
+                ("b", VibeVote::Up),
+                ("a", VibeVote::Up),
+            ],
+            &pool,
+        )
+        .await;
+        assert_eq!(before, None);
+
+        let after = votes(track, rainy, &[("c", VibeVote::Up)], &pool)
+            .await
+            .unwrap();
+        assert_eq!((after.up_votes, after.down_votes), (VOTES_TO_ADD, 0));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn vote_takes_off_a_vibe_listeners_disagree_with(pool: Pool<Postgres>) {
+        let pool = VibingPool::for_test(pool).await;
+        let track = track_with_vibes(&["rainy", "sunny"], &pool).await;
+        let rainy = vibe_id("rainy", &pool).await;
+        let sunny = vibe_id("sunny", &pool).await;
+        TrackVibe::set_locked(track, sunny, true, &pool)
+            .await
+            .unwrap();
+
+        let down = [
+            ("a", VibeVote::Down),
+            ("b", VibeVote::Down),
+            ("c", VibeVote::Down),
+        ];
+        assert_eq!(votes(track, rainy, &down, &pool).await, None);
+
+        let suppressed: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM suppressed_vibes WHERE track = $1 AND vibe = $2)",
+        )
+        .bind(track)
+        .bind(rainy)
+        .fetch_one(pool.get_inner())
+        .await
+        .unwrap();
+        assert!(suppressed);
+
+        // votes never take off a locked vibe
+        let locked = votes(track, sunny, &down, &pool).await.unwrap();
+        assert_eq!((locked.down_votes, locked.confidence), (3, 1.0));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn vote_keeps_the_vote_when_the_group_is_taken(pool: Pool<Postgres>) {
+        let pool = VibingPool::for_test(pool).await;
+        let track = track_with_vibes(&["dawn"], &pool).await;
+        let night = vibe_id("night", &pool).await;
+
+        let up = [
+            ("a", VibeVote::Up),
+            ("b", VibeVote::Up),
+            ("c", VibeVote::Up),
+        ];
+        assert_eq!(votes(track, night, &up, &pool).await, None);
+
+        let recorded: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM vibe_votes WHERE track = $1 AND vibe = $2")
+                .bind(track)
+                .bind(night)
+                .fetch_one(pool.get_inner())
+                .await
+                .unwrap();
+        assert_eq!(recorded, 3);
+
+        let vibes = TrackVibe::get_by_track_id(track, &pool).await.unwrap();
+        let names: Vec<_> = vibes.iter().map(|vibe| vibe.name.as_str()).collect();
+        assert_eq!(names, ["dawn"]);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn confidence_follows_the_votes(pool: Pool<Postgres>) {
+        let pool = VibingPool::for_test(pool).await;
+        let track = track_with_vibes(&["rainy"], &pool).await;
+        let rainy = vibe_id("rainy", &pool).await;
+
+        let cases = [
+            ("a", VibeVote::Up, 1, 0),
+            ("b", VibeVote::Up, 2, 0),
+            ("c", VibeVote::Down, 2, 1),
+            ("c", VibeVote::Up, 3, 0),
+            ("d", VibeVote::Down, 3, 1),
+        ];
+
+        for (voter, vote, up_votes, down_votes) in cases {
+            let track_vibe = TrackVibe::vote(track, rainy, voter, vote, &pool)
+                .await
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(
+                (track_vibe.up_votes, track_vibe.down_votes),
+                (up_votes, down_votes),
+                "{voter} {vote:?}"
+            );
+            assert!(
+                (f64::from(track_vibe.confidence) - wilson(up_votes, down_votes)).abs() < 1e-5,
+                "{voter} {vote:?}: {}",
+                track_vibe.confidence
+            );
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use crate::database::{core::pool::VibingPool, error::Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+pub type UploadID = Uuid;
+
+/// State of a resumable upload that has not been completed yet
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq, FromRow)]
+pub struct Upload {
+    pub id: UploadID,
+    pub file_name: String,
+    pub length: i64,
+    pub offset: i64,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub genre: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct UploadMetadata {
+    pub file_name: String,
+    pub length: i64,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub genre: Option<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Upload {
+    pub async fn create_from(metadata: UploadMetadata, pool: &VibingPool) -> Result<Upload> {
+        Ok(sqlx::query_as!(
+            Upload,
+            r#"
+            INSERT INTO uploads (upload_id, file_name, upload_length, title, author, genre, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING
+                upload_id AS id, file_name, upload_length AS length, upload_offset AS offset,
+                title, author, genre, created_at, expires_at
+            "#,
+            Uuid::new_v4(),
+            metadata.file_name,
+            metadata.length,
+            metadata.title,
+            metadata.author,
+            metadata.genre,
+            metadata.expires_at
+        )
+        .fetch_one(pool.get_inner())
+        .await?)
+    }
+
+    pub async fn get_by_id(id: UploadID, pool: &VibingPool) -> Result<Upload> {
+        Ok(sqlx::query_as!(
+            Upload,
+            r#"
+            SELECT
+                upload_id AS id, file_name, upload_length AS length, upload_offset AS offset,
+                title, author, genre, created_at, expires_at
+            FROM uploads
+            WHERE upload_id = $1
+            "#,
+            id
+        )
+        .fetch_one(pool.get_inner())
+        .await?)
+    }
+
+    pub async fn get_expired(pool: &VibingPool) -> Result<Vec<Upload>> {
+        Ok(sqlx::query_as!(
+            Upload,
+            r#"
+            SELECT
+                upload_id AS id, file_name, upload_length AS length, upload_offset AS offset,
+                title, author, genre, created_at, expires_at
+            FROM uploads
+            WHERE expires_at < now()
+            "#
+        )
+        .fetch_all(pool.get_inner())
+        .await?)
+    }
+
+    /// Moves the offset forward, but only if nobody else has moved it since `self` was read.
+    /// Returns `None` when the stored offset no longer matches.
+    pub async fn advance(
+        self,
+        offset: i64,
+        expires_at: DateTime<Utc>,
+        pool: &VibingPool,
+    ) -> Result<Option<Upload>> {
+        Ok(sqlx::query_as!(
+            Upload,
+            r#"
+            UPDATE uploads
+            SET upload_offset = $1, expires_at = $2
+            WHERE upload_id = $3 AND upload_offset = $4
+            RETURNING
+                upload_id AS id, file_name, upload_length AS length, upload_offset AS offset,
+                title, author, genre, created_at, expires_at
+            "#,
+            offset,
+            expires_at,
+            self.id,
+            self.offset
+        )
+        .fetch_optional(pool.get_inner())
+        .await?)
+    }
+
+    pub async fn remove(self, pool: &VibingPool) -> Result<()> {
+        sqlx::query!(
+            "
+            DELETE FROM uploads
+            WHERE upload_id = $1
+            ",
+            self.id
+        )
+        .execute(pool.get_inner())
+        .await?;
+
+        Ok(())
+    }
+}
@@ -1,4 +1,8 @@
-use crate::database::{core::pool::VibingPool, error::Result};
+use crate::database::{
+    core::pool::VibingPool,
+    entities::vibe_group::{VibeGroup, check_exclusive},
+    error::{DatabaseError, Result},
+};
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
 use std::collections::HashMap;
@@ -10,7 +14,51 @@ pub struct Vibe {
     pub group_name: Option<String>,
 }
 
+/// A vibe and the number of tracks on disk tagged with it
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq, FromRow)]
+pub struct VibeCount {
+    pub id: i32,
+    pub name: String,
+    pub group_name: Option<String>,
+    pub track_count: i64,
+}
+
+/// Renames or regroups the vibe `id`, what is left out stays as it is
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct VibePatch {
+    pub id: i32,
+    pub name: Option<String>,
+    /// `Some(None)` takes the vibe out of its group
+    pub group_name: Option<Option<String>>,
+}
+
 impl Vibe {
+    /// Adds the group too when it is a new one
+    pub async fn create(name: &str, group_name: Option<&str>, pool: &VibingPool) -> Result<Vibe> {
+        let mut tx = pool.transaction().await?;
+
+        if let Some(group_name) = group_name {
+            VibeGroup::ensure(group_name, &mut tx).await?;
+        }
+
+        let vibe = sqlx::query_as!(
+            Vibe,
+            "
+            INSERT INTO vibes (name, group_name)
+            VALUES ($1, $2)
+            RETURNING vibe_id AS id, name, group_name
+            ",
+            name as _,
+            group_name as _
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(vibe)
+    }
+
     pub async fn get_by_id(id: i32, pool: &VibingPool) -> Result<Vibe> {
         Ok(sqlx::query_as!(
             Vibe,
@@ -39,6 +87,26 @@ impl Vibe {
         .await?)
     }
 
+    /// The vibe `name` of the group `group_name`, names ignoring case
+    pub async fn get_in_group(
+        name: &str,
+        group_name: &str,
+        pool: &VibingPool,
+    ) -> Result<Option<Vibe>> {
+        Ok(sqlx::query_as!(
+            Vibe,
+            "
+            SELECT vibe_id AS id, name, group_name
+            FROM vibes
+            WHERE name = $1 AND group_name = $2
+            ",
+            name,
+            group_name
+        )
+        .fetch_optional(pool.get_inner())
+        .await?)
+    }
+
     pub async fn get_all(pool: &VibingPool) -> Result<Vec<Vibe>> {
         Ok(sqlx::query_as!(
             Vibe,
@@ -111,4 +179,220 @@ impl Vibe {
         .vibes_count
         .unwrap_or(-1))
     }
+
+    /// Applies every patch or none. `unique_vibe` is only checked once all of them are
+    /// applied, so two vibes can swap their names. New groups are added, and moving a vibe
+    /// to an exclusive group fails if a track would then carry two of its vibes.
+    pub async fn update_all(patches: &[VibePatch], pool: &VibingPool) -> Result<Vec<Vibe>> {
+        let mut tx = pool.transaction().await?;
+
+        sqlx::query!("SET CONSTRAINTS unique_vibe DEFERRED")
+            .execute(&mut *tx)
+            .await?;
+
+        let mut vibes = Vec::with_capacity(patches.len());
+        for patch in patches {
+            if let Some(Some(group_name)) = &patch.group_name {
+                VibeGroup::ensure(group_name, &mut tx).await?;
+            }
+
+            let vibe = sqlx::query_as!(
+                Vibe,
+                r#"
+                UPDATE vibes
+                SET
+                    name = COALESCE($2, name),
+                    group_name = CASE WHEN $3 THEN $4 ELSE group_name END
+                WHERE vibe_id = $1
+                RETURNING vibe_id AS id, name AS "name: String", group_name AS "group_name: String"
+                "#,
+                patch.id,
+                patch.name.as_deref() as _,
+                patch.group_name.is_some(),
+                patch.group_name.clone().flatten() as _
+            )
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or(DatabaseError::NotFound)?;
+
+            vibes.push(vibe);
+        }
+
+        let group_names: Vec<String> = vibes
+            .iter()
+            .filter_map(|vibe| vibe.group_name.clone())
+            .collect();
+        check_exclusive(&group_names, None, &mut tx).await?;
+
+        tx.commit().await?;
+
+        Ok(vibes)
+    }
+
+    /// Tags the tracks of `source` with `target` instead, then deletes `source`
+    pub async fn merge(source: i32, target: i32, pool: &VibingPool) -> Result<()> {
+        let mut tx = pool.transaction().await?;
+
+        let found = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!"
+            FROM vibes
+            WHERE vibe_id = $1 OR vibe_id = $2
+            "#,
+            source,
+            target
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if found != 2 {
+            return Err(DatabaseError::NotFound);
+        }
+
+        // tracks tagged with both keep a single row
+        sqlx::query!(
+            "
+            INSERT INTO tracks_with_vibes (track, vibe, rule, up_votes, down_votes, locked)
+            SELECT track, $2, rule, up_votes, down_votes, locked
+            FROM tracks_with_vibes
+            WHERE vibe = $1
+            ON CONFLICT DO NOTHING
+            ",
+            source,
+            target
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // the votes for the source count for the target, unless the voter voted on both
+        sqlx::query!(
+            "
+            INSERT INTO vibe_votes (track, vibe, voter, up, voted_at)
+            SELECT track, $2, voter, up, voted_at
+            FROM vibe_votes
+            WHERE vibe = $1
+            ON CONFLICT DO NOTHING
+            ",
+            source,
+            target
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // and the tracks that lost the source by hand stay without the target
+        sqlx::query!(
+            "
+            INSERT INTO suppressed_vibes (track, vibe)
+            SELECT track, $2
+            FROM suppressed_vibes
+            WHERE vibe = $1
+            ON CONFLICT DO NOTHING
+            ",
+            source,
+            target
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // rules adding the source add the target from now on
+        sqlx::query!(
+            "
+            INSERT INTO vibe_rule_vibes (rule, vibe)
+            SELECT rule, $2
+            FROM vibe_rule_vibes
+            WHERE vibe = $1
+            ON CONFLICT DO NOTHING
+            ",
+            source,
+            target
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // its rows in tracks_with_vibes go with it
+        sqlx::query!(
+            "
+            DELETE FROM vibes
+            WHERE vibe_id = $1
+            ",
+            source
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let group_name = sqlx::query_scalar!(
+            r#"
+            SELECT group_name::TEXT
+            FROM vibes
+            WHERE vibe_id = $1
+            "#,
+            target
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        let group_names: Vec<String> = group_name.into_iter().collect();
+        check_exclusive(&group_names, None, &mut tx).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn remove(id: i32, pool: &VibingPool) -> Result<()> {
+        let result = sqlx::query!(
+            "
+            DELETE FROM vibes
+            WHERE vibe_id = $1
+            ",
+            id
+        )
+        .execute(pool.get_inner())
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(DatabaseError::NotFound);
+        }
+
+        Ok(())
+    }
+}
+
+impl VibeCount {
+    /// Every vibe by group, the ungrouped ones last
+    pub async fn get_all(pool: &VibingPool) -> Result<Vec<VibeCount>> {
+        Ok(sqlx::query_as!(
+            VibeCount,
+            r#"
+            SELECT
+                vb.vibe_id AS id, vb.name AS "name: String", vb.group_name AS "group_name: String",
+                COUNT(t.track_id) AS "track_count!"
+            FROM vibes vb
+            LEFT JOIN tracks_with_vibes twv ON twv.vibe = vb.vibe_id
+            LEFT JOIN tracks t ON t.track_id = twv.track AND NOT t.missing
+            GROUP BY vb.vibe_id
+            ORDER BY vb.group_name NULLS LAST, vb.name
+            "#
+        )
+        .fetch_all(pool.get_inner())
+        .await?)
+    }
+
+    pub async fn get_by_id(id: i32, pool: &VibingPool) -> Result<VibeCount> {
+        Ok(sqlx::query_as!(
+            VibeCount,
+            r#"
+            SELECT
+                vb.vibe_id AS id, vb.name AS "name: String", vb.group_name AS "group_name: String",
+                COUNT(t.track_id) AS "track_count!"
+            FROM vibes vb
+            LEFT JOIN tracks_with_vibes twv ON twv.vibe = vb.vibe_id
+            LEFT JOIN tracks t ON t.track_id = twv.track AND NOT t.missing
+            WHERE vb.vibe_id = $1
+            GROUP BY vb.vibe_id
+            "#,
+            id
+        )
+        .fetch_one(pool.get_inner())
+        .await?)
+    }
 }
@@ -0,0 +1,239 @@
+use crate::database::{
+    core::pool::VibingPool,
+    entities::track::TrackID,
+    error::{DatabaseError, Result},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, QueryBuilder};
+
+/// What the `group_name` of vibes refers to
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq, FromRow)]
+pub struct VibeGroup {
+    pub name: String,
+    pub display_name: Option<String>,
+    pub description: Option<String>,
+    /// Groups are listed from the lowest
+    pub sort_order: i32,
+    /// `#rrggbb`
+    pub color: Option<String>,
+    pub icon: Option<String>,
+    /// A track carries at most one vibe of the group
+    pub exclusive: bool,
+}
+
+/// What is left out stays as it is, `Some(None)` clears the field
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct VibeGroupPatch {
+    pub display_name: Option<Option<String>>,
+    pub description: Option<Option<String>>,
+    pub sort_order: Option<i32>,
+    pub color: Option<Option<String>>,
+    pub icon: Option<Option<String>>,
+    pub exclusive: Option<bool>,
+}
+
+impl VibeGroup {
+    pub async fn get_all(pool: &VibingPool) -> Result<Vec<VibeGroup>> {
+        Ok(sqlx::query_as!(
+            VibeGroup,
+            r#"
+            SELECT
+                name AS "name: String", display_name, description, sort_order,
+                color, icon, exclusive
+            FROM vibe_groups
+            ORDER BY sort_order, name
+            "#
+        )
+        .fetch_all(pool.get_inner())
+        .await?)
+    }
+
+    /// Adds the group `name` with no details unless it already exists
+    pub async fn ensure(name: &str, connection: &mut PgConnection) -> Result<()> {
+        sqlx::query!(
+            "
+            INSERT INTO vibe_groups (name)
+            VALUES ($1::TEXT)
+            ON CONFLICT DO NOTHING
+            ",
+            name
+        )
+        .execute(connection)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Making a group exclusive fails while a track carries two of its vibes
+    pub async fn update(name: &str, patch: VibeGroupPatch, pool: &VibingPool) -> Result<VibeGroup> {
+        let mut tx = pool.transaction().await?;
+
+        let mut update_query: QueryBuilder<sqlx::Postgres> =
+            QueryBuilder::new("UPDATE vibe_groups SET ");
+        let mut separated = update_query.separated(", ");
+
+        // keeps the statement valid when nothing is patched
+        separated.push("name = name");
+
+        if let Some(display_name) = patch.display_name {
+            separated
+                .push("display_name = ")
+                .push_bind_unseparated(display_name);
+        }
+
+        if let Some(description) = patch.description {
+            separated
+                .push("description = ")
+                .push_bind_unseparated(description);
+        }
+
+        if let Some(sort_order) = patch.sort_order {
+            separated
+                .push("sort_order = ")
+                .push_bind_unseparated(sort_order);
+        }
+
+        if let Some(color) = patch.color {
+            separated.push("color = ").push_bind_unseparated(color);
+        }
+
+        if let Some(icon) = patch.icon {
+            separated.push("icon = ").push_bind_unseparated(icon);
+        }
+
+        if let Some(exclusive) = patch.exclusive {
+            separated
+                .push("exclusive = ")
+                .push_bind_unseparated(exclusive);
+        }
+
+        update_query
+            .push(" WHERE name = CAST(")
+            .push_bind(name.to_string())
+            .push(" AS CITEXT) RETURNING name::TEXT AS name, display_name, description, sort_order, color, icon, exclusive");
+
+        let group: VibeGroup = update_query
+            .build_query_as()
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or(DatabaseError::NotFound)?;
+
+        if group.exclusive {
+            check_exclusive(std::slice::from_ref(&group.name), None, &mut tx).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(group)
+    }
+}
+
+/// Fails when a track carries two vibes of one of the exclusive groups among `group_names`,
+/// only `track` is looked at when given
+pub(crate) async fn check_exclusive(
+    group_names: &[String],
+    track: Option<TrackID>,
+    connection: &mut PgConnection,
+) -> Result<()> {
+    let violated = sqlx::query_scalar!(
+        r#"
+        SELECT vb.group_name::TEXT AS "group_name!"
+        FROM tracks_with_vibes twv
+        JOIN vibes vb ON vb.vibe_id = twv.vibe
+        JOIN vibe_groups g ON g.name = vb.group_name
+        WHERE g.exclusive AND g.name = ANY($1::TEXT[]::CITEXT[])
+            AND ($2::INT IS NULL OR twv.track = $2)
+        GROUP BY twv.track, vb.group_name
+        HAVING COUNT(*) > 1
+        LIMIT 1
+        "#,
+        group_names,
+        track
+    )
+    .fetch_optional(connection)
+    .await?;
+
+    match violated {
+        Some(group_name) => Err(DatabaseError::ExclusiveViolation(group_name)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::{Pool, Postgres};
+
+    async fn track_with_vibes(path: &str, vibes: &[&str], pool: &VibingPool) -> TrackID {
+        let track = sqlx::query_scalar("INSERT INTO tracks (path) VALUES ($1) RETURNING track_id")
+            .bind(path)
+            .fetch_one(pool.get_inner())
+            .await
+            .unwrap();
+        sqlx::query(
+            "
+            INSERT INTO tracks_with_vibes (track, vibe)
+            SELECT $1, vibe_id FROM vibes WHERE name = ANY($2::TEXT[]::CITEXT[])
+            ",
+        )
+        .bind(track)
+        .bind(vibes)
+        .execute(pool.get_inner())
+        .await
+        .unwrap();
+
+        track
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn check_exclusive_finds_two_vibes_of_a_group(pool: Pool<Postgres>) {
+        let pool = VibingPool::for_test(pool).await;
+        // the group of a vibe can be spelled in another case than the group itself
+        sqlx::query("INSERT INTO vibes (name, group_name) VALUES ('midnight', 'DAYTIME')")
+            .execute(pool.get_inner())
+            .await
+            .unwrap();
+        let clashing = track_with_vibes("clashing.mp3", &["midnight", "dawn"], &pool).await;
+        let single = track_with_vibes("single.mp3", &["dawn", "rainy", "sunny"], &pool).await;
+        let mut connection = pool.connection().await.unwrap();
+
+        let cases = [
+            (vec!["daytime"], None, true),
+            (vec!["DayTime"], None, true),
+            (vec!["weather", "Daytime"], Some(clashing), true),
+            (vec!["DAYTIME"], Some(single), false),
+            // weather is not exclusive
+            (vec!["weather"], Some(single), false),
+            (vec!["seasonal"], None, false),
+            (vec![], None, false),
+        ];
+
+        for (group_names, track, violated) in cases {
+            let group_names: Vec<String> = group_names.into_iter().map(String::from).collect();
+            let result = check_exclusive(&group_names, track, &mut connection).await;
+
+            assert_eq!(
+                matches!(result, Err(DatabaseError::ExclusiveViolation(_))),
+                violated,
+                "{group_names:?} {track:?}: {result:?}"
+            );
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn update_refuses_to_make_a_clashing_group_exclusive(pool: Pool<Postgres>) {
+        let pool = VibingPool::for_test(pool).await;
+        track_with_vibes("stormy.mp3", &["rainy", "stormy"], &pool).await;
+
+        let patch = VibeGroupPatch {
+            exclusive: Some(true),
+            ..Default::default()
+        };
+        let result = VibeGroup::update("Weather", patch, &pool).await;
+
+        assert!(
+            matches!(&result, Err(DatabaseError::ExclusiveViolation(group)) if group == "weather"),
+            "{result:?}"
+        );
+    }
+}
@@ -0,0 +1,748 @@
+use crate::database::{
+    core::pool::VibingPool,
+    error::{DatabaseError, Result},
+    filter::FilterExpr,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, QueryBuilder};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Tags every track matching `filter` with `vibes`.
+///
+/// The tags a rule added are recorded with it in `tracks_with_vibes`, so applying the
+/// rules again takes back the ones whose track stopped matching, and deleting the rule
+/// takes back all of them. A vibe added by hand is never taken back, and one taken off is
+/// never added again.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct VibeRule {
+    pub id: i32,
+    pub name: String,
+    pub filter: FilterExpr,
+    pub vibes: Vec<i32>,
+    /// Disabled rules are left out, the tags they added go on the next apply
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+    /// Tags on tracks the rule added
+    pub tag_count: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct NewVibeRule {
+    pub name: String,
+    pub filter: FilterExpr,
+    pub vibes: Vec<i32>,
+    pub enabled: bool,
+}
+
+/// What is left out stays as it is
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct VibeRulePatch {
+    pub name: Option<String>,
+    pub filter: Option<FilterExpr>,
+    pub vibes: Option<Vec<i32>>,
+    pub enabled: Option<bool>,
+}
+
+/// What applying the rules changes, or would change on a dry run
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct RuleReport {
+    pub dry_run: bool,
+    pub gained: usize,
+    pub lost: usize,
+    pub skipped: usize,
+    /// The tracks that gain, lose or skip a vibe, by id
+    pub tracks: Vec<TrackRuleChanges>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct TrackRuleChanges {
+    pub track_id: i32,
+    pub title: Option<String>,
+    pub gains: Vec<RuleVibe>,
+    /// Tags a rule added that no rule wants anymore
+    pub losses: Vec<RuleVibe>,
+    /// Gains left out because the track already carries a vibe of the same exclusive group
+    pub skipped: Vec<RuleVibe>,
+}
+
+/// A vibe and the rule that adds or added it
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct RuleVibe {
+    pub vibe_id: i32,
+    pub name: String,
+    pub group_name: Option<String>,
+    pub rule_id: i32,
+    pub rule_name: String,
+}
+
+/// A `tracks_with_vibes` row written by a rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RuleTag {
+    track: i32,
+    vibe: i32,
+    rule: i32,
+}
+
+/// A `tracks_with_vibes` row, whoever wrote it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CarriedTag {
+    track: i32,
+    vibe: i32,
+    rule: Option<i32>,
+}
+
+#[derive(Debug, Default)]
+struct RulePlan {
+    gains: Vec<RuleTag>,
+    losses: Vec<RuleTag>,
+    skipped: Vec<RuleTag>,
+    /// Tags another rule now stands for
+    moved: Vec<RuleTag>,
+}
+
+struct VibeInfo {
+    name: String,
+    group_name: Option<String>,
+    exclusive: bool,
+}
+
+impl VibeRule {
+    /// Every rule, in the order they are evaluated
+    pub async fn get_all(pool: &VibingPool) -> Result<Vec<VibeRule>> {
+        let mut connection = pool.get_inner().acquire().await?;
+
+        Self::fetch(None, &mut connection).await
+    }
+
+    pub async fn get_by_id(id: i32, pool: &VibingPool) -> Result<VibeRule> {
+        let mut connection = pool.get_inner().acquire().await?;
+
+        Self::fetch(Some(id), &mut connection)
+            .await?
+            .pop()
+            .ok_or(DatabaseError::NotFound)
+    }
+
+    /// The rule is not applied to the catalog until the next apply, only to new tracks
+    pub async fn create(rule: NewVibeRule, pool: &VibingPool) -> Result<VibeRule> {
+        let mut tx = pool.transaction().await?;
+
+        let id = sqlx::query_scalar!(
+            "
+            INSERT INTO vibe_rules (name, filter, enabled)
+            VALUES ($1, $2::TEXT::JSONB, $3)
+            RETURNING rule_id
+            ",
+            rule.name,
+            encode_filter(&rule.filter)?,
+            rule.enabled
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        set_vibes(id, &rule.vibes, &mut tx).await?;
+
+        let rule = Self::fetch(Some(id), &mut tx)
+            .await?
+            .pop()
+            .ok_or(DatabaseError::NotFound)?;
+
+        tx.commit().await?;
+
+        Ok(rule)
+    }
+
+    /// Like `create`, the catalog follows on the next apply
+    pub async fn update(id: i32, patch: VibeRulePatch, pool: &VibingPool) -> Result<VibeRule> {
+        let mut tx = pool.transaction().await?;
+
+        let mut update_query: QueryBuilder<sqlx::Postgres> =
+            QueryBuilder::new("UPDATE vibe_rules SET ");
+        let mut separated = update_query.separated(", ");
+
+        // keeps the statement valid when nothing is patched
+        separated.push("rule_id = rule_id");
+
+        if let Some(name) = patch.name {
+            separated.push("name = ").push_bind_unseparated(name);
+        }
+
+        if let Some(filter) = &patch.filter {
+            separated
+                .push("filter = CAST(")
+                .push_bind_unseparated(encode_filter(filter)?)
+                .push_unseparated(" AS JSONB)");
+        }
+
+        if let Some(enabled) = patch.enabled {
+            separated.push("enabled = ").push_bind_unseparated(enabled);
+        }
+
+        update_query.push(" WHERE rule_id = ").push_bind(id);
+
+        if update_query
+            .build()
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+            == 0
+        {
+            return Err(DatabaseError::NotFound);
+        }
+
+        if let Some(vibes) = &patch.vibes {
+            sqlx::query!(
+                "
+                DELETE FROM vibe_rule_vibes
+                WHERE rule = $1
+                ",
+                id
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            set_vibes(id, vibes, &mut tx).await?;
+        }
+
+        let rule = Self::fetch(Some(id), &mut tx)
+            .await?
+            .pop()
+            .ok_or(DatabaseError::NotFound)?;
+
+        tx.commit().await?;
+
+        Ok(rule)
+    }
+
+    /// Takes back every tag the rule added
+    pub async fn remove(id: i32, pool: &VibingPool) -> Result<()> {
+        let result = sqlx::query!(
+            "
+            DELETE FROM vibe_rules
+            WHERE rule_id = $1
+            ",
+            id
+        )
+        .execute(pool.get_inner())
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(DatabaseError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates the enabled rules over the tracks on disk, or only over `track_ids`, and
+    /// writes the difference unless `dry_run`.
+    ///
+    /// When several rules add the same vibe to a track the oldest one is recorded. A vibe
+    /// taken off a track by hand or by votes is left off until it is added again.
+    pub async fn apply(
+        track_ids: Option<&[i32]>,
+        dry_run: bool,
+        pool: &VibingPool,
+    ) -> Result<RuleReport> {
+        let mut tx = pool.transaction().await?;
+
+        let report = Self::apply_in(track_ids, dry_run, &mut tx).await?;
+
+        if !dry_run {
+            tx.commit().await?;
+        }
+
+        Ok(report)
+    }
+
+    /// `apply` on a connection the caller already holds, within its transaction
+    pub(crate) async fn apply_in(
+        track_ids: Option<&[i32]>,
+        dry_run: bool,
+        connection: &mut PgConnection,
+    ) -> Result<RuleReport> {
+        let rules = Self::fetch(None, &mut *connection).await?;
+        let plan = plan(&rules, track_ids, &mut *connection).await?;
+
+        if !dry_run {
+            write_plan(&plan, &mut *connection).await?;
+        }
+
+        report(&plan, &rules, dry_run, connection).await
+    }
+
+    /// The rule `id`, or every rule when `None`
+    async fn fetch(id: Option<i32>, connection: &mut PgConnection) -> Result<Vec<VibeRule>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                r.rule_id, r.name, r.filter::TEXT AS "filter!", r.enabled, r.created_at,
+                ARRAY(
+                    SELECT rv.vibe FROM vibe_rule_vibes rv
+                    WHERE rv.rule = r.rule_id
+                    ORDER BY rv.vibe
+                ) AS "vibes!",
+                (SELECT COUNT(*) FROM tracks_with_vibes twv WHERE twv.rule = r.rule_id) AS "tag_count!"
+            FROM vibe_rules r
+            WHERE $1::INT IS NULL OR r.rule_id = $1
+            ORDER BY r.rule_id
+            "#,
+            id
+        )
+        .fetch_all(connection)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(VibeRule {
+                    id: row.rule_id,
+                    name: row.name,
+                    filter: serde_json::from_str(&row.filter).map_err(|_| {
+                        // LOG_RULE_ERROR
+                        DatabaseError::DatabaseError
+                    })?,
+                    vibes: row.vibes,
+                    enabled: row.enabled,
+                    created_at: row.created_at,
+                    tag_count: row.tag_count,
+                })
+            })
+            .collect()
+    }
+}
+
+fn encode_filter(filter: &FilterExpr) -> Result<String> {
+    serde_json::to_string(filter).map_err(|_| DatabaseError::DatabaseError)
+}
+
+/// Fails with a foreign key violation when a vibe does not exist
+async fn set_vibes(rule: i32, vibes: &[i32], connection: &mut PgConnection) -> Result<()> {
+    sqlx::query!(
+        "
+        INSERT INTO vibe_rule_vibes (rule, vibe)
+        SELECT $1, vibe FROM UNNEST($2::INT[]) AS vibe
+        ON CONFLICT DO NOTHING
+        ",
+        rule,
+        vibes
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+async fn plan(
+    rules: &[VibeRule],
+    track_ids: Option<&[i32]>,
+    connection: &mut PgConnection,
+) -> Result<RulePlan> {
+    // --- 1. What the rules want, the oldest rule standing for a tag ---
+    let mut wanted: BTreeMap<(i32, i32), i32> = BTreeMap::new();
+
+    for rule in rules.iter().filter(|rule| rule.enabled) {
+        let mut select_query: QueryBuilder<sqlx::Postgres> =
+            QueryBuilder::new("SELECT t.track_id FROM tracks t WHERE NOT t.missing AND ");
+        rule.filter.push_sql(&mut select_query);
+
+        if let Some(track_ids) = track_ids {
+            select_query
+                .push(" AND t.track_id = ANY(")
+                .push_bind(track_ids)
+                .push(")");
+        }
+
+        let matching: Vec<i32> = select_query
+            .build_query_scalar()
+            .fetch_all(&mut *connection)
+            .await?;
+
+        for track in matching {
+            for &vibe in &rule.vibes {
+                wanted.entry((track, vibe)).or_insert(rule.id);
+            }
+        }
+    }
+
+    // vibes taken off by hand or by votes stay off
+    let suppressed = sqlx::query!(
+        "
+        SELECT track, vibe
+        FROM suppressed_vibes
+        WHERE $1::INT[] IS NULL OR track = ANY($1)
+        ",
+        track_ids as _
+    )
+    .fetch_all(&mut *connection)
+    .await?;
+
+    for row in suppressed {
+        wanted.remove(&(row.track, row.vibe));
+    }
+
+    // --- 2. What the tracks carry ---
+    let carried = sqlx::query_as!(
+        CarriedTag,
+        "
+        SELECT twv.track, twv.vibe, twv.rule
+        FROM tracks_with_vibes twv
+        JOIN tracks t ON t.track_id = twv.track
+        WHERE NOT t.missing AND ($1::INT[] IS NULL OR twv.track = ANY($1))
+        ",
+        track_ids as _
+    )
+    .fetch_all(&mut *connection)
+    .await?;
+
+    let vibes = vibe_infos(connection).await?;
+
+    Ok(diff(wanted, carried, &vibes))
+}
+
+/// Turns the tags the rules want into changes to the tags the tracks carry
+fn diff(
+    wanted: BTreeMap<(i32, i32), i32>,
+    rows: Vec<CarriedTag>,
+    vibes: &HashMap<i32, VibeInfo>,
+) -> RulePlan {
+    let mut plan = RulePlan::default();
+    let mut carried: HashMap<i32, HashSet<i32>> = HashMap::new();
+    let mut present = HashSet::new();
+
+    for row in rows {
+        present.insert((row.track, row.vibe));
+
+        match (row.rule, wanted.get(&(row.track, row.vibe))) {
+            (Some(rule), None) => {
+                plan.losses.push(RuleTag {
+                    track: row.track,
+                    vibe: row.vibe,
+                    rule,
+                });
+                continue;
+            }
+            (Some(rule), Some(&wanted_rule)) if rule != wanted_rule => {
+                plan.moved.push(RuleTag {
+                    track: row.track,
+                    vibe: row.vibe,
+                    rule: wanted_rule,
+                });
+            }
+            _ => {}
+        }
+
+        carried.entry(row.track).or_default().insert(row.vibe);
+    }
+
+    // --- 3. What is missing, as long as exclusive groups allow it ---
+    let mut gains: Vec<RuleTag> = wanted
+        .into_iter()
+        .filter(|key| !present.contains(&key.0))
+        .map(|((track, vibe), rule)| RuleTag { track, vibe, rule })
+        .collect();
+    gains.sort_by_key(|tag| (tag.track, tag.rule, tag.vibe));
+
+    for tag in gains {
+        let held = carried.entry(tag.track).or_default();
+
+        let conflicting = vibes.get(&tag.vibe).is_some_and(|info| {
+            info.exclusive
+                && held.iter().any(|other| {
+                    vibes
+                        .get(other)
+                        .is_some_and(|other| other.group_name == info.group_name)
+                })
+        });
+
+        if conflicting {
+            plan.skipped.push(tag);
+        } else {
+            held.insert(tag.vibe);
+            plan.gains.push(tag);
+        }
+    }
+
+    plan
+}
+
+async fn vibe_infos(connection: &mut PgConnection) -> Result<HashMap<i32, VibeInfo>> {
+    // groups are spelled as the group itself, a vibe may spell it in another case
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            vb.vibe_id AS "vibe_id!", vb.name AS "name!", g.name::TEXT AS group_name,
+            COALESCE(g.exclusive, FALSE) AS "exclusive!"
+        FROM vibes vb
+        LEFT JOIN vibe_groups g ON g.name = vb.group_name
+        "#
+    )
+    .fetch_all(connection)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.vibe_id,
+                VibeInfo {
+                    name: row.name,
+                    group_name: row.group_name,
+                    exclusive: row.exclusive,
+                },
+            )
+        })
+        .collect())
+}
+
+async fn write_plan(plan: &RulePlan, connection: &mut PgConnection) -> Result<()> {
+    let columns = |tags: &[RuleTag]| -> (Vec<i32>, Vec<i32>, Vec<i32>) {
+        (
+            tags.iter().map(|tag| tag.track).collect(),
+            tags.iter().map(|tag| tag.vibe).collect(),
+            tags.iter().map(|tag| tag.rule).collect(),
+        )
+    };
+
+    let (tracks, vibes, _) = columns(&plan.losses);
+    sqlx::query!(
+        "
+        DELETE FROM tracks_with_vibes twv
+        USING UNNEST($1::INT[], $2::INT[]) AS lost(track, vibe)
+        WHERE twv.track = lost.track AND twv.vibe = lost.vibe AND twv.rule IS NOT NULL
+        ",
+        &tracks,
+        &vibes
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    let (tracks, vibes, rules) = columns(&plan.moved);
+    sqlx::query!(
+        "
+        UPDATE tracks_with_vibes twv
+        SET rule = moved.rule
+        FROM UNNEST($1::INT[], $2::INT[], $3::INT[]) AS moved(track, vibe, rule)
+        WHERE twv.track = moved.track AND twv.vibe = moved.vibe AND twv.rule IS NOT NULL
+        ",
+        &tracks,
+        &vibes,
+        &rules
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    let (tracks, vibes, rules) = columns(&plan.gains);
+    sqlx::query!(
+        "
+        INSERT INTO tracks_with_vibes (track, vibe, rule)
+        SELECT * FROM UNNEST($1::INT[], $2::INT[], $3::INT[])
+        ON CONFLICT DO NOTHING
+        ",
+        &tracks,
+        &vibes,
+        &rules
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+async fn report(
+    plan: &RulePlan,
+    rules: &[VibeRule],
+    dry_run: bool,
+    connection: &mut PgConnection,
+) -> Result<RuleReport> {
+    let vibes = vibe_infos(&mut *connection).await?;
+    let rule_names: HashMap<i32, &str> = rules
+        .iter()
+        .map(|rule| (rule.id, rule.name.as_str()))
+        .collect();
+
+    let rule_vibe = |tag: &RuleTag| {
+        let vibe = vibes.get(&tag.vibe);
+        RuleVibe {
+            vibe_id: tag.vibe,
+            name: vibe.map(|vibe| vibe.name.clone()).unwrap_or_default(),
+            group_name: vibe.and_then(|vibe| vibe.group_name.clone()),
+            rule_id: tag.rule,
+            rule_name: rule_names
+                .get(&tag.rule)
+                .map(|name| name.to_string())
+                .unwrap_or_default(),
+        }
+    };
+
+    let mut tracks: BTreeMap<i32, TrackRuleChanges> = BTreeMap::new();
+    for tag in &plan.gains {
+        changes_of(&mut tracks, tag.track)
+            .gains
+            .push(rule_vibe(tag));
+    }
+    for tag in &plan.losses {
+        changes_of(&mut tracks, tag.track)
+            .losses
+            .push(rule_vibe(tag));
+    }
+    for tag in &plan.skipped {
+        changes_of(&mut tracks, tag.track)
+            .skipped
+            .push(rule_vibe(tag));
+    }
+
+    let track_ids: Vec<i32> = tracks.keys().copied().collect();
+    let titles = sqlx::query!(
+        "
+        SELECT track_id, title
+        FROM tracks
+        WHERE track_id = ANY($1)
+        ",
+        &track_ids
+    )
+    .fetch_all(connection)
+    .await?;
+
+    for row in titles {
+        if let Some(changes) = tracks.get_mut(&row.track_id) {
+            changes.title = row.title;
+        }
+    }
+
+    Ok(RuleReport {
+        dry_run,
+        gained: plan.gains.len(),
+        lost: plan.losses.len(),
+        skipped: plan.skipped.len(),
+        tracks: tracks.into_values().collect(),
+    })
+}
+
+fn changes_of(tracks: &mut BTreeMap<i32, TrackRuleChanges>, track: i32) -> &mut TrackRuleChanges {
+    tracks.entry(track).or_insert_with(|| TrackRuleChanges {
+        track_id: track,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAWN: i32 = 1;
+    const NIGHT: i32 = 2;
+    const RAINY: i32 = 3;
+    const SUNNY: i32 = 4;
+
+    fn vibes() -> HashMap<i32, VibeInfo> {
+        let info = |name: &str, group_name: &str, exclusive| VibeInfo {
+            name: name.to_string(),
+            group_name: Some(group_name.to_string()),
+            exclusive,
+        };
+
+        HashMap::from([
+            (DAWN, info("dawn", "daytime", true)),
+            (NIGHT, info("night", "daytime", true)),
+            (RAINY, info("rainy", "weather", false)),
+            (SUNNY, info("sunny", "weather", false)),
+        ])
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn apply_skips_a_group_spelled_in_another_case(pool: sqlx::Pool<sqlx::Postgres>) {
+        let pool = VibingPool::for_test(pool).await;
+        let track: i32 = sqlx::query_scalar(
+            "
+            WITH track AS (INSERT INTO tracks (path) VALUES ('dawn.mp3') RETURNING track_id)
+            INSERT INTO tracks_with_vibes (track, vibe)
+            SELECT track_id, vibe_id FROM track, vibes WHERE name = 'dawn'
+            RETURNING track
+            ",
+        )
+        .fetch_one(pool.get_inner())
+        .await
+        .unwrap();
+        let midnight: i32 = sqlx::query_scalar(
+            "INSERT INTO vibes (name, group_name) VALUES ('midnight', 'DayTime') RETURNING vibe_id",
+        )
+        .fetch_one(pool.get_inner())
+        .await
+        .unwrap();
+
+        VibeRule::create(
+            NewVibeRule {
+                name: String::from("late"),
+                filter: FilterExpr::default(),
+                vibes: vec![midnight],
+                enabled: true,
+            },
+            &pool,
+        )
+        .await
+        .unwrap();
+        let report = VibeRule::apply(Some(&[track]), true, &pool).await.unwrap();
+
+        assert_eq!((report.gained, report.skipped), (0, 1), "{report:?}");
+    }
+
+    fn tag(track: i32, vibe: i32, rule: i32) -> RuleTag {
+        RuleTag { track, vibe, rule }
+    }
+
+    fn carried(track: i32, vibe: i32, rule: Option<i32>) -> CarriedTag {
+        CarriedTag { track, vibe, rule }
+    }
+
+    #[test]
+    fn diff_takes_back_only_rule_tags() {
+        let plan = diff(
+            BTreeMap::new(),
+            vec![carried(1, RAINY, Some(7)), carried(1, SUNNY, None)],
+            &vibes(),
+        );
+
+        assert_eq!(plan.losses, [tag(1, RAINY, 7)]);
+        assert!(plan.gains.is_empty());
+        assert!(plan.skipped.is_empty());
+    }
+
+    #[test]
+    fn diff_adds_what_is_missing_and_moves_what_changed_hands() {
+        let wanted = BTreeMap::from([((1, RAINY), 2), ((1, SUNNY), 3), ((2, RAINY), 2)]);
+        let plan = diff(
+            wanted,
+            vec![carried(1, RAINY, Some(5)), carried(2, RAINY, None)],
+            &vibes(),
+        );
+
+        assert_eq!(plan.gains, [tag(1, SUNNY, 3)]);
+        // a vibe added by hand stays hand-picked
+        assert_eq!(plan.moved, [tag(1, RAINY, 2)]);
+        assert!(plan.losses.is_empty());
+    }
+
+    #[test]
+    fn diff_skips_gains_breaking_an_exclusive_group() {
+        let wanted = BTreeMap::from([
+            ((1, NIGHT), 4),
+            ((1, RAINY), 4),
+            ((2, DAWN), 6),
+            ((2, NIGHT), 5),
+        ]);
+        let plan = diff(wanted, vec![carried(1, DAWN, None)], &vibes());
+
+        assert_eq!(plan.gains, [tag(1, RAINY, 4), tag(2, NIGHT, 5)], "{plan:?}");
+        assert_eq!(plan.skipped, [tag(1, NIGHT, 4), tag(2, DAWN, 6)]);
+    }
+
+    #[test]
+    fn diff_fills_the_place_a_loss_leaves() {
+        let wanted = BTreeMap::from([((1, NIGHT), 2)]);
+        let plan = diff(wanted, vec![carried(1, DAWN, Some(1))], &vibes());
+
+        assert_eq!(plan.losses, [tag(1, DAWN, 1)]);
+        assert_eq!(plan.gains, [tag(1, NIGHT, 2)]);
+        assert!(plan.skipped.is_empty());
+    }
+}
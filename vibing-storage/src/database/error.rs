@@ -2,8 +2,17 @@ use serde::{Deserialize, Serialize};
 
 pub type Result<T> = std::result::Result<T, DatabaseError>;
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
 pub enum DatabaseError {
+    NotFound,
+    /// Carries the name of the violated constraint
+    UniqueViolation(String),
+    /// Carries the name of the violated constraint
+    ForeignKeyViolation(String),
+    /// Carries the name of the exclusive vibe group a track would carry two vibes of
+    ExclusiveViolation(String),
+    /// Carries the name of a locked vibe a change would take off a track
+    LockedVibe(String),
     QueryTimeout,
     DatabaseConnectionError,
     DatabaseError,
@@ -14,10 +23,18 @@ impl From<sqlx::Error> for DatabaseError {
         // LOG_DATABASE_ERROR
 
         match error {
-            sqlx::Error::Database(err) if err.code().as_deref() == Some("57014") => {
-                DatabaseError::QueryTimeout
+            sqlx::Error::RowNotFound => DatabaseError::NotFound,
+            sqlx::Error::Database(err) => {
+                let constraint = err.constraint().unwrap_or_default().to_string();
+
+                match err.code().as_deref() {
+                    Some("23505") => DatabaseError::UniqueViolation(constraint),
+                    Some("23503") => DatabaseError::ForeignKeyViolation(constraint),
+                    Some("57014") => DatabaseError::QueryTimeout,
+                    _ => DatabaseError::DatabaseError,
+                }
             }
-            sqlx::Error::PoolClosed | sqlx::Error::PoolTimedOut => {
+            sqlx::Error::PoolClosed | sqlx::Error::PoolTimedOut | sqlx::Error::Io(_) => {
                 DatabaseError::DatabaseConnectionError
             }
             _ => DatabaseError::DatabaseError,
@@ -0,0 +1,593 @@
+use crate::{
+    app::fetch::normalize_text,
+    database::entities::track::{LOSSLESS_CODECS, TrackFilter, VibeID, VibeMatch},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Encode, Postgres, QueryBuilder, Type};
+use std::collections::HashSet;
+
+pub mod query;
+
+/// Average rating of `tracks t`, unrated tracks count as 0
+pub const AVERAGE_RATING: &str =
+    "(CASE WHEN t.vote_count > 0 THEN t.total_rating::FLOAT / t.vote_count ELSE 0 END)";
+
+/// A condition on tracks, combined with `and`, `or` and `not`.
+///
+/// In JSON every node is an object with a single key, for instance
+/// `{"and": [{"genre": "jazz"}, {"not": {"vibe": "rainy"}}, {"duration": {"max": 240}}]}`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterExpr {
+    /// Every condition holds, an empty list matches every track
+    And(Vec<FilterExpr>),
+    /// At least one condition holds, an empty list matches no track
+    Or(Vec<FilterExpr>),
+    Not(Box<FilterExpr>),
+    /// Title or author contains the text
+    Pattern(String),
+    /// Same author, ignoring case
+    Author(String),
+    /// Same genre, ignoring case
+    Genre(String),
+    /// Author matching a `LIKE` pattern, `%` for any text and `_` for one character,
+    /// ignoring case and accents
+    AuthorLike(String),
+    /// Genre matching a `LIKE` pattern, like `author_like`
+    GenreLike(String),
+    /// The track has this vibe
+    Vibe(VibeRef),
+    /// The track has this vibe and listeners agree with it at least this much, from 0 to 1
+    ConfidentVibe {
+        vibe: VibeRef,
+        min_confidence: f64,
+    },
+    /// The track has a vibe of this group
+    VibeGroup(String),
+    /// In seconds
+    Duration(Range<i32>),
+    /// Average rating, between 0 and 5
+    Rating(Range<f64>),
+    Downloads(Range<i32>),
+    Codec(String),
+    Lossless(bool),
+    /// In kbps
+    Bitrate(Range<i32>),
+    /// In Hz
+    SampleRate(Range<i32>),
+    BitDepth(Range<i16>),
+    /// When the track joined the catalog
+    Added(Range<DateTime<Utc>>),
+}
+
+/// A vibe given by id, or by name in any group
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum VibeRef {
+    Id(VibeID),
+    Name(String),
+}
+
+/// Inclusive bounds, a missing bound is open
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Range<T> {
+    pub min: Option<T>,
+    pub max: Option<T>,
+}
+
+impl Default for FilterExpr {
+    fn default() -> Self {
+        FilterExpr::And(Vec::new())
+    }
+}
+
+impl FilterExpr {
+    /// Appends the expression as one parenthesized condition over `tracks t`, every value
+    /// is bound as a parameter.
+    ///
+    /// A predicate on an unknown value is false rather than NULL, so `not` always matches
+    /// exactly the tracks its operand does not.
+    pub fn push_sql(&self, query_builder: &mut QueryBuilder<Postgres>) {
+        match self {
+            FilterExpr::And(exprs) => push_joined(query_builder, exprs, " AND ", "TRUE"),
+            FilterExpr::Or(exprs) => push_joined(query_builder, exprs, " OR ", "FALSE"),
+            FilterExpr::Not(expr) => {
+                query_builder.push("(NOT ");
+                expr.push_sql(query_builder);
+                query_builder.push(")");
+            }
+            predicate => {
+                query_builder.push("COALESCE(");
+                predicate.push_predicate(query_builder);
+                query_builder.push(", FALSE)");
+            }
+        }
+    }
+
+    /// The patterns a matching track contains, negated ones left out
+    pub fn patterns(&self) -> Vec<&str> {
+        match self {
+            FilterExpr::And(exprs) | FilterExpr::Or(exprs) => {
+                exprs.iter().flat_map(FilterExpr::patterns).collect()
+            }
+            FilterExpr::Pattern(pattern) => vec![pattern.as_str()],
+            _ => Vec::new(),
+        }
+    }
+
+    fn push_predicate(&self, query_builder: &mut QueryBuilder<Postgres>) {
+        match self {
+            FilterExpr::And(_) | FilterExpr::Or(_) | FilterExpr::Not(_) => {
+                self.push_sql(query_builder)
+            }
+            // words anywhere in the search document, substrings or near misses of the
+            // title or author, all of them are served by an index and ignore accents
+            FilterExpr::Pattern(pattern) => {
+                let pattern = normalize_text(pattern);
+                let like = format!("%{}%", escape_like(&pattern));
+                query_builder
+                    .push("(t.search_vector @@ websearch_to_tsquery('simple_unaccent', ")
+                    .push_bind(pattern.clone())
+                    .push(") OR fold_accents(t.title) ILIKE fold_accents(")
+                    .push_bind(like.clone())
+                    .push(") OR fold_accents(t.author) ILIKE fold_accents(")
+                    .push_bind(like)
+                    .push(") OR fold_accents(")
+                    .push_bind(pattern.clone())
+                    .push(") <% fold_accents(t.title) OR fold_accents(")
+                    .push_bind(pattern)
+                    .push(") <% fold_accents(t.author))");
+            }
+            FilterExpr::Author(author) => {
+                query_builder
+                    .push("LOWER(fold_accents(t.author)) = LOWER(fold_accents(")
+                    .push_bind(normalize_text(author))
+                    .push("))");
+            }
+            FilterExpr::Genre(genre) => {
+                query_builder
+                    .push("LOWER(fold_accents(t.genre)) = LOWER(fold_accents(")
+                    .push_bind(normalize_text(genre))
+                    .push("))");
+            }
+            FilterExpr::AuthorLike(pattern) => {
+                query_builder
+                    .push("fold_accents(t.author) ILIKE fold_accents(")
+                    .push_bind(normalize_text(pattern))
+                    .push(")");
+            }
+            FilterExpr::GenreLike(pattern) => {
+                query_builder
+                    .push("fold_accents(t.genre) ILIKE fold_accents(")
+                    .push_bind(normalize_text(pattern))
+                    .push(")");
+            }
+            FilterExpr::Vibe(VibeRef::Id(id)) => {
+                query_builder
+                    .push("EXISTS (SELECT 1 FROM tracks_with_vibes twv WHERE twv.track = t.track_id AND twv.vibe = ")
+                    .push_bind(*id)
+                    .push(")");
+            }
+            // vibe and group names are citext, compare them as such
+            FilterExpr::Vibe(VibeRef::Name(name)) => {
+                query_builder
+                    .push("EXISTS (SELECT 1 FROM tracks_with_vibes twv JOIN vibes vb ON vb.vibe_id = twv.vibe WHERE twv.track = t.track_id AND vb.name = CAST(")
+                    .push_bind(name.clone())
+                    .push(" AS CITEXT))");
+            }
+            FilterExpr::ConfidentVibe {
+                vibe,
+                min_confidence,
+            } => {
+                query_builder.push("EXISTS (SELECT 1 FROM tracks_with_vibes twv JOIN vibes vb ON vb.vibe_id = twv.vibe WHERE twv.track = t.track_id AND ");
+                match vibe {
+                    VibeRef::Id(id) => query_builder.push("vb.vibe_id = ").push_bind(*id),
+                    VibeRef::Name(name) => query_builder
+                        .push("vb.name = CAST(")
+                        .push_bind(name.clone())
+                        .push(" AS CITEXT)"),
+                };
+                query_builder
+                    .push(" AND twv.confidence >= ")
+                    .push_bind(*min_confidence)
+                    .push(")");
+            }
+            FilterExpr::VibeGroup(group) => {
+                query_builder
+                    .push("EXISTS (SELECT 1 FROM tracks_with_vibes twv JOIN vibes vb ON vb.vibe_id = twv.vibe WHERE twv.track = t.track_id AND vb.group_name = CAST(")
+                    .push_bind(group.clone())
+                    .push(" AS CITEXT))");
+            }
+            FilterExpr::Duration(range) => push_range(query_builder, "t.duration", range),
+            FilterExpr::Rating(range) => push_range(query_builder, AVERAGE_RATING, range),
+            FilterExpr::Downloads(range) => push_range(query_builder, "t.download_count", range),
+            FilterExpr::Codec(codec) => {
+                query_builder
+                    .push("LOWER(t.codec) = LOWER(")
+                    .push_bind(codec.clone())
+                    .push(")");
+            }
+            FilterExpr::Lossless(true) => {
+                query_builder
+                    .push("t.codec = ANY(")
+                    .push_bind(&LOSSLESS_CODECS[..])
+                    .push(")");
+            }
+            FilterExpr::Lossless(false) => {
+                query_builder
+                    .push("NOT t.codec = ANY(")
+                    .push_bind(&LOSSLESS_CODECS[..])
+                    .push(")");
+            }
+            FilterExpr::Bitrate(range) => push_range(query_builder, "t.bitrate", range),
+            FilterExpr::SampleRate(range) => push_range(query_builder, "t.sample_rate", range),
+            FilterExpr::BitDepth(range) => push_range(query_builder, "t.bit_depth", range),
+            FilterExpr::Added(range) => push_range(query_builder, "t.added_at", range),
+        }
+    }
+}
+
+/// Appends how well a track of `tracks t` matches `patterns`, higher is better: the rank of
+/// the full-text match plus the trigram similarity of the title or author
+pub fn push_relevance(query_builder: &mut QueryBuilder<Postgres>, patterns: &[&str]) {
+    if patterns.is_empty() {
+        query_builder.push("0");
+        return;
+    }
+
+    query_builder.push("(");
+    for (index, pattern) in patterns.iter().enumerate() {
+        if index > 0 {
+            query_builder.push(" + ");
+        }
+        let pattern = normalize_text(pattern);
+        query_builder
+            .push("ts_rank(t.search_vector, websearch_to_tsquery('simple_unaccent', ")
+            .push_bind(pattern.clone())
+            .push(")) + GREATEST(word_similarity(fold_accents(")
+            .push_bind(pattern.clone())
+            .push("), fold_accents(COALESCE(t.title, ''))), word_similarity(fold_accents(")
+            .push_bind(pattern)
+            .push("), fold_accents(COALESCE(t.author, ''))))");
+    }
+    query_builder.push(")");
+}
+
+/// `text` with the `LIKE` wildcards and the escape character taken literally
+fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+fn push_joined(
+    query_builder: &mut QueryBuilder<Postgres>,
+    exprs: &[FilterExpr],
+    separator: &str,
+    empty: &str,
+) {
+    if exprs.is_empty() {
+        query_builder.push(empty);
+        return;
+    }
+
+    query_builder.push("(");
+    for (index, expr) in exprs.iter().enumerate() {
+        if index > 0 {
+            query_builder.push(separator);
+        }
+        expr.push_sql(query_builder);
+    }
+    query_builder.push(")");
+}
+
+fn push_range<'args, T>(
+    query_builder: &mut QueryBuilder<'args, Postgres>,
+    column: &str,
+    range: &Range<T>,
+) where
+    T: 'args + Encode<'args, Postgres> + Type<Postgres> + Copy,
+{
+    match (range.min, range.max) {
+        (Some(min), Some(max)) => {
+            query_builder
+                .push(format!("({} BETWEEN ", column))
+                .push_bind(min)
+                .push(" AND ")
+                .push_bind(max)
+                .push(")");
+        }
+        (Some(min), None) => {
+            query_builder.push(format!("{} >= ", column)).push_bind(min);
+        }
+        (None, Some(max)) => {
+            query_builder.push(format!("{} <= ", column)).push_bind(max);
+        }
+        // an open range still leaves out the tracks with an unknown value
+        (None, None) => {
+            query_builder.push(format!("{} IS NOT NULL", column));
+        }
+    }
+}
+
+impl From<&TrackFilter> for FilterExpr {
+    /// The conditions of the query string filter, all of them must hold
+    fn from(filter: &TrackFilter) -> Self {
+        let mut exprs = Vec::new();
+
+        if let Some(pattern) = &filter.pattern {
+            exprs.push(FilterExpr::Pattern(pattern.clone()));
+        }
+
+        if let Some(author) = &filter.author {
+            exprs.push(FilterExpr::Author(author.clone()));
+        }
+
+        if let Some(vibes) = &filter.vibes
+            && !vibes.is_empty()
+        {
+            let vibes: Vec<FilterExpr> = vibes
+                .iter()
+                .copied()
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .map(|id| match filter.min_vibe_confidence {
+                    Some(min_confidence) => FilterExpr::ConfidentVibe {
+                        vibe: VibeRef::Id(id),
+                        min_confidence,
+                    },
+                    None => FilterExpr::Vibe(VibeRef::Id(id)),
+                })
+                .collect();
+
+            exprs.push(match filter.vibe_match.unwrap_or_default() {
+                VibeMatch::Any => FilterExpr::Or(vibes),
+                VibeMatch::All => FilterExpr::And(vibes),
+                VibeMatch::None => FilterExpr::Not(Box::new(FilterExpr::Or(vibes))),
+            });
+        }
+
+        if let Some(exclude_vibes) = &filter.exclude_vibes
+            && !exclude_vibes.is_empty()
+        {
+            let vibes = exclude_vibes
+                .iter()
+                .map(|id| FilterExpr::Vibe(VibeRef::Id(*id)))
+                .collect();
+            exprs.push(FilterExpr::Not(Box::new(FilterExpr::Or(vibes))));
+        }
+
+        if let Some(groups) = &filter.vibe_groups
+            && !groups.is_empty()
+        {
+            let groups = groups.iter().cloned().map(FilterExpr::VibeGroup).collect();
+            exprs.push(FilterExpr::Or(groups));
+        }
+
+        if let Some(groups) = &filter.exclude_vibe_groups
+            && !groups.is_empty()
+        {
+            let groups = groups.iter().cloned().map(FilterExpr::VibeGroup).collect();
+            exprs.push(FilterExpr::Not(Box::new(FilterExpr::Or(groups))));
+        }
+
+        if let Some(lossless) = filter.lossless {
+            exprs.push(FilterExpr::Lossless(lossless));
+        }
+
+        if filter.min_bitrate.is_some() || filter.max_bitrate.is_some() {
+            exprs.push(FilterExpr::Bitrate(Range {
+                min: filter.min_bitrate,
+                max: filter.max_bitrate,
+            }));
+        }
+
+        if let Some(min_sample_rate) = filter.min_sample_rate {
+            exprs.push(FilterExpr::SampleRate(Range {
+                min: Some(min_sample_rate),
+                max: None,
+            }));
+        }
+
+        if let Some(min_bit_depth) = filter.min_bit_depth {
+            exprs.push(FilterExpr::BitDepth(Range {
+                min: Some(min_bit_depth),
+                max: None,
+            }));
+        }
+
+        FilterExpr::And(exprs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vibe(id: VibeID) -> FilterExpr {
+        FilterExpr::Vibe(VibeRef::Id(id))
+    }
+
+    #[test]
+    fn from_track_filter_matches_vibes_by_mode() {
+        let cases = [
+            (None, FilterExpr::Or(vec![vibe(3)])),
+            (Some(VibeMatch::Any), FilterExpr::Or(vec![vibe(3)])),
+            (Some(VibeMatch::All), FilterExpr::And(vec![vibe(3)])),
+            (
+                Some(VibeMatch::None),
+                FilterExpr::Not(Box::new(FilterExpr::Or(vec![vibe(3)]))),
+            ),
+        ];
+
+        for (vibe_match, expected) in cases {
+            let filter = TrackFilter {
+                // a vibe listed twice is matched once
+                vibes: Some(vec![3, 3]),
+                vibe_match,
+                ..Default::default()
+            };
+
+            assert_eq!(
+                FilterExpr::from(&filter),
+                FilterExpr::And(vec![expected]),
+                "{vibe_match:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn from_track_filter_combines_every_condition() {
+        let filter = TrackFilter {
+            pattern: Some(String::from("rain")),
+            author: Some(String::from("Nujabes")),
+            vibes: Some(vec![1]),
+            min_vibe_confidence: Some(0.5),
+            exclude_vibes: Some(vec![2, 4]),
+            vibe_groups: Some(vec![String::from("mood")]),
+            exclude_vibe_groups: Some(vec![String::from("weather")]),
+            lossless: Some(true),
+            min_bitrate: Some(192),
+            min_sample_rate: Some(44100),
+            min_bit_depth: Some(24),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            FilterExpr::from(&filter),
+            FilterExpr::And(vec![
+                FilterExpr::Pattern(String::from("rain")),
+                FilterExpr::Author(String::from("Nujabes")),
+                FilterExpr::Or(vec![FilterExpr::ConfidentVibe {
+                    vibe: VibeRef::Id(1),
+                    min_confidence: 0.5,
+                }]),
+                FilterExpr::Not(Box::new(FilterExpr::Or(vec![vibe(2), vibe(4)]))),
+                FilterExpr::Or(vec![FilterExpr::VibeGroup(String::from("mood"))]),
+                FilterExpr::Not(Box::new(FilterExpr::Or(vec![FilterExpr::VibeGroup(
+                    String::from("weather")
+                )]))),
+                FilterExpr::Lossless(true),
+                FilterExpr::Bitrate(Range {
+                    min: Some(192),
+                    max: None,
+                }),
+                FilterExpr::SampleRate(Range {
+                    min: Some(44100),
+                    max: None,
+                }),
+                FilterExpr::BitDepth(Range {
+                    min: Some(24),
+                    max: None,
+                }),
+            ])
+        );
+    }
+
+    #[test]
+    fn from_track_filter_ignores_empty_lists() {
+        let filter = TrackFilter {
+            vibes: Some(Vec::new()),
+            exclude_vibes: Some(Vec::new()),
+            vibe_groups: Some(Vec::new()),
+            exclude_vibe_groups: Some(Vec::new()),
+            ..Default::default()
+        };
+
+        assert_eq!(FilterExpr::from(&filter), FilterExpr::default());
+    }
+
+    fn sql_of(expr: &FilterExpr) -> String {
+        let mut query_builder = QueryBuilder::new("");
+        expr.push_sql(&mut query_builder);
+        query_builder.sql().to_string()
+    }
+
+    #[test]
+    fn push_sql_combines_conditions() {
+        let duration = |min, max| FilterExpr::Duration(Range { min, max });
+        let cases = [
+            (FilterExpr::And(Vec::new()), "TRUE"),
+            (FilterExpr::Or(Vec::new()), "FALSE"),
+            (
+                duration(Some(60), None),
+                "COALESCE(t.duration >= $1, FALSE)",
+            ),
+            (
+                FilterExpr::Not(Box::new(duration(None, Some(240)))),
+                "(NOT COALESCE(t.duration <= $1, FALSE))",
+            ),
+            (
+                FilterExpr::And(vec![
+                    duration(Some(60), Some(240)),
+                    FilterExpr::Or(vec![
+                        FilterExpr::Downloads(Range::default()),
+                        FilterExpr::Lossless(false),
+                    ]),
+                ]),
+                "(COALESCE((t.duration BETWEEN $1 AND $2), FALSE) AND \
+                 (COALESCE(t.download_count IS NOT NULL, FALSE) OR \
+                 COALESCE(NOT t.codec = ANY($3), FALSE)))",
+            ),
+        ];
+
+        for (expr, sql) in cases {
+            assert_eq!(sql_of(&expr), sql, "{expr:?}");
+        }
+    }
+
+    #[test]
+    fn push_sql_binds_every_value() {
+        let exprs = [
+            FilterExpr::Author(String::from("x' OR TRUE --")),
+            FilterExpr::Vibe(VibeRef::Name(String::from("rainy"))),
+            FilterExpr::VibeGroup(String::from("mood")),
+            FilterExpr::ConfidentVibe {
+                vibe: VibeRef::Id(7),
+                min_confidence: 0.5,
+            },
+        ];
+
+        for expr in exprs {
+            let sql = sql_of(&expr);
+            assert!(sql.contains("$1"), "{expr:?}: {sql}");
+            for value in ["x'", "rainy", "mood", "0.5"] {
+                assert!(!sql.contains(value), "{expr:?}: {sql}");
+            }
+        }
+    }
+
+    #[test]
+    fn escape_like_takes_wildcards_literally() {
+        let cases = [
+            ("rain", "rain"),
+            ("100%", "100\\%"),
+            ("snake_case", "snake\\_case"),
+            ("back\\slash", "back\\\\slash"),
+            ("", ""),
+        ];
+
+        for (text, escaped) in cases {
+            assert_eq!(escape_like(text), escaped, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn patterns_leaves_out_negated_ones() {
+        let expr = FilterExpr::And(vec![
+            FilterExpr::Pattern(String::from("rain")),
+            FilterExpr::Or(vec![FilterExpr::Pattern(String::from("city"))]),
+            FilterExpr::Not(Box::new(FilterExpr::Pattern(String::from("live")))),
+        ]);
+
+        assert_eq!(expr.patterns(), ["rain", "city"]);
+    }
+}
@@ -0,0 +1,614 @@
+//! The search box syntax, for instance
+//! `vibe:rainy vibe:night author:"Nujabes" dur:<240 rating:>=4 -genre:rock`.
+//!
+//! Terms separated by spaces must all hold, `OR` between them needs only one,
+//! `-` negates a term and parentheses group terms. A term without a key is searched
+//! in titles and authors. Numeric keys take `<`, `<=`, `>`, `>=`, `=` or a `min..max` range,
+//! `*` in an author or genre matches any text, as in `genre:*lofi*`.
+
+use crate::database::filter::{FilterExpr, Range, VibeRef, escape_like};
+use chrono::{DateTime, Days, NaiveDate, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+const KEYS: &str = "vibe, group, author, genre, codec, lossless, dur, rating, downloads, bitrate, rate, depth, added";
+
+/// Characters `start..end` of the query, counted from 0
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at character {}: {}", self.span.start, self.message)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+type Result<T> = std::result::Result<T, QueryError>;
+
+impl FilterExpr {
+    /// Parses the search box syntax, an empty query matches every track
+    pub fn parse(query: &str) -> Result<FilterExpr> {
+        let mut parser = Parser {
+            chars: query.chars().collect(),
+            pos: 0,
+        };
+
+        parser.skip_whitespace();
+        if parser.at_end() {
+            return Ok(FilterExpr::default());
+        }
+
+        let expr = parser.parse_or()?;
+        if !parser.at_end() {
+            // parse_and only stops early on a closing parenthesis
+            return Err(parser.error("unexpected `)`", parser.pos, parser.pos + 1));
+        }
+
+        Ok(expr)
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn parse_or(&mut self) -> Result<FilterExpr> {
+        let mut exprs = vec![self.parse_and()?];
+
+        while self.at_or() {
+            let start = self.pos;
+            self.pos += 2;
+            self.skip_whitespace();
+            if self.at_end() || self.peek() == Some(')') || self.at_or() {
+                return Err(self.error("expected a term after `OR`", start, start + 2));
+            }
+            exprs.push(self.parse_and()?);
+        }
+
+        Ok(if exprs.len() == 1 {
+            exprs.remove(0)
+        } else {
+            FilterExpr::Or(exprs)
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<FilterExpr> {
+        let mut exprs = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+            if self.at_end() || self.peek() == Some(')') || self.at_or() {
+                break;
+            }
+            exprs.push(self.parse_unary()?);
+        }
+
+        match exprs.len() {
+            0 if self.at_or() => {
+                Err(self.error("expected a term before `OR`", self.pos, self.pos + 2))
+            }
+            0 => Err(self.error("expected a term", self.pos, self.pos + 1)),
+            1 => Ok(exprs.remove(0)),
+            _ => Ok(FilterExpr::And(exprs)),
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<FilterExpr> {
+        let start = self.pos;
+
+        match self.peek() {
+            Some('-') => {
+                self.pos += 1;
+                if self.peek().is_none_or(|c| c.is_whitespace() || c == ')') {
+                    return Err(self.error("expected a term after `-`", start, start + 1));
+                }
+                Ok(FilterExpr::Not(Box::new(self.parse_unary()?)))
+            }
+            Some('(') => {
+                self.pos += 1;
+                self.skip_whitespace();
+                if self.peek() == Some(')') {
+                    return Err(self.error("empty parentheses", start, self.pos + 1));
+                }
+
+                let expr = self.parse_or()?;
+                if self.peek() != Some(')') {
+                    return Err(self.error("unclosed `(`", start, start + 1));
+                }
+                self.pos += 1;
+
+                Ok(expr)
+            }
+            _ => self.parse_term(),
+        }
+    }
+
+    fn parse_term(&mut self) -> Result<FilterExpr> {
+        let start = self.pos;
+
+        if self.peek() == Some('"') {
+            let (text, _) = self.read_value()?;
+            return Ok(FilterExpr::Pattern(text));
+        }
+
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        {
+            self.pos += 1;
+        }
+
+        if self.pos == start || self.peek() != Some(':') {
+            self.pos = start;
+            let (text, _) = self.read_value()?;
+            return Ok(FilterExpr::Pattern(text));
+        }
+
+        let key: String = self.chars[start..self.pos].iter().collect();
+        let key_span = Span {
+            start,
+            end: self.pos,
+        };
+        self.pos += 1;
+
+        let (value, value_span) = self.read_value()?;
+        if value.is_empty() {
+            return Err(self.error(
+                &format!("missing value for `{}`", key),
+                key_span.start,
+                value_span.end,
+            ));
+        }
+
+        self.build_term(&key.to_lowercase(), key_span, value, value_span)
+    }
+
+    fn build_term(
+        &self,
+        key: &str,
+        key_span: Span,
+        value: String,
+        value_span: Span,
+    ) -> Result<FilterExpr> {
+        let text = |value: String| -> Result<String> {
+            if value.starts_with(['<', '>', '=']) {
+                Err(self.error(
+                    &format!("`{}` cannot be compared", key),
+                    value_span.start,
+                    value_span.start + 1,
+                ))
+            } else {
+                Ok(value)
+            }
+        };
+
+        let expr = match key {
+            "vibe" => {
+                let value = text(value)?;
+                FilterExpr::Vibe(match value.parse() {
+                    Ok(id) => VibeRef::Id(id),
+                    Err(_) => VibeRef::Name(value),
+                })
+            }
+            "group" | "vibe_group" => FilterExpr::VibeGroup(text(value)?),
+            "author" => {
+                let value = text(value)?;
+                like_pattern(&value).map_or(FilterExpr::Author(value), FilterExpr::AuthorLike)
+            }
+            "genre" => {
+                let value = text(value)?;
+                like_pattern(&value).map_or(FilterExpr::Genre(value), FilterExpr::GenreLike)
+            }
+            "codec" => FilterExpr::Codec(text(value)?),
+            "lossless" => match value.to_lowercase().as_str() {
+                "yes" | "true" => FilterExpr::Lossless(true),
+                "no" | "false" => FilterExpr::Lossless(false),
+                _ => {
+                    return Err(self.span_error("expected yes or no", value_span));
+                }
+            },
+            "dur" | "duration" => {
+                FilterExpr::Duration(self.parse_range(&value, value_span, parse_duration)?)
+            }
+            "rating" => FilterExpr::Rating(self.parse_range(&value, value_span, |text| {
+                text.parse::<f64>().ok().filter(|rating| rating.is_finite())
+            })?),
+            "downloads" | "dl" => {
+                FilterExpr::Downloads(
+                    self.parse_range(&value, value_span, |text| text.parse().ok())?,
+                )
+            }
+            "bitrate" => {
+                FilterExpr::Bitrate(self.parse_range(&value, value_span, |text| text.parse().ok())?)
+            }
+            "rate" | "sample_rate" => {
+                FilterExpr::SampleRate(
+                    self.parse_range(&value, value_span, |text| text.parse().ok())?,
+                )
+            }
+            "depth" | "bit_depth" => {
+                FilterExpr::BitDepth(
+                    self.parse_range(&value, value_span, |text| text.parse().ok())?,
+                )
+            }
+            "added" => {
+                let days = self.parse_range(&value, value_span, |text| {
+                    NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()
+                })?;
+                FilterExpr::Added(Range {
+                    min: days.min.map(start_of_day),
+                    // up to the last microsecond postgres can tell apart
+                    max: days
+                        .max
+                        .and_then(|day| day.checked_add_days(Days::new(1)))
+                        .map(|day| start_of_day(day) - TimeDelta::microseconds(1)),
+                })
+            }
+            _ => {
+                return Err(self.span_error(
+                    &format!("unknown key `{}`, expected one of {}", key, KEYS),
+                    key_span,
+                ));
+            }
+        };
+
+        Ok(expr)
+    }
+
+    /// Reads `<N`, `<=N`, `>N`, `>=N`, `=N`, `N` or `min..max` where either bound may be left out
+    fn parse_range<T: Bound>(
+        &self,
+        value: &str,
+        span: Span,
+        parse: impl Fn(&str) -> Option<T>,
+    ) -> Result<Range<T>> {
+        let operators = ["<=", ">=", "<", ">", "="];
+        let operator = operators
+            .into_iter()
+            .find(|operator| value.starts_with(operator));
+        let operand_start = span.start + operator.map_or(0, |operator| operator.len());
+        let operand = &value[operator.map_or(0, |operator| operator.len())..];
+
+        let parse_at = |text: &str, start: usize| -> Result<T> {
+            parse(text).ok_or_else(|| {
+                let end = start + text.chars().count();
+                if text.is_empty() {
+                    self.error("missing number", start, start + 1)
+                } else {
+                    self.error(&format!("invalid value `{}`", text), start, end)
+                }
+            })
+        };
+
+        let range = match operator {
+            Some("<=") => Range {
+                min: None,
+                max: Some(parse_at(operand, operand_start)?),
+            },
+            Some(">=") => Range {
+                min: Some(parse_at(operand, operand_start)?),
+                max: None,
+            },
+            Some("<") => Range {
+                min: None,
+                max: Some(parse_at(operand, operand_start)?.before()),
+            },
+            Some(">") => Range {
+                min: Some(parse_at(operand, operand_start)?.after()),
+                max: None,
+            },
+            _ => match operand.split_once("..") {
+                Some((min, max)) => {
+                    let max_start = operand_start + min.chars().count() + 2;
+                    if min.is_empty() && max.is_empty() {
+                        return Err(self.span_error("a range needs at least one bound", span));
+                    }
+
+                    let range = Range {
+                        min: (!min.is_empty())
+                            .then(|| parse_at(min, operand_start))
+                            .transpose()?,
+                        max: (!max.is_empty())
+                            .then(|| parse_at(max, max_start))
+                            .transpose()?,
+                    };
+
+                    if let (Some(min), Some(max)) = (range.min, range.max)
+                        && min > max
+                    {
+                        return Err(self.span_error("the range is empty", span));
+                    }
+
+                    range
+                }
+                None => {
+                    let exact = parse_at(operand, operand_start)?;
+                    Range {
+                        min: Some(exact),
+                        max: Some(exact),
+                    }
+                }
+            },
+        };
+
+        Ok(range)
+    }
+
+    /// Reads a quoted string or everything up to the next space or closing parenthesis
+    fn read_value(&mut self) -> Result<(String, Span)> {
+        let start = self.pos;
+
+        if self.peek() == Some('"') {
+            self.pos += 1;
+            let mut text = String::new();
+
+            loop {
+                match self.peek() {
+                    None => return Err(self.error("unclosed `\"`", start, self.pos)),
+                    Some('"') => {
+                        self.pos += 1;
+                        break;
+                    }
+                    Some('\\') if self.chars.get(self.pos + 1).is_some() => {
+                        text.push(self.chars[self.pos + 1]);
+                        self.pos += 2;
+                    }
+                    Some(c) => {
+                        text.push(c);
+                        self.pos += 1;
+                    }
+                }
+            }
+
+            return Ok((
+                text,
+                Span {
+                    start,
+                    end: self.pos,
+                },
+            ));
+        }
+
+        while self
+            .peek()
+            .is_some_and(|c| !c.is_whitespace() && c != '(' && c != ')')
+        {
+            self.pos += 1;
+        }
+
+        if self.pos == start && self.peek() == Some('(') {
+            return Err(self.error("unexpected `(`", start, start + 1));
+        }
+
+        Ok((
+            self.chars[start..self.pos].iter().collect(),
+            Span {
+                start,
+                end: self.pos,
+            },
+        ))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.chars.len()
+    }
+
+    /// `OR` on its own, `ORANGE` or `OR:` are terms
+    fn at_or(&self) -> bool {
+        self.chars.get(self.pos) == Some(&'O')
+            && self.chars.get(self.pos + 1) == Some(&'R')
+            && self
+                .chars
+                .get(self.pos + 2)
+                .is_none_or(|c| c.is_whitespace() || *c == '(' || *c == ')')
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn error(&self, message: &str, start: usize, end: usize) -> QueryError {
+        QueryError {
+            message: message.to_string(),
+            span: Span {
+                start,
+                end: end.min(self.chars.len()).max(start),
+            },
+        }
+    }
+
+    fn span_error(&self, message: &str, span: Span) -> QueryError {
+        self.error(message, span.start, span.end)
+    }
+}
+
+/// Values a strict comparison can be turned into an inclusive bound for
+trait Bound: Copy + PartialOrd {
+    /// The smallest value greater than this one
+    fn after(self) -> Self;
+    /// The largest value less than this one
+    fn before(self) -> Self;
+}
+
+impl Bound for i32 {
+    fn after(self) -> Self {
+        self.saturating_add(1)
+    }
+
+    fn before(self) -> Self {
+        self.saturating_sub(1)
+    }
+}
+
+impl Bound for i16 {
+    fn after(self) -> Self {
+        self.saturating_add(1)
+    }
+
+    fn before(self) -> Self {
+        self.saturating_sub(1)
+    }
+}
+
+impl Bound for f64 {
+    fn after(self) -> Self {
+        self.next_up()
+    }
+
+    fn before(self) -> Self {
+        self.next_down()
+    }
+}
+
+impl Bound for NaiveDate {
+    fn after(self) -> Self {
+        self.succ_opt().unwrap_or(self)
+    }
+
+    fn before(self) -> Self {
+        self.pred_opt().unwrap_or(self)
+    }
+}
+
+/// Seconds, or minutes and seconds as in `3:45`
+fn parse_duration(text: &str) -> Option<i32> {
+    match text.split_once(':') {
+        Some((minutes, seconds)) if seconds.len() == 2 => {
+            let minutes: i32 = minutes.parse().ok()?;
+            let seconds: i32 = seconds.parse().ok()?;
+            (seconds < 60).then(|| minutes.checked_mul(60)?.checked_add(seconds))?
+        }
+        Some(_) => None,
+        None => text.parse().ok(),
+    }
+}
+
+/// The `LIKE` pattern of `text` when it has a `*` wildcard
+fn like_pattern(text: &str) -> Option<String> {
+    if !text.contains('*') {
+        return None;
+    }
+
+    Some(
+        text.split('*')
+            .map(escape_like)
+            .collect::<Vec<_>>()
+            .join("%"),
+    )
+}
+
+fn start_of_day(day: NaiveDate) -> DateTime<Utc> {
+    day.and_time(Default::default()).and_utc()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    #[test]
+    fn parse_builds_the_expression() {
+        let cases: [(&str, Value); 17] = [
+            ("", json!({"and": []})),
+            ("   ", json!({"and": []})),
+            ("rainy", json!({"pattern": "rainy"})),
+            ("3:45", json!({"pattern": "3:45"})),
+            (r#""lofi \"beats\"""#, json!({"pattern": "lofi \"beats\""})),
+            (
+                "vibe:rainy vibe:7",
+                json!({"and": [{"vibe": "rainy"}, {"vibe": 7}]}),
+            ),
+            ("Vibe:rainy", json!({"vibe": "rainy"})),
+            (
+                r#"author:"Nujabes" -genre:rock"#,
+                json!({"and": [{"author": "Nujabes"}, {"not": {"genre": "rock"}}]}),
+            ),
+            ("genre:*lo_fi*", json!({"genre_like": "%lo\\_fi%"})),
+            ("dur:<240", json!({"duration": {"min": null, "max": 239}})),
+            (
+                "dur:3:00..4:30",
+                json!({"duration": {"min": 180, "max": 270}}),
+            ),
+            (
+                "downloads:>=10",
+                json!({"downloads": {"min": 10, "max": null}}),
+            ),
+            ("bitrate:320", json!({"bitrate": {"min": 320, "max": 320}})),
+            ("rating:4..", json!({"rating": {"min": 4.0, "max": null}})),
+            ("lossless:YES", json!({"lossless": true})),
+            (
+                "(vibe:rainy OR vibe:night) ORANGE",
+                json!({"and": [
+                    {"or": [{"vibe": "rainy"}, {"vibe": "night"}]},
+                    {"pattern": "ORANGE"},
+                ]}),
+            ),
+            (
+                "added:2024-01-01",
+                json!({"added": {
+                    "min": "2024-01-01T00:00:00Z",
+                    "max": "2024-01-01T23:59:59.999999Z",
+                }}),
+            ),
+        ];
+
+        for (query, expected) in cases {
+            let expr =
+                FilterExpr::parse(query).unwrap_or_else(|error| panic!("{query:?}: {error}"));
+            assert_eq!(serde_json::to_value(expr).unwrap(), expected, "{query:?}");
+        }
+    }
+
+    #[test]
+    fn parse_points_at_the_mistake() {
+        let cases = [
+            ("rainy)", "unexpected `)`", 5, 6),
+            ("(rainy", "unclosed `(`", 0, 1),
+            ("()", "empty parentheses", 0, 2),
+            ("OR rainy", "expected a term before `OR`", 0, 2),
+            ("rainy OR", "expected a term after `OR`", 6, 8),
+            // spans count characters, not bytes
+            ("mưa OR", "expected a term after `OR`", 4, 6),
+            ("- rainy", "expected a term after `-`", 0, 1),
+            ("mood:happy", "unknown key `mood`, expected one of", 0, 4),
+            ("author:", "missing value for `author`", 0, 7),
+            ("genre:>rock", "`genre` cannot be compared", 6, 7),
+            ("lossless:maybe", "expected yes or no", 9, 14),
+            ("dur:<abc", "invalid value `abc`", 5, 8),
+            ("dur:3:75", "invalid value `3:75`", 4, 8),
+            ("dur:>", "missing number", 5, 5),
+            ("dur:..", "a range needs at least one bound", 4, 6),
+            ("rating:5..1", "the range is empty", 7, 11),
+            ("\"night drive", "unclosed `\"`", 0, 12),
+        ];
+
+        for (query, message, start, end) in cases {
+            let error = FilterExpr::parse(query).expect_err(query);
+            assert!(
+                error.message.starts_with(message),
+                "{query:?}: {}",
+                error.message
+            );
+            assert_eq!(error.span, Span { start, end }, "{query:?}");
+        }
+    }
+}
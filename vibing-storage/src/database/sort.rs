@@ -0,0 +1,628 @@
+use crate::database::{
+    entities::track::TrackID,
+    filter::{AVERAGE_RATING, FilterExpr, push_relevance},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, QueryBuilder, Row, postgres::PgRow};
+use std::fmt;
+
+/// Every sort key an `order_by` can name, each may be followed by `:asc` or `:desc`
+pub const SORT_OPTIONS: [&str; 10] = [
+    "title",
+    "author",
+    "duration",
+    "added",
+    "rating",
+    "bayesian",
+    "downloads",
+    "play_count",
+    "random(<seed>)",
+    "relevance",
+];
+
+/// Votes of the average track every track is assumed to have on top of its own, so that
+/// a single perfect vote does not outrank a hundred good ones
+const BAYESIAN_PRIOR_VOTES: i32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Title,
+    Author,
+    Duration,
+    Added,
+    Rating,
+    /// Rating pulled towards the average of the library by `BAYESIAN_PRIOR_VOTES`
+    Bayesian,
+    Downloads,
+    PlayCount,
+    /// A shuffle that stays the same for the same seed, so it can be paged through
+    Random(i64),
+    /// How well a track matches the patterns of the filter
+    Relevance,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Direction {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sort {
+    pub key: SortKey,
+    pub direction: Direction,
+}
+
+/// The sorts of a track listing, the track id always breaks the remaining ties so that
+/// every track has a single place and a cursor can resume right after it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackOrder {
+    pub sorts: Vec<Sort>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct SortError {
+    pub message: String,
+}
+
+impl fmt::Display for SortError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}; sort keys are {}, each optionally followed by :asc or :desc",
+            self.message,
+            SORT_OPTIONS.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for SortError {}
+
+/// Where the previous page of a listing ended, opaque to clients
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Cursor {
+    /// The order the cursor was made for, it means nothing in another one
+    #[serde(rename = "o")]
+    order: String,
+    #[serde(rename = "v")]
+    values: Vec<SortValue>,
+    id: TrackID,
+}
+
+/// Sort values are compared with the same type they were read with
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+enum SortValue {
+    #[serde(rename = "i")]
+    Int(i64),
+    #[serde(rename = "f")]
+    Float(f64),
+    #[serde(rename = "s")]
+    Text(String),
+    #[serde(rename = "t")]
+    Time(DateTime<Utc>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValueType {
+    Int,
+    Float,
+    Text,
+    Time,
+}
+
+impl SortKey {
+    fn parse(text: &str) -> Result<SortKey, SortError> {
+        let key = match text.to_lowercase().as_str() {
+            "title" => SortKey::Title,
+            "author" => SortKey::Author,
+            "duration" => SortKey::Duration,
+            "added" => SortKey::Added,
+            "rating" => SortKey::Rating,
+            "bayesian" => SortKey::Bayesian,
+            "downloads" => SortKey::Downloads,
+            "play_count" => SortKey::PlayCount,
+            "relevance" => SortKey::Relevance,
+            "random" => {
+                return Err(SortError {
+                    message: String::from("random needs a seed, as in random(42)"),
+                });
+            }
+            other => {
+                let seed = other
+                    .strip_prefix("random(")
+                    .and_then(|rest| rest.strip_suffix(')'))
+                    .map(|seed| seed.trim().parse::<i64>());
+
+                match seed {
+                    Some(Ok(seed)) => SortKey::Random(seed),
+                    Some(Err(_)) => {
+                        return Err(SortError {
+                            message: format!("the seed of `{}` is not an integer", text),
+                        });
+                    }
+                    None => {
+                        return Err(SortError {
+                            message: format!("unknown sort key `{}`", text),
+                        });
+                    }
+                }
+            }
+        };
+
+        Ok(key)
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            SortKey::Title => String::from("title"),
+            SortKey::Author => String::from("author"),
+            SortKey::Duration => String::from("duration"),
+            SortKey::Added => String::from("added"),
+            SortKey::Rating => String::from("rating"),
+            SortKey::Bayesian => String::from("bayesian"),
+            SortKey::Downloads => String::from("downloads"),
+            SortKey::PlayCount => String::from("play_count"),
+            SortKey::Random(seed) => format!("random({})", seed),
+            SortKey::Relevance => String::from("relevance"),
+        }
+    }
+
+    /// Texts and lengths read from the start, everything measuring how liked or new a
+    /// track is from the top
+    fn default_direction(self) -> Direction {
+        match self {
+            SortKey::Title | SortKey::Author | SortKey::Duration | SortKey::Random(_) => {
+                Direction::Asc
+            }
+            SortKey::Added
+            | SortKey::Rating
+            | SortKey::Bayesian
+            | SortKey::Downloads
+            | SortKey::PlayCount
+            | SortKey::Relevance => Direction::Desc,
+        }
+    }
+
+    /// The key as a value that is never NULL, so that it compares like it sorts
+    fn push_sql(self, query_builder: &mut QueryBuilder<Postgres>, filter: &FilterExpr) {
+        match self {
+            SortKey::Title => {
+                query_builder.push("COALESCE(t.title, '')");
+            }
+            SortKey::Author => {
+                query_builder.push("COALESCE(t.author, '')");
+            }
+            SortKey::Duration => {
+                query_builder.push("COALESCE(t.duration, 0)::BIGINT");
+            }
+            SortKey::Added => {
+                query_builder.push("t.added_at");
+            }
+            SortKey::Rating => {
+                query_builder.push(format!("{}::FLOAT8", AVERAGE_RATING));
+            }
+            SortKey::Bayesian => {
+                query_builder.push(format!(
+                    r#"((t.total_rating + {prior} * (
+                        SELECT COALESCE(SUM(r.total_rating)::FLOAT8 / NULLIF(SUM(r.vote_count), 0), 0)
+                        FROM tracks r WHERE NOT r.missing
+                    )) / (t.vote_count + {prior}))::FLOAT8"#,
+                    prior = BAYESIAN_PRIOR_VOTES
+                ));
+            }
+            SortKey::Downloads => {
+                query_builder.push("t.download_count::BIGINT");
+            }
+            SortKey::PlayCount => {
+                query_builder.push("t.play_count::BIGINT");
+            }
+            SortKey::Random(seed) => {
+                query_builder
+                    .push("md5(t.track_id::TEXT || ':' || ")
+                    .push_bind(seed)
+                    .push("::TEXT)");
+            }
+            SortKey::Relevance => {
+                query_builder.push("(");
+                push_relevance(query_builder, &filter.patterns());
+                query_builder.push(")::FLOAT8");
+            }
+        }
+    }
+
+    fn value_type(self) -> ValueType {
+        match self {
+            SortKey::Title | SortKey::Author | SortKey::Random(_) => ValueType::Text,
+            SortKey::Rating | SortKey::Bayesian | SortKey::Relevance => ValueType::Float,
+            SortKey::Duration | SortKey::Downloads | SortKey::PlayCount => ValueType::Int,
+            SortKey::Added => ValueType::Time,
+        }
+    }
+}
+
+impl TrackOrder {
+    pub fn by(key: SortKey, direction: Direction) -> Self {
+        TrackOrder {
+            sorts: vec![Sort { key, direction }],
+        }
+    }
+
+    /// Reads an `order_by` like `rating:desc,title`, a key without direction takes its
+    /// usual one. Without any, tracks come by relevance when the filter searches for a
+    /// pattern and in the order they were added to the database otherwise.
+    pub fn parse(order_by: Option<&str>, filter: &FilterExpr) -> Result<TrackOrder, SortError> {
+        let order_by = order_by.map(str::trim).unwrap_or_default();
+        if order_by.is_empty() {
+            return Ok(if filter.patterns().is_empty() {
+                TrackOrder::default()
+            } else {
+                TrackOrder::by(SortKey::Relevance, Direction::Desc)
+            });
+        }
+
+        // the one spelling listings understood before the sort keys
+        if order_by == "most download" {
+            return Ok(TrackOrder::by(SortKey::Downloads, Direction::Desc));
+        }
+
+        let mut order = TrackOrder::default();
+        for item in order_by.split(',').map(str::trim) {
+            if item.is_empty() {
+                return Err(SortError {
+                    message: String::from("empty sort key"),
+                });
+            }
+
+            let (key, direction) = match item.rsplit_once(':') {
+                Some((key, direction)) => {
+                    let direction = match direction.trim().to_lowercase().as_str() {
+                        "asc" => Direction::Asc,
+                        "desc" => Direction::Desc,
+                        _ => {
+                            return Err(SortError {
+                                message: format!(
+                                    "unknown direction `{}` of `{}`, use asc or desc",
+                                    direction, key
+                                ),
+                            });
+                        }
+                    };
+                    let key = SortKey::parse(key.trim())?;
+
+                    (key, direction)
+                }
+                None => {
+                    let key = SortKey::parse(item)?;
+
+                    (key, key.default_direction())
+                }
+            };
+
+            let repeated = order
+                .sorts
+                .iter()
+                .any(|sort| std::mem::discriminant(&sort.key) == std::mem::discriminant(&key));
+            if repeated {
+                return Err(SortError {
+                    message: format!("`{}` is sorted on twice", key.name()),
+                });
+            }
+
+            order.sorts.push(Sort { key, direction });
+        }
+
+        Ok(order)
+    }
+
+    /// Identifies the order inside cursors
+    pub fn signature(&self) -> String {
+        self.sorts
+            .iter()
+            .map(|sort| match sort.direction {
+                Direction::Asc => sort.key.name(),
+                Direction::Desc => format!("-{}", sort.key.name()),
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Appends `, <key> AS sort_<n>` for every sort, what a cursor is made from
+    pub fn push_columns(&self, query_builder: &mut QueryBuilder<Postgres>, filter: &FilterExpr) {
+        for (index, sort) in self.sorts.iter().enumerate() {
+            query_builder.push(", ");
+            sort.key.push_sql(query_builder, filter);
+            query_builder.push(format!(" AS sort_{}", index));
+        }
+    }
+
+    pub fn push_order_by(&self, query_builder: &mut QueryBuilder<Postgres>, filter: &FilterExpr) {
+        query_builder.push(" ORDER BY ");
+        for sort in &self.sorts {
+            sort.key.push_sql(query_builder, filter);
+            query_builder.push(match sort.direction {
+                Direction::Asc => " ASC, ",
+                Direction::Desc => " DESC, ",
+            });
+        }
+        query_builder.push("t.track_id ASC");
+    }
+
+    /// Appends the condition of the tracks that come after `cursor`:
+    /// `k1 > v1 OR (k1 = v1 AND k2 > v2) OR ... OR (k1 = v1 AND ... AND id > last_id)`,
+    /// with `<` for the descending keys
+    pub fn push_after(
+        &self,
+        query_builder: &mut QueryBuilder<Postgres>,
+        filter: &FilterExpr,
+        cursor: &Cursor,
+    ) {
+        query_builder.push("(");
+        for depth in 0..=self.sorts.len() {
+            if depth > 0 {
+                query_builder.push(" OR ");
+            }
+            query_builder.push("(");
+
+            for (sort, value) in self.sorts.iter().zip(&cursor.values).take(depth) {
+                sort.key.push_sql(query_builder, filter);
+                query_builder.push(" = ");
+                push_value(query_builder, value);
+                query_builder.push(" AND ");
+            }
+
+            match self.sorts.get(depth) {
+                Some(sort) => {
+                    sort.key.push_sql(query_builder, filter);
+                    query_builder.push(match sort.direction {
+                        Direction::Asc => " > ",
+                        Direction::Desc => " < ",
+                    });
+                    push_value(query_builder, &cursor.values[depth]);
+                }
+                None => {
+                    query_builder.push("t.track_id > ").push_bind(cursor.id);
+                }
+            }
+
+            query_builder.push(")");
+        }
+        query_builder.push(")");
+    }
+
+    /// The cursor after `row`, a row selected with `push_columns`
+    pub fn cursor_after(&self, id: TrackID, row: &PgRow) -> sqlx::Result<Cursor> {
+        let mut values = Vec::with_capacity(self.sorts.len());
+
+        for (index, sort) in self.sorts.iter().enumerate() {
+            let column = format!("sort_{}", index);
+            values.push(match sort.key.value_type() {
+                ValueType::Int => SortValue::Int(row.try_get(column.as_str())?),
+                ValueType::Float => SortValue::Float(row.try_get(column.as_str())?),
+                ValueType::Text => SortValue::Text(row.try_get(column.as_str())?),
+                ValueType::Time => SortValue::Time(row.try_get(column.as_str())?),
+            });
+        }
+
+        Ok(Cursor {
+            order: self.signature(),
+            values,
+            id,
+        })
+    }
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    /// Reads a cursor made for `order`, None when it was tampered with or made for another
+    /// order
+    pub fn decode(text: &str, order: &TrackOrder) -> Option<Cursor> {
+        let bytes = URL_SAFE_NO_PAD.decode(text).ok()?;
+        let cursor: Cursor = serde_json::from_slice(&bytes).ok()?;
+
+        if cursor.order != order.signature() || cursor.values.len() != order.sorts.len() {
+            return None;
+        }
+
+        let types_match = order.sorts.iter().zip(&cursor.values).all(|(sort, value)| {
+            matches!(
+                (sort.key.value_type(), value),
+                (ValueType::Int, SortValue::Int(_))
+                    | (ValueType::Float, SortValue::Float(_))
+                    | (ValueType::Text, SortValue::Text(_))
+                    | (ValueType::Time, SortValue::Time(_))
+            )
+        });
+
+        types_match.then_some(cursor)
+    }
+}
+
+fn push_value(query_builder: &mut QueryBuilder<Postgres>, value: &SortValue) {
+    match value {
+        SortValue::Int(value) => query_builder.push_bind(*value),
+        SortValue::Float(value) => query_builder.push_bind(*value),
+        SortValue::Text(value) => query_builder.push_bind(value.clone()),
+        SortValue::Time(value) => query_builder.push_bind(*value),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rating_then_title() -> TrackOrder {
+        TrackOrder {
+            sorts: vec![
+                Sort {
+                    key: SortKey::Rating,
+                    direction: Direction::Desc,
+                },
+                Sort {
+                    key: SortKey::Title,
+                    direction: Direction::Asc,
+                },
+            ],
+        }
+    }
+
+    fn cursor(order: &TrackOrder, values: Vec<SortValue>) -> Cursor {
+        Cursor {
+            order: order.signature(),
+            values,
+            id: 42,
+        }
+    }
+
+    #[test]
+    fn decode_reads_back_what_encode_wrote() {
+        let added = TrackOrder::by(SortKey::Added, Direction::Desc);
+        let cases = [
+            (TrackOrder::default(), Vec::new()),
+            (
+                added.clone(),
+                vec![SortValue::Time(
+                    "2025-10-21T09:00:00.123456Z".parse().unwrap(),
+                )],
+            ),
+            (
+                rating_then_title(),
+                vec![
+                    SortValue::Float(4.25),
+                    SortValue::Text(String::from("Hẹn gặp lại \"em\"")),
+                ],
+            ),
+            (
+                TrackOrder::by(SortKey::Random(-7), Direction::Asc),
+                vec![SortValue::Text(String::from("0f3a"))],
+            ),
+            (
+                TrackOrder::by(SortKey::PlayCount, Direction::Desc),
+                vec![SortValue::Int(i64::MAX)],
+            ),
+        ];
+
+        for (order, values) in cases {
+            let cursor = cursor(&order, values);
+            let text = cursor.encode();
+
+            assert!(
+                text.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+                "{text}"
+            );
+            assert_eq!(Cursor::decode(&text, &order), Some(cursor), "{order:?}");
+        }
+    }
+
+    #[test]
+    fn decode_refuses_cursors_of_another_order() {
+        let order = rating_then_title();
+        let valid = || vec![SortValue::Float(4.0), SortValue::Text(String::from("a"))];
+
+        let cases = [
+            // made for the same keys in another direction
+            cursor(
+                &TrackOrder {
+                    sorts: vec![
+                        Sort {
+                            key: SortKey::Rating,
+                            direction: Direction::Asc,
+                        },
+                        Sort {
+                            key: SortKey::Title,
+                            direction: Direction::Asc,
+                        },
+                    ],
+                },
+                valid(),
+            ),
+            cursor(&TrackOrder::by(SortKey::Rating, Direction::Desc), valid()),
+            // right order, values tampered with
+            cursor(&order, vec![SortValue::Float(4.0)]),
+            cursor(
+                &order,
+                vec![SortValue::Int(4), SortValue::Text(String::from("a"))],
+            ),
+            cursor(&order, vec![SortValue::Float(4.0), SortValue::Float(1.0)]),
+        ];
+
+        for cursor in cases {
+            assert_eq!(Cursor::decode(&cursor.encode(), &order), None, "{cursor:?}");
+        }
+    }
+
+    #[test]
+    fn decode_refuses_malformed_text() {
+        let order = TrackOrder::default();
+        let cases = [
+            String::new(),
+            String::from("not base64!"),
+            URL_SAFE_NO_PAD.encode("not json"),
+            URL_SAFE_NO_PAD.encode(r#"{"o":"","v":[]}"#),
+            URL_SAFE_NO_PAD.encode(r#"{"o":"","v":[],"id":"42"}"#),
+        ];
+
+        for text in cases {
+            assert_eq!(Cursor::decode(&text, &order), None, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn parse_reads_keys_and_directions() {
+        let search = FilterExpr::Pattern(String::from("rain"));
+        let everything = FilterExpr::default();
+
+        let cases = [
+            (None, &everything, ""),
+            (Some("  "), &everything, ""),
+            (None, &search, "-relevance"),
+            (Some("title"), &everything, "title"),
+            (Some("rating"), &everything, "-rating"),
+            (Some("rating:asc"), &everything, "rating"),
+            (Some("Title:DESC"), &everything, "-title"),
+            (Some("rating:desc, title"), &search, "-rating,title"),
+            (
+                Some("play_count,added:asc"),
+                &everything,
+                "-play_count,added",
+            ),
+            (Some("random( 42 )"), &everything, "random(42)"),
+            (Some("random(-3):desc"), &everything, "-random(-3)"),
+            (Some("most download"), &everything, "-downloads"),
+        ];
+
+        for (order_by, filter, signature) in cases {
+            let order = TrackOrder::parse(order_by, filter)
+                .unwrap_or_else(|error| panic!("{order_by:?}: {error}"));
+            assert_eq!(order.signature(), signature, "{order_by:?}");
+        }
+    }
+
+    #[test]
+    fn parse_rejects_unknown_keys() {
+        let cases = [
+            ("popularity", "unknown sort key `popularity`"),
+            ("title,", "empty sort key"),
+            (
+                "title:up",
+                "unknown direction `up` of `title`, use asc or desc",
+            ),
+            ("random", "random needs a seed, as in random(42)"),
+            ("random(abc)", "the seed of `random(abc)` is not an integer"),
+            ("rating,title,rating:asc", "`rating` is sorted on twice"),
+            ("random(1),random(2)", "`random(2)` is sorted on twice"),
+        ];
+
+        for (order_by, message) in cases {
+            let error =
+                TrackOrder::parse(Some(order_by), &FilterExpr::default()).expect_err(order_by);
+            assert_eq!(error.message, message, "{order_by:?}");
+        }
+    }
+}
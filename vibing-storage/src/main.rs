@@ -1,18 +1,38 @@
 use axum::{
     Router,
-    routing::{get, post},
+    extract::DefaultBodyLimit,
+    http::{HeaderName, HeaderValue},
+    routing::{get, head, patch, post, put},
     serve,
 };
+use clap::Parser;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 use tokio::net::TcpListener;
-use tower_http::cors::{Any, CorsLayer};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 
 use vibing_storage::{
     app::api::{
-        delete::delete_track,
-        get::{get_filtered_page, get_root, handle_download_request, handle_stream_request},
-        patch::update_track,
-        post::handle_upload_request,
+        delete::{delete_track, delete_vibe, delete_vibe_rule, unlock_track_vibe},
+        get::{
+            get_filtered_page, get_root, get_track_vibes, get_tracks_now, get_vibe_groups,
+            get_vibe_rule, get_vibe_rules, get_vibes, get_vibes_now, handle_download_request,
+            handle_stream_request, handle_suggest_request,
+        },
+        paging::PAGE_EXPOSED_HEADERS,
+        patch::{update_track, update_vibe, update_vibe_group, update_vibe_rule, update_vibes},
+        post::{
+            apply_vibe_rules, create_vibe, create_vibe_rule, handle_scan_request,
+            handle_search_request, handle_upload_request, merge_vibe, vote_track_vibe,
+        },
+        put::lock_track_vibe,
+        tus::{
+            TUS_EXPOSED_HEADERS, handle_tus_creation, handle_tus_head, handle_tus_options,
+            handle_tus_patch, handle_tus_termination,
+        },
     },
+    app::services::{tus::remove_expired, watcher::watch, weather::StaticWeather},
+    app::state::AppState,
+    cli::{Cli, Command, run},
     config::Configuration,
     database::core::pool::VibingPool,
 };
@@ -21,36 +41,79 @@ use vibing_storage::{
 async fn main() {
     dotenvy::dotenv().ok();
 
-    #[cfg(feature = "init_db")]
-    let pool = VibingPool::init().await;
-    #[cfg(not(feature = "init_db"))]
+    let cli = Cli::parse();
+    if let Err(error) = Configuration::init(cli.config.file.clone().as_deref(), cli.config.into()) {
+        eprintln!("error: {}", error);
+        std::process::exit(2);
+    }
+
     let pool = VibingPool::get().await;
 
-    #[cfg(feature = "get_resource")]
-    {
-        use vibing_storage::database::entities::track::TrackFull;
-        let metadata_vec =
-            vibing_storage::app::fetch::fetch_resource_from(&Configuration::get().resource_dir)
-                .expect("cannot get resource");
-        for metadata in metadata_vec {
-            TrackFull::create_from(metadata, &pool)
-                .await
-                .expect("cannot store resource");
+    match cli.command.unwrap_or(Command::Serve { migrate: false }) {
+        Command::Serve { migrate } => {
+            if migrate {
+                pool.migrate_up().await;
+            }
+            serve_api(pool).await;
+        }
+        command => {
+            if let Err(error) = run(command, &pool).await {
+                eprintln!("error: {:?}", error);
+                std::process::exit(1);
+            }
         }
     }
+}
+
+async fn serve_api(pool: VibingPool) {
+    let cleanup_pool = pool.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(10 * 60));
+        loop {
+            interval.tick().await;
+            if let Err(error) = remove_expired(&cleanup_pool).await {
+                eprintln!("cannot remove expired uploads: {:?}", error);
+            }
+        }
+    });
 
-    let address = format!("127.0.0.1:{}", Configuration::get().port);
+    let config = Configuration::get();
+
+    // dropping the watcher stops it, keep it around while serving
+    let _watcher = if config.watch {
+        Some(watch(&config.resource_dirs, pool.clone()).expect("cannot watch resource"))
+    } else {
+        None
+    };
+
+    let address = SocketAddr::new(config.bind_address, config.port);
     let listener = TcpListener::bind(address)
         .await
         .expect("cannot bind address");
 
+    // origins were validated when the configuration was loaded
+    let origins = if config.cors_origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            config
+                .cors_origins
+                .iter()
+                .map(|origin| HeaderValue::from_str(origin).unwrap()),
+        )
+    };
+
     let cors = CorsLayer::new()
-        .allow_origin([
-            "http://localhost:3000".parse().unwrap(),
-            "https://glacial.site".parse().unwrap(),
-        ])
+        .allow_origin(origins)
         .allow_methods(Any)
-        .allow_headers(Any);
+        .allow_headers(Any)
+        .expose_headers(
+            TUS_EXPOSED_HEADERS
+                .into_iter()
+                .chain(PAGE_EXPOSED_HEADERS)
+                .map(HeaderName::from_static)
+                .collect::<Vec<_>>(),
+        );
 
     let app = Router::new()
         .route("/", get(get_root))
@@ -60,13 +123,63 @@ async fn main() {
                 .patch(update_track)
                 .delete(delete_track),
         )
+        .route("/tracks/search", post(handle_search_request))
+        .route("/tracks/now", get(get_tracks_now))
         .route("/tracks/download", get(handle_download_request))
-        .route("/tracks/upload", post(handle_upload_request))
+        .route(
+            "/tracks/upload",
+            post(handle_upload_request).layer(DefaultBodyLimit::max(
+                // leave room for the multipart framing and text fields
+                config.max_upload_size as usize + 64 * 1024,
+            )),
+        )
+        .route(
+            "/tracks/tus",
+            post(handle_tus_creation).options(handle_tus_options),
+        )
+        .route(
+            "/tracks/tus/{id}",
+            head(handle_tus_head)
+                .patch(handle_tus_patch)
+                .delete(handle_tus_termination)
+                .options(handle_tus_options),
+        )
         .route("/tracks/stream", get(handle_stream_request))
-        .with_state(pool)
+        .route("/tracks/{id}/vibes", get(get_track_vibes))
+        .route("/tracks/{id}/vibes/{vibe_id}/votes", post(vote_track_vibe))
+        .route(
+            "/tracks/{id}/vibes/{vibe_id}/lock",
+            put(lock_track_vibe).delete(unlock_track_vibe),
+        )
+        .route(
+            "/vibes",
+            get(get_vibes).post(create_vibe).patch(update_vibes),
+        )
+        .route("/vibes/now", get(get_vibes_now))
+        .route("/vibes/{id}", patch(update_vibe).delete(delete_vibe))
+        .route("/vibes/{id}/merge", post(merge_vibe))
+        .route("/vibe-groups", get(get_vibe_groups))
+        .route("/vibe-groups/{name}", patch(update_vibe_group))
+        .route("/vibe-rules", get(get_vibe_rules).post(create_vibe_rule))
+        .route("/vibe-rules/apply", post(apply_vibe_rules))
+        .route(
+            "/vibe-rules/{id}",
+            get(get_vibe_rule)
+                .patch(update_vibe_rule)
+                .delete(delete_vibe_rule),
+        )
+        .route("/suggest", get(handle_suggest_request))
+        .route("/admin/scan", post(handle_scan_request))
+        .with_state(AppState::new(
+            pool,
+            Arc::new(StaticWeather::new(config.weather.clone())),
+        ))
         .layer(cors);
 
-    serve(listener, app.into_make_service())
-        .await
-        .expect("cannot serving app");
+    serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .expect("cannot serving app");
 }